@@ -0,0 +1,60 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use serde_json::Value;
+use toon_tuna::{encode_document, EncodeOptions};
+
+fn large_table(rows: usize) -> Value {
+    let rows: Vec<Value> = (0..rows)
+        .map(|i| {
+            serde_json::json!({
+                "id": i,
+                "name": format!("user{}", i),
+                "email": format!("user{}@example.com", i),
+                "score": i as f64 * 1.5,
+                "active": i % 2 == 0
+            })
+        })
+        .collect();
+    serde_json::json!({ "users": rows })
+}
+
+fn bench_tabular(c: &mut Criterion) {
+    let data = large_table(100_000);
+    let serial = EncodeOptions::default();
+    let parallel = EncodeOptions::builder().parallel(true).build();
+
+    let mut group = c.benchmark_group("tabular_100k");
+    group.sample_size(10);
+    group.bench_function("serial", |b| b.iter(|| encode_document(&data, &serial).unwrap()));
+    group.bench_function("parallel", |b| b.iter(|| encode_document(&data, &parallel).unwrap()));
+    group.finish();
+}
+
+/// Nested objects of string values, a few of which need quotes or escapes
+fn string_heavy(count: usize) -> Value {
+    let items: Vec<Value> = (0..count)
+        .map(|i| {
+            serde_json::json!({
+                "title": format!("Item{}", i),
+                "slug": format!("item_{}_slug", i),
+                "summary": format!("A longer description for item {}, with a comma", i),
+                "path": format!("docs/section{}/page.html", i % 50),
+                "note": if i % 10 == 0 { format!("line one\nline \"{}\"", i) } else { format!("note{}", i) },
+                "tags": ["alpha", "beta", format!("tag{}", i % 7)]
+            })
+        })
+        .collect();
+    serde_json::json!({ "items": items })
+}
+
+fn bench_strings(c: &mut Criterion) {
+    let data = string_heavy(20_000);
+    let options = EncodeOptions::default();
+
+    let mut group = c.benchmark_group("strings_20k");
+    group.sample_size(10);
+    group.bench_function("encode", |b| b.iter(|| encode_document(&data, &options).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, bench_tabular, bench_strings);
+criterion_main!(benches);
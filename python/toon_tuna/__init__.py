@@ -6,7 +6,8 @@ and minified JSON based on token efficiency for LLM contexts.
 """
 
 import json
-from typing import Any, Dict, Optional
+from os import PathLike
+from typing import Any, Callable, Dict, Iterable, List, Optional, Tuple, Union
 
 try:
     import tiktoken
@@ -17,29 +18,91 @@ except ImportError:
 
 from toon_tuna._toon_tuna import (
     encode as _encode,
+    encode_toon_rows as _encode_rows,
+    encode_toon_to_file as _encode_to_file,
     decode_toon as _decode,
+    decode_toon_file as _decode_file,
+    merge_toon as _merge,
+    roundtrip_ok as _roundtrip_ok,
+    encode_toon_preview as _encode_preview,
+    toon_savings as _savings,
+    normalize_toon as _normalize,
+    retarget_toon_delimiter as _retarget_delimiter,
+    validate_finite as _validate_finite,
+    encode_flattened as _encode_flattened,
+    decode_unflatten as _decode_unflatten,
+    encode_toon_with_comments as _encode_with_comments,
+    decode_toon_with_comments as _decode_with_comments,
+    decode_toon_with_spans as _decode_with_spans,
+    decode_toon_with_meta as _decode_with_meta,
+    decode_toon_flat as _decode_flat,
+    decode_toon_schema as _decode_schema,
+    toon_content_hash as _content_hash,
     EncodeOptions,
     DecodeOptions,
+    BytesEncoding,
+    LengthMarkerStyle,
+    NumberOverflow,
+    StreamingDecoder,
+    ToonError,
+    ToonEncodeError,
+    ToonDecodeError,
+    ToonDelimiterError,
 )
 
 __version__ = "0.1.0"
 __all__ = [
     "encode",
+    "encode_rows",
+    "encode_to_file",
     "decode",
+    "decode_file",
+    "merge",
+    "roundtrip_ok",
+    "encode_preview",
+    "savings",
+    "normalize",
+    "retarget_delimiter",
+    "validate_finite",
+    "encode_flattened",
+    "decode_unflatten",
+    "encode_with_comments",
+    "decode_with_comments",
+    "decode_with_spans",
+    "decode_with_meta",
+    "decode_flat",
+    "decode_schema",
+    "content_hash",
     "encode_optimal",
     "estimate_savings",
     "EncodeOptions",
     "DecodeOptions",
+    "BytesEncoding",
+    "LengthMarkerStyle",
+    "NumberOverflow",
+    "StreamingDecoder",
+    "ToonError",
+    "ToonEncodeError",
+    "ToonDecodeError",
+    "ToonDelimiterError",
 ]
 
 
-def encode(data: Any, options: Optional[EncodeOptions] = None) -> str:
+def encode(
+    data: Any,
+    options: Optional[EncodeOptions] = None,
+    default: Optional[Callable[[Any], Any]] = None,
+    **overrides: Any,
+) -> str:
     """
     Encode Python data to TOON format.
 
     Args:
         data: Python data structure (dict, list, primitives)
         options: Optional encoding options (delimiter, indent, etc.)
+        default: Called with any object that can't be encoded; returns a substitute,
+            like json.dumps's `default`
+        **overrides: EncodeOptions fields (e.g. indent=4) overriding `options`
 
     Returns:
         TOON-formatted string
@@ -50,8 +113,63 @@ def encode(data: Any, options: Optional[EncodeOptions] = None) -> str:
 
         >>> encode({"users": [{"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}]})
         'users:\\n  [2,]{id,name}:\\n    1,Alice\\n    2,Bob'
+
+        >>> from decimal import Decimal
+        >>> encode({"price": Decimal("1.23")}, default=lambda d: f"${d}")
+        'price: $1.23'
+    """
+    return _encode(data, options, default, **overrides)
+
+
+def encode_rows(
+    rows: Iterable[Dict[str, Any]],
+    fields: List[str],
+    options: Optional[EncodeOptions] = None,
+) -> str:
     """
-    return _encode(data, options)
+    Encode an iterable of dicts as a tabular TOON array, one row at a time.
+
+    Rows are pulled lazily, so generators never need to be materialized.
+
+    Args:
+        rows: Iterable of dicts holding primitive values
+        fields: Column names, in output order
+        options: Optional encoding options
+
+    Returns:
+        TOON-formatted string
+
+    Examples:
+        >>> encode_rows(({"id": i} for i in range(2)), ["id"])
+        '[2,]{id}:\\n  0\\n  1'
+    """
+    return _encode_rows(rows, fields, options)
+
+
+def encode_to_file(
+    rows: Iterable[Dict[str, Any]],
+    path: Union[str, PathLike],
+    fields: List[str],
+    options: Optional[EncodeOptions] = None,
+) -> None:
+    """
+    Encode an iterable of dicts as a tabular TOON file, one row at a time.
+
+    Only the row being written is held in memory. The header states the row
+    count when `rows` has a length (a list, say); otherwise it is written as `[]`.
+
+    Args:
+        rows: Iterable of dicts holding primitive values
+        path: File to create or overwrite
+        fields: Column names, in output order
+        options: Optional encoding options
+
+    Examples:
+        >>> encode_to_file(({"id": i} for i in range(2)), "ids.toon", ["id"])
+        >>> open("ids.toon").read()
+        '[]{id}:\\n  0\\n  1'
+    """
+    _encode_to_file(rows, path, fields, options)
 
 
 def decode(toon_str: str, options: Optional[DecodeOptions] = None) -> Any:
@@ -72,6 +190,314 @@ def decode(toon_str: str, options: Optional[DecodeOptions] = None) -> Any:
     return _decode(toon_str, options)
 
 
+def decode_file(
+    path: Union[str, PathLike], options: Optional[DecodeOptions] = None
+) -> Any:
+    """
+    Decode a TOON file to Python data, reading it line by line.
+
+    Args:
+        path: Path to the TOON file
+        options: Optional decoding options (strict mode, etc.)
+
+    Returns:
+        Python data structure
+
+    Raises:
+        OSError: If the file can't be read
+        ToonDecodeError: If the content isn't valid TOON
+    """
+    return _decode_file(path, options)
+
+
+def merge(
+    a_str: str,
+    b_str: str,
+    options: Optional[EncodeOptions] = None,
+    concat_arrays: bool = True,
+    decode_options: Optional[DecodeOptions] = None,
+) -> str:
+    """
+    Deep-merge two TOON documents and re-encode the result.
+
+    Args:
+        a_str: Base TOON document
+        b_str: TOON document merged on top (wins on scalar conflicts)
+        options: Optional encoding options for the merged output
+        concat_arrays: Concatenate arrays at matching keys instead of replacing them
+        decode_options: Optional decoding options for both documents
+
+    Returns:
+        TOON-formatted string
+
+    Examples:
+        >>> merge("id: 1\\nname: Alice", "name: Bob")
+        'id: 1\\nname: Bob'
+    """
+    return _merge(a_str, b_str, options, concat_arrays, decode_options)
+
+
+def encode_flattened(
+    data: Any, options: Optional[EncodeOptions] = None, separator: str = "."
+) -> str:
+    """
+    Flatten nested objects into dotted keys, then encode to TOON format.
+
+    Arrays of nested records become tabular once flattened. A key that
+    already contains the separator couldn't be told apart from a joined one,
+    so it is an error.
+
+    Args:
+        data: Python data structure (dict, list, primitives)
+        options: Optional encoding options
+        separator: String joining nested key names
+
+    Returns:
+        TOON-formatted string
+
+    Raises:
+        ToonEncodeError: If a key contains the separator
+
+    Examples:
+        >>> encode_flattened({"user": {"id": 1, "name": "Alice"}})
+        'user.id: 1\\nuser.name: Alice'
+    """
+    return _encode_flattened(data, options, separator)
+
+
+def decode_unflatten(
+    toon_str: str, options: Optional[DecodeOptions] = None, separator: str = "."
+) -> Any:
+    """
+    Decode TOON format and rebuild nested objects from dotted keys.
+
+    Args:
+        toon_str: TOON-formatted string
+        options: Optional decoding options
+        separator: String joining nested key names
+
+    Returns:
+        Python data structure
+
+    Examples:
+        >>> decode_unflatten("user.id: 1\\nuser.name: Alice")
+        {'user': {'id': 1, 'name': 'Alice'}}
+    """
+    return _decode_unflatten(toon_str, options, separator)
+
+
+def encode_with_comments(
+    data: Any, comments: Dict[str, str], options: Optional[EncodeOptions] = None
+) -> str:
+    """
+    Encode Python data to TOON format, writing `#` comments above keys.
+
+    Args:
+        data: Python data structure (dict, list, primitives)
+        comments: Comment text keyed by key path ("a.b", "items[0].id")
+        options: Optional encoding options
+
+    Returns:
+        TOON-formatted string
+
+    Examples:
+        >>> encode_with_comments({"id": 1}, {"id": "primary key"})
+        '# primary key\\nid: 1'
+    """
+    return _encode_with_comments(data, comments, options)
+
+
+def encode_preview(
+    data: Any,
+    max_items: int = 10,
+    max_chars: int = 80,
+    options: Optional[EncodeOptions] = None,
+) -> str:
+    """
+    Encode a truncated TOON preview for logs. The output decodes, with each
+    `…(+N more)` marker as a string, but not back to the original data.
+
+    Args:
+        data: Python data structure (dict, list, primitives)
+        max_items: Items kept from each array
+        max_chars: Characters kept from each string
+        options: Optional encoding options
+
+    Returns:
+        TOON-formatted preview, with `…(+N more)` where items or characters were dropped
+
+    Examples:
+        >>> encode_preview({"ids": list(range(100))}, max_items=3)
+        'ids:\\n  [4,]: 0,1,2,"…(+97 more)"'
+    """
+    return _encode_preview(data, max_items, max_chars, options)
+
+
+def decode_with_comments(
+    toon_str: str, options: Optional[DecodeOptions] = None
+) -> Tuple[Any, Dict[str, str]]:
+    """
+    Decode TOON format, keeping `#` comments attached to the key below them.
+
+    Args:
+        toon_str: TOON-formatted string
+        options: Optional decoding options
+
+    Returns:
+        Tuple of (Python data structure, comments keyed by key path)
+
+    Examples:
+        >>> decode_with_comments("# primary key\\nid: 1")
+        ({'id': 1}, {'id': 'primary key'})
+    """
+    return _decode_with_comments(toon_str, options)
+
+
+def decode_with_spans(
+    toon_str: str, options: Optional[DecodeOptions] = None
+) -> Tuple[Any, Dict[str, Tuple[int, int]]]:
+    """
+    Decode TOON format, also recording which source lines each value came from.
+
+    Args:
+        toon_str: TOON-formatted string
+        options: Optional decoding options
+
+    Returns:
+        Tuple of (Python data structure, spans) where spans maps the JSON
+        Pointer of every key, list item and row to its (first, last) line
+
+    Examples:
+        >>> data, spans = decode_with_spans("user:\\n  id: 1")
+        >>> spans["/user/id"]
+        (2, 2)
+    """
+    return _decode_with_spans(toon_str, options)
+
+
+def decode_with_meta(
+    toon_str: str, options: Optional[DecodeOptions] = None
+) -> Tuple[Any, Dict[str, Any]]:
+    """
+    Decode TOON format, also describing the source so it can be re-encoded alike.
+
+    Args:
+        toon_str: TOON-formatted string
+        options: Optional decoding options
+
+    Returns:
+        Tuple of (Python data structure, meta) where meta has `indent` (the
+        source's indentation width, or None if nothing is indented) and `comments`
+        (read under `allow_comments`)
+
+    Examples:
+        >>> data, meta = decode_with_meta("user:\\n    id: 1")
+        >>> meta["indent"]
+        4
+        >>> encode(data, indent=meta["indent"])
+        'user:\\n    id: 1'
+    """
+    return _decode_with_meta(toon_str, options)
+
+
+def decode_flat(toon_str: str) -> Dict[str, Any]:
+    """
+    Decode a TOON document into a flat dict of JSON Pointers to leaf values.
+
+    Useful for diffing: each scalar is keyed by its RFC 6901 pointer, in
+    document order. Empty lists and dicts are kept as values, and a scalar
+    root is keyed by "".
+
+    Args:
+        toon_str: TOON-formatted string
+
+    Returns:
+        Dict mapping pointers like "/users/0/name" to leaf values
+
+    Raises:
+        ToonDecodeError: If the document can't be decoded
+
+    Examples:
+        >>> decode_flat("users[2,]{id,name}:\\n  1,Alice\\n  2,Bob")
+        {'/users/0/id': 1, '/users/0/name': 'Alice', '/users/1/id': 2, '/users/1/name': 'Bob'}
+    """
+    return _decode_flat(toon_str)
+
+
+def decode_schema(toon_str: str) -> Any:
+    """
+    Describe a TOON document's keys without decoding its values.
+
+    Only key and header lines are read; array items and rows are skipped,
+    so this is much faster than decode for inspecting large documents.
+
+    Args:
+        toon_str: TOON-formatted string
+
+    Returns:
+        Nested dicts mirroring the document's objects, where tabular arrays
+        map to their column names, other arrays to "array" and anything
+        else to "scalar"
+
+    Examples:
+        >>> decode_schema("name: Demo\\nusers[2,]{id,name}:\\n  1,Alice\\n  2,Bob")
+        {'name': 'scalar', 'users': ['id', 'name']}
+    """
+    return _decode_schema(toon_str)
+
+
+def content_hash(toon_str: str) -> int:
+    """
+    Hash the value a TOON document decodes to.
+
+    Documents that decode to equal values hash equally however they are
+    formatted: indentation, delimiters, tabular vs expanded arrays and
+    object key order don't count. Array order does. The hash is stable
+    across runs and platforms, so it can be stored for change detection.
+
+    Args:
+        toon_str: TOON-formatted string
+
+    Returns:
+        Unsigned 64-bit hash
+
+    Raises:
+        ToonDecodeError: If the document can't be decoded
+
+    Examples:
+        >>> content_hash("a: 1\\nb: 2") == content_hash("b: 2\\na: 1")
+        True
+    """
+    return _content_hash(toon_str)
+
+
+def roundtrip_ok(
+    data: Any,
+    encode_options: Optional[EncodeOptions] = None,
+    decode_options: Optional[DecodeOptions] = None,
+    with_path: bool = False,
+) -> Union[bool, Tuple[bool, Optional[str]]]:
+    """
+    Check that data survives a TOON encode/decode cycle unchanged.
+
+    Args:
+        data: Python data structure (dict, list, primitives)
+        encode_options: Optional encoding options
+        decode_options: Optional decoding options
+        with_path: Also return the first differing path ("" for the root)
+
+    Returns:
+        True if the decoded data equals the input, or (ok, path) with `with_path`
+
+    Examples:
+        >>> roundtrip_ok({"id": 1, "tags": ["a", "b"]})
+        True
+        >>> roundtrip_ok({"ratio": 1.0}, with_path=True)
+        (False, 'ratio')
+    """
+    return _roundtrip_ok(data, encode_options, decode_options, with_path)
+
+
 def encode_optimal(
     data: Any,
     target: str = "llm",
@@ -165,6 +591,88 @@ def encode_optimal(
     }
 
 
+def validate_finite(data: Any) -> Optional[str]:
+    """
+    Find the first NaN or infinite float, without encoding the data.
+
+    Args:
+        data: Python data structure (dict, list, tuple, primitives)
+
+    Returns:
+        Path of the first non-finite float ("" for the root), or None if all are finite
+
+    Examples:
+        >>> validate_finite({"rows": [{"x": 1.0}, {"x": float("nan")}]})
+        'rows[1].x'
+        >>> validate_finite([1.0, 2.0]) is None
+        True
+    """
+    return _validate_finite(data)
+
+
+def savings(data: Any, options: Optional[EncodeOptions] = None) -> Dict[str, Any]:
+    """
+    Compare the byte size of data as compact JSON and as TOON.
+
+    Unlike estimate_savings, this needs no tokenizer.
+
+    Args:
+        data: Python data structure
+        options: Optional TOON encoding options
+
+    Returns:
+        Dictionary with json_bytes, toon_bytes and ratio (toon_bytes / json_bytes)
+
+    Examples:
+        >>> savings({"users": [{"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}]})
+        {'json_bytes': 57, 'toon_bytes': 45, 'ratio': 0.7894736842105263}
+    """
+    return _savings(data, options)
+
+
+def normalize(toon_str: str, options: Optional[DecodeOptions] = None) -> str:
+    """
+    Re-emit TOON in canonical form.
+
+    Delimiters, indentation, quoting and key order are normalized, so
+    documents holding equal data produce identical text.
+
+    Args:
+        toon_str: TOON formatted string
+        options: Optional decoding options for reading the input
+
+    Returns:
+        Canonical TOON string
+
+    Examples:
+        >>> normalize('b: "x"\\na:\\n    [2|]: 1|2')
+        'a:\\n  [2,]: 1,2\\nb: x'
+    """
+    return _normalize(toon_str, options)
+
+
+def retarget_delimiter(toon_str: str, from_delimiter: str, to_delimiter: str) -> str:
+    """
+    Switch a TOON document's tables and inline arrays to another delimiter.
+
+    Lines are rewritten in place without decoding, so formatting, comments and
+    key order are kept. Cells holding the new delimiter get quoted.
+
+    Args:
+        toon_str: TOON formatted string
+        from_delimiter: Delimiter the arrays to rewrite use: ",", "|" or "\t"
+        to_delimiter: Delimiter to write instead
+
+    Returns:
+        The document with those arrays re-delimited
+
+    Examples:
+        >>> retarget_delimiter("[2,]{id,tag}:\\n  1,a|b\\n  2,c", ",", "|")
+        '[2|]{id|tag}:\\n  1|"a|b"\\n  2|c'
+    """
+    return _retarget_delimiter(toon_str, from_delimiter, to_delimiter)
+
+
 def estimate_savings(
     data: Any, tokenizer: str = "cl100k_base", options: Optional[EncodeOptions] = None
 ) -> Dict[str, Any]:
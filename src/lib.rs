@@ -1,8 +1,26 @@
+use base64::Engine;
 use pyo3::prelude::*;
-use pythonize::{depythonize, pythonize};
+use pyo3::IntoPyObjectExt;
+use pyo3::pyclass_init::PyClassInitializer;
+use pyo3::PyClass;
+use pyo3::types::{PyBytes, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
+use pythonize::depythonize;
 use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use thiserror::Error;
 
+/// Comment text keyed by the path of the key it sits above (`a.b[0].c`)
+pub type Comments = std::collections::HashMap<String, String>;
+
+/// First and last source line of each decoded value, keyed by JSON Pointer (`/a/b/0`)
+pub type Spans = std::collections::HashMap<String, (usize, usize)>;
+
 #[derive(Error, Debug)]
 pub enum ToonError {
     #[error("Encoding error: {0}")]
@@ -13,15 +31,119 @@ pub enum ToonError {
     InvalidDelimiter(String),
     #[error("Python conversion error: {0}")]
     PythonError(String),
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+/// Python exception classes, one per error kind. The base `ToonError`
+/// subclasses `ValueError` so existing `except ValueError` handlers still work.
+pub mod exceptions {
+    use pyo3::create_exception;
+    use pyo3::exceptions::PyValueError;
+
+    create_exception!(_toon_tuna, ToonError, PyValueError, "Base class for toon-tuna errors");
+    create_exception!(_toon_tuna, ToonEncodeError, ToonError, "Data could not be encoded to TOON");
+    create_exception!(_toon_tuna, ToonDecodeError, ToonError, "Input is not valid TOON");
+    create_exception!(_toon_tuna, ToonDelimiterError, ToonError, "Unsupported delimiter");
 }
 
 impl From<ToonError> for PyErr {
     fn from(err: ToonError) -> PyErr {
-        pyo3::exceptions::PyValueError::new_err(err.to_string())
+        let message = err.to_string();
+        match err {
+            // Keep OSError subclasses such as FileNotFoundError
+            ToonError::IoError(err) => err.into(),
+            ToonError::EncodingError(_) => exceptions::ToonEncodeError::new_err(message),
+            ToonError::DecodingError(_) => exceptions::ToonDecodeError::new_err(message),
+            ToonError::InvalidDelimiter(_) => exceptions::ToonDelimiterError::new_err(message),
+            ToonError::PythonError(_) => exceptions::ToonError::new_err(message),
+        }
+    }
+}
+
+/// How Python `bytes` values are written when encoding
+#[pyclass(eq, eq_int)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BytesEncoding {
+    /// `b64:` followed by standard padded base64
+    Base64,
+    /// `hex:` followed by lowercase hex digits
+    Hex,
+}
+
+/// How array headers state their length
+#[pyclass(eq, eq_int)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LengthMarkerStyle {
+    /// `[3,]` inside the header
+    Inline,
+    /// `[]` in the header and a trailing `  # 3 items` comment
+    TrailingComment,
+    /// `[]` with no length at all
+    Omitted,
+}
+
+/// What the decoder does with an integer token too large for `i64` and `u64`
+#[pyclass(eq, eq_int)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NumberOverflow {
+    /// Keep the token as a string, digits intact
+    AsString,
+    /// Parse it as the nearest float, losing precision
+    AsFloat,
+    /// Fail with a `DecodingError`
+    Error,
+}
+
+const BASE64_PREFIX: &str = "b64:";
+const HEX_PREFIX: &str = "hex:";
+
+/// Render bytes as a prefixed `b64:` / `hex:` token
+fn encode_bytes(bytes: &[u8], encoding: BytesEncoding) -> String {
+    match encoding {
+        BytesEncoding::Base64 => {
+            format!("{}{}", BASE64_PREFIX, base64::engine::general_purpose::STANDARD.encode(bytes))
+        }
+        BytesEncoding::Hex => {
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{}{}", HEX_PREFIX, hex)
+        }
+    }
+}
+
+/// Parse a prefixed `b64:` / `hex:` token back into bytes; other strings yield `None`
+fn decode_bytes(s: &str) -> Result<Option<Vec<u8>>, ToonError> {
+    if let Some(encoded) = s.strip_prefix(BASE64_PREFIX) {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| ToonError::DecodingError(format!("Invalid base64 bytes: {}", e)))?;
+        return Ok(Some(bytes));
+    }
+
+    if let Some(encoded) = s.strip_prefix(HEX_PREFIX) {
+        if encoded.len() % 2 != 0 || !encoded.is_ascii() {
+            return Err(ToonError::DecodingError(format!("Invalid hex bytes: {}", encoded)));
+        }
+        let bytes = (0..encoded.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&encoded[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| ToonError::DecodingError(format!("Invalid hex bytes: {}", e)))?;
+        return Ok(Some(bytes));
     }
+
+    Ok(None)
 }
 
 /// Configuration options for TOON encoding
+///
+/// With `strict` set, encoding fails with `ToonError::EncodingError` instead of
+/// silently coercing data that cannot round-trip losslessly:
+/// - non-finite floats (`NaN`, `inf`), which are otherwise emitted as `null`
+/// - integers outside the 64-bit range (below `i64::MIN` or above `u64::MAX`),
+///   which are otherwise emitted as floats
+/// - tabular header keys that collide once rendered (e.g. `a` and `a `)
+/// - nested values with `indent` 0, which would read back as siblings
 #[pyclass]
 #[derive(Clone)]
 pub struct EncodeOptions {
@@ -33,18 +155,231 @@ pub struct EncodeOptions {
     pub use_length_markers: bool,
     #[pyo3(get, set)]
     pub strict: bool,
+    /// Quote every string value, even when `quote_if_needed` would not
+    #[pyo3(get, set)]
+    pub always_quote_strings: bool,
+    /// Annotate tabular header fields with their inferred type (`{id:int,name:str}`)
+    #[pyo3(get, set)]
+    pub typed_header: bool,
+    /// Prefix top-level scalars with `= ` so they decode back to scalars
+    #[pyo3(get, set)]
+    pub scalar_sentinel: bool,
+    /// Encode tabular rows concurrently (requires the `rayon` feature)
+    #[pyo3(get, set)]
+    pub parallel: bool,
+    /// Omit object fields whose value is null
+    #[pyo3(get, set)]
+    pub skip_nulls: bool,
+    /// Omit object fields whose value is an empty string or array
+    #[pyo3(get, set)]
+    pub skip_empty: bool,
+    /// Keep arrays of objects-or-null tabular, writing null rows as empty cells;
+    /// decode with `DecodeOptions.tabular_allow_null_rows` to read them as null
+    #[pyo3(get, set)]
+    pub tabular_allow_null_rows: bool,
+    /// Abort with an error as soon as the output grows past this many bytes
+    #[pyo3(get, set)]
+    pub max_bytes: Option<usize>,
+    /// Treat keys differing only in case as duplicates (error if strict, else keep the last)
+    #[pyo3(get, set)]
+    pub case_insensitive_keys: bool,
+    /// Write Python `bytes` as `b64:` / `hex:` tokens instead of failing
+    #[pyo3(get, set)]
+    pub bytes_encoding: Option<BytesEncoding>,
+    /// Insert a blank line before each top-level object or array section
+    #[pyo3(get, set)]
+    pub section_spacing: bool,
+    /// Literal written for boolean true
+    #[pyo3(get, set)]
+    pub true_repr: String,
+    /// Literal written for boolean false
+    #[pyo3(get, set)]
+    pub false_repr: String,
+    /// Wrap the root value under this key unless it is already wrapped
+    #[pyo3(get, set)]
+    pub root_key: Option<String>,
+    /// Comments to write above the keys at these paths (see [`encode_with_comments`])
+    pub comments: Comments,
+    /// Truncate arrays and strings for a log preview (see [`encode_preview`])
+    pub preview: Option<PreviewConfig>,
+    /// Write numbers with their stored text (e.g. decoded `1.50`) instead of normalizing
+    #[pyo3(get, set)]
+    pub preserve_number_text: bool,
+    /// Encode `enum.Enum` members as their `.value`
+    #[pyo3(get, set)]
+    pub enums_by_value: bool,
+    /// Write rectangular numeric arrays of arrays as matrix rows under a `[rows][cols]` header
+    #[pyo3(get, set)]
+    pub matrix_mode: bool,
+    /// Literal written for null
+    #[pyo3(get, set)]
+    pub null_repr: String,
+    /// Write nested object fields as dotted `a.b.c: value` keys instead of indented blocks
+    #[pyo3(get, set)]
+    pub flatten_paths: bool,
+    /// Write object keys in sorted order
+    #[pyo3(get, set)]
+    pub sort_keys: bool,
+    /// Keys written first, in this order, ahead of the remaining keys
+    #[pyo3(get, set)]
+    pub key_order: Vec<String>,
+    /// Escape the delimiter inside unquoted values (`a\,b`) instead of quoting them
+    #[pyo3(get, set)]
+    pub escape_delimiter_inline: bool,
+    /// Write floats in their shortest exact form, using exponents when shorter (`1e-10`)
+    #[pyo3(get, set)]
+    pub compact_numbers: bool,
+    /// How array lengths are written; `use_length_markers=False` always omits them
+    #[pyo3(get, set)]
+    pub length_marker_style: LengthMarkerStyle,
+    /// Write arrays of objects with more columns than this in expanded form
+    #[pyo3(get, set)]
+    pub max_tabular_columns: Option<usize>,
+    /// Encode a list of (key, value) 2-tuples as an object, keeping pair order
+    #[pyo3(get, set)]
+    pub pairs_as_object: bool,
+    /// Write each expanded array item's index after its list marker, as `- [0] ...`
+    #[pyo3(get, set)]
+    pub index_expanded_items: bool,
+    /// Write flat tuples tagged by the Python walk as `(N,): a,b` so they decode back to tuples
+    #[pyo3(get, set)]
+    pub tuple_marker: bool,
+    /// Keys to write under a new name, e.g. `user_id` -> `id`; other keys pass through
+    #[pyo3(get, set)]
+    pub rename_keys: std::collections::HashMap<String, String>,
+    /// Marker starting each expanded array item
+    #[pyo3(get, set)]
+    pub list_marker: String,
+    /// Keys whose values are written as `***`, matched case-insensitively; for logs, as this loses data
+    #[pyo3(get, set)]
+    pub redact_keys: Vec<String>,
+    /// Column to sort tabular rows by, for diff-stable output; every row must have it
+    #[pyo3(get, set)]
+    pub sort_rows_by: Option<String>,
+    /// Write primitive arrays longer than this as an `{i,value}` table, one item per row
+    #[pyo3(get, set)]
+    pub index_large_arrays: Option<usize>,
+    /// Start the document with a `#toon v1` version line
+    #[pyo3(get, set)]
+    pub emit_version: bool,
+    /// Encode objects with `keys()` and `__getitem__` as objects
+    #[pyo3(get, set)]
+    pub duck_typed_mappings: bool,
+    /// Close each table with a `# count=N sum(col)=...` comment line
+    #[pyo3(get, set)]
+    pub tabular_footer: bool,
+    /// Turn off every lossy transform so `decode(encode(x)) == x`; overrides the options that would lose data
+    #[pyo3(get, set)]
+    pub lossless: bool,
+    /// Keys whose values are written as a quoted compact JSON string instead of as TOON
+    #[pyo3(get, set)]
+    pub json_passthrough_keys: Vec<String>,
+    /// Longest string value, in characters, that strict mode accepts
+    #[pyo3(get, set)]
+    pub max_string_len: Option<usize>,
+    /// Quote every key that isn't a plain identifier
+    #[pyo3(get, set)]
+    pub defensive_keys: bool,
 }
 
 #[pymethods]
 impl EncodeOptions {
     #[new]
-    #[pyo3(signature = (delimiter=",".to_string(), indent=2, use_length_markers=true, strict=true))]
-    fn new(delimiter: String, indent: usize, use_length_markers: bool, strict: bool) -> Self {
+    #[pyo3(signature = (delimiter=",".to_string(), indent=2, use_length_markers=true, strict=true, always_quote_strings=false, typed_header=false, scalar_sentinel=false, parallel=false, skip_nulls=false, skip_empty=false, tabular_allow_null_rows=false, max_bytes=None, case_insensitive_keys=false, bytes_encoding=None, section_spacing=false, true_repr="true".to_string(), false_repr="false".to_string(), root_key=None, preserve_number_text=false, enums_by_value=false, matrix_mode=false, null_repr="null".to_string(), flatten_paths=false, sort_keys=false, key_order=Vec::new(), escape_delimiter_inline=false, compact_numbers=false, length_marker_style=LengthMarkerStyle::Inline, max_tabular_columns=None, pairs_as_object=false, index_expanded_items=false, tuple_marker=false, rename_keys=std::collections::HashMap::new(), list_marker="- ".to_string(), redact_keys=Vec::new(), sort_rows_by=None, index_large_arrays=None, emit_version=false, duck_typed_mappings=false, tabular_footer=false, lossless=false, json_passthrough_keys=Vec::new(), max_string_len=None, defensive_keys=false))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        delimiter: String,
+        indent: usize,
+        use_length_markers: bool,
+        strict: bool,
+        always_quote_strings: bool,
+        typed_header: bool,
+        scalar_sentinel: bool,
+        parallel: bool,
+        skip_nulls: bool,
+        skip_empty: bool,
+        tabular_allow_null_rows: bool,
+        max_bytes: Option<usize>,
+        case_insensitive_keys: bool,
+        bytes_encoding: Option<BytesEncoding>,
+        section_spacing: bool,
+        true_repr: String,
+        false_repr: String,
+        root_key: Option<String>,
+        preserve_number_text: bool,
+        enums_by_value: bool,
+        matrix_mode: bool,
+        null_repr: String,
+        flatten_paths: bool,
+        sort_keys: bool,
+        key_order: Vec<String>,
+        escape_delimiter_inline: bool,
+        compact_numbers: bool,
+        length_marker_style: LengthMarkerStyle,
+        max_tabular_columns: Option<usize>,
+        pairs_as_object: bool,
+        index_expanded_items: bool,
+        tuple_marker: bool,
+        rename_keys: std::collections::HashMap<String, String>,
+        list_marker: String,
+        redact_keys: Vec<String>,
+        sort_rows_by: Option<String>,
+        index_large_arrays: Option<usize>,
+        emit_version: bool,
+        duck_typed_mappings: bool,
+        tabular_footer: bool,
+        lossless: bool,
+        json_passthrough_keys: Vec<String>,
+        max_string_len: Option<usize>,
+        defensive_keys: bool,
+    ) -> Self {
         EncodeOptions {
             delimiter,
             indent,
             use_length_markers,
             strict,
+            always_quote_strings,
+            typed_header,
+            scalar_sentinel,
+            parallel,
+            skip_nulls,
+            skip_empty,
+            tabular_allow_null_rows,
+            max_bytes,
+            case_insensitive_keys,
+            bytes_encoding,
+            section_spacing,
+            true_repr,
+            false_repr,
+            root_key,
+            comments: Comments::new(),
+            preview: None,
+            preserve_number_text,
+            enums_by_value,
+            matrix_mode,
+            null_repr,
+            flatten_paths,
+            sort_keys,
+            key_order,
+            escape_delimiter_inline,
+            compact_numbers,
+            length_marker_style,
+            max_tabular_columns,
+            pairs_as_object,
+            index_expanded_items,
+            tuple_marker,
+            rename_keys,
+            list_marker,
+            redact_keys,
+            sort_rows_by,
+            index_large_arrays,
+            emit_version,
+            duck_typed_mappings,
+            tabular_footer,
+            lossless,
+            json_passthrough_keys,
+            max_string_len,
+            defensive_keys,
         }
     }
 }
@@ -56,665 +391,6963 @@ impl Default for EncodeOptions {
             indent: 2,
             use_length_markers: true,
             strict: true,
+            always_quote_strings: false,
+            typed_header: false,
+            scalar_sentinel: false,
+            parallel: false,
+            skip_nulls: false,
+            skip_empty: false,
+            tabular_allow_null_rows: false,
+            max_bytes: None,
+            case_insensitive_keys: false,
+            bytes_encoding: None,
+            section_spacing: false,
+            true_repr: "true".to_string(),
+            false_repr: "false".to_string(),
+            root_key: None,
+            comments: Comments::new(),
+            preview: None,
+            preserve_number_text: false,
+            enums_by_value: false,
+            matrix_mode: false,
+            null_repr: "null".to_string(),
+            flatten_paths: false,
+            sort_keys: false,
+            key_order: Vec::new(),
+            escape_delimiter_inline: false,
+            compact_numbers: false,
+            length_marker_style: LengthMarkerStyle::Inline,
+            max_tabular_columns: None,
+            pairs_as_object: false,
+            index_expanded_items: false,
+            tuple_marker: false,
+            rename_keys: std::collections::HashMap::new(),
+            list_marker: "- ".to_string(),
+            redact_keys: Vec::new(),
+            sort_rows_by: None,
+            index_large_arrays: None,
+            emit_version: false,
+            duck_typed_mappings: false,
+            tabular_footer: false,
+            lossless: false,
+            json_passthrough_keys: Vec::new(),
+            max_string_len: None,
+            defensive_keys: false,
         }
     }
 }
 
-/// Configuration options for TOON decoding
-#[pyclass]
-#[derive(Clone)]
-pub struct DecodeOptions {
-    #[pyo3(get, set)]
-    pub strict: bool,
-}
+impl EncodeOptions {
+    /// Start building options from the defaults
+    pub fn builder() -> EncodeOptionsBuilder {
+        EncodeOptionsBuilder::default()
+    }
 
-#[pymethods]
-impl DecodeOptions {
-    #[new]
-    #[pyo3(signature = (strict=true,))]
-    fn new(strict: bool) -> Self {
-        DecodeOptions { strict }
+    /// Fixed options for the canonical form: the defaults with keys sorted
+    pub fn canonical() -> Self {
+        EncodeOptions::builder().sort_keys(true).build()
     }
 }
 
-impl Default for DecodeOptions {
-    fn default() -> Self {
-        DecodeOptions { strict: true }
-    }
+/// Chainable builder for `EncodeOptions`; unset fields keep their defaults
+///
+/// ```
+/// use toon_tuna::EncodeOptions;
+///
+/// let options = EncodeOptions::builder()
+///     .delimiter("|")
+///     .indent(4)
+///     .use_length_markers(false)
+///     .build();
+///
+/// assert_eq!(options.delimiter, "|");
+/// assert_eq!(options.indent, 4);
+/// assert!(!options.use_length_markers);
+/// assert!(options.strict);
+/// ```
+#[derive(Clone, Default)]
+pub struct EncodeOptionsBuilder {
+    options: EncodeOptions,
 }
 
-/// Check if a string needs quoting according to TOON rules
-fn needs_quoting(s: &str, delimiter: &str) -> bool {
-    if s.is_empty() {
-        return true;
+impl EncodeOptionsBuilder {
+    pub fn delimiter(mut self, delimiter: impl Into<String>) -> Self {
+        self.options.delimiter = delimiter.into();
+        self
     }
 
-    // Check for leading/trailing whitespace
-    if s.starts_with(' ') || s.ends_with(' ') {
-        return true;
+    pub fn indent(mut self, indent: usize) -> Self {
+        self.options.indent = indent;
+        self
     }
 
-    // Check for reserved words
-    if matches!(s, "true" | "false" | "null") {
-        return true;
+    pub fn use_length_markers(mut self, use_length_markers: bool) -> Self {
+        self.options.use_length_markers = use_length_markers;
+        self
     }
 
-    // Check if it looks like a number
-    if s.parse::<f64>().is_ok() || s.starts_with('0') && s.len() > 1 && s.chars().nth(1) != Some('.') {
-        return true;
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
     }
 
-    // Check for special characters
-    for ch in s.chars() {
-        if matches!(ch, ':' | '"' | '\\' | '\n' | '\r' | '\t' | '[' | ']' | '{' | '}' | '-') {
-            return true;
-        }
-        if ch.is_control() {
-            return true;
-        }
+    pub fn always_quote_strings(mut self, always_quote_strings: bool) -> Self {
+        self.options.always_quote_strings = always_quote_strings;
+        self
     }
 
-    // Check for delimiter
-    if s.contains(delimiter) {
-        return true;
+    pub fn typed_header(mut self, typed_header: bool) -> Self {
+        self.options.typed_header = typed_header;
+        self
     }
 
-    false
-}
+    pub fn scalar_sentinel(mut self, scalar_sentinel: bool) -> Self {
+        self.options.scalar_sentinel = scalar_sentinel;
+        self
+    }
 
-/// Escape a string for TOON format
-fn escape_string(s: &str) -> String {
-    let mut result = String::new();
-    for ch in s.chars() {
-        match ch {
-            '\\' => result.push_str("\\\\"),
-            '"' => result.push_str("\\\""),
-            '\n' => result.push_str("\\n"),
-            '\r' => result.push_str("\\r"),
-            '\t' => result.push_str("\\t"),
-            _ => result.push(ch),
-        }
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.options.parallel = parallel;
+        self
     }
-    result
-}
 
-/// Quote a string if needed
-fn quote_if_needed(s: &str, delimiter: &str) -> String {
-    if needs_quoting(s, delimiter) {
-        format!("\"{}\"", escape_string(s))
-    } else {
-        s.to_string()
+    pub fn skip_nulls(mut self, skip_nulls: bool) -> Self {
+        self.options.skip_nulls = skip_nulls;
+        self
     }
-}
 
-/// Check if a key is a valid identifier (doesn't need quoting)
-fn is_valid_identifier(s: &str) -> bool {
-    if s.is_empty() {
-        return false;
+    pub fn skip_empty(mut self, skip_empty: bool) -> Self {
+        self.options.skip_empty = skip_empty;
+        self
     }
 
-    let mut chars = s.chars();
-    let first = chars.next().unwrap();
+    pub fn tabular_allow_null_rows(mut self, tabular_allow_null_rows: bool) -> Self {
+        self.options.tabular_allow_null_rows = tabular_allow_null_rows;
+        self
+    }
 
-    if !matches!(first, 'A'..='Z' | 'a'..='z' | '_') {
-        return false;
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.options.max_bytes = Some(max_bytes);
+        self
     }
 
-    for ch in chars {
-        if !matches!(ch, 'A'..='Z' | 'a'..='z' | '0'..='9' | '_' | '.') {
-            return false;
-        }
+    pub fn case_insensitive_keys(mut self, case_insensitive_keys: bool) -> Self {
+        self.options.case_insensitive_keys = case_insensitive_keys;
+        self
     }
 
-    true
-}
+    pub fn bytes_encoding(mut self, bytes_encoding: BytesEncoding) -> Self {
+        self.options.bytes_encoding = Some(bytes_encoding);
+        self
+    }
 
-/// Check if array contains uniform objects (all same keys, all primitive values)
-fn is_uniform_object_array(arr: &[Value]) -> Option<Vec<String>> {
-    if arr.is_empty() {
-        return None;
+    pub fn section_spacing(mut self, section_spacing: bool) -> Self {
+        self.options.section_spacing = section_spacing;
+        self
     }
 
-    let first = arr.first()?;
-    if !first.is_object() {
-        return None;
+    pub fn true_repr(mut self, true_repr: impl Into<String>) -> Self {
+        self.options.true_repr = true_repr.into();
+        self
     }
 
-    let first_obj = first.as_object()?;
-    let keys: Vec<String> = first_obj.keys().cloned().collect();
+    pub fn false_repr(mut self, false_repr: impl Into<String>) -> Self {
+        self.options.false_repr = false_repr.into();
+        self
+    }
 
-    // Check all values are primitives
-    for val in first_obj.values() {
-        if val.is_object() || val.is_array() {
-            return None;
-        }
+    pub fn root_key(mut self, root_key: impl Into<String>) -> Self {
+        self.options.root_key = Some(root_key.into());
+        self
     }
 
-    // Check all other objects have same keys and primitive values
-    for item in &arr[1..] {
-        if !item.is_object() {
-            return None;
-        }
-        let obj = item.as_object()?;
+    pub fn preserve_number_text(mut self, preserve_number_text: bool) -> Self {
+        self.options.preserve_number_text = preserve_number_text;
+        self
+    }
 
-        if obj.len() != keys.len() {
-            return None;
-        }
+    pub fn enums_by_value(mut self, enums_by_value: bool) -> Self {
+        self.options.enums_by_value = enums_by_value;
+        self
+    }
 
-        for key in &keys {
-            if !obj.contains_key(key) {
-                return None;
-            }
-            let val = &obj[key];
-            if val.is_object() || val.is_array() {
-                return None;
-            }
-        }
+    pub fn matrix_mode(mut self, matrix_mode: bool) -> Self {
+        self.options.matrix_mode = matrix_mode;
+        self
     }
 
-    Some(keys)
-}
+    pub fn null_repr(mut self, null_repr: impl Into<String>) -> Self {
+        self.options.null_repr = null_repr.into();
+        self
+    }
 
-/// Encode a value to TOON format
-fn encode_value(
-    value: &Value,
-    indent_level: usize,
-    options: &EncodeOptions,
-) -> Result<String, ToonError> {
-    let indent = " ".repeat(indent_level * options.indent);
+    pub fn flatten_paths(mut self, flatten_paths: bool) -> Self {
+        self.options.flatten_paths = flatten_paths;
+        self
+    }
 
-    match value {
-        Value::Null => Ok("null".to_string()),
-        Value::Bool(b) => Ok(b.to_string()),
-        Value::Number(n) => {
-            // Normalize numbers: no exponent, no trailing zeros
-            if let Some(i) = n.as_i64() {
-                Ok(i.to_string())
-            } else if let Some(f) = n.as_f64() {
-                let s = format!("{}", f);
-                // Remove trailing zeros after decimal point
-                if s.contains('.') {
-                    let trimmed = s.trim_end_matches('0').trim_end_matches('.');
-                    Ok(trimmed.to_string())
-                } else {
-                    Ok(s)
-                }
-            } else {
-                Ok(n.to_string())
-            }
-        }
-        Value::String(s) => Ok(quote_if_needed(s, &options.delimiter)),
-        Value::Array(arr) => {
-            if arr.is_empty() {
-                return Ok(format!("[0]:"));
-            }
+    pub fn sort_keys(mut self, sort_keys: bool) -> Self {
+        self.options.sort_keys = sort_keys;
+        self
+    }
 
-            // Check if it's a uniform object array (tabular format)
-            if let Some(keys) = is_uniform_object_array(arr) {
-                let mut result = String::new();
-
-                // Header: [N,]{key1,key2,...}:
-                let delim_marker = if options.delimiter == "," {
-                    ""
-                } else if options.delimiter == "\t" {
-                    "\t"
-                } else if options.delimiter == "|" {
-                    "|"
-                } else {
-                    return Err(ToonError::InvalidDelimiter(options.delimiter.clone()));
-                };
+    pub fn key_order(mut self, key_order: Vec<String>) -> Self {
+        self.options.key_order = key_order;
+        self
+    }
 
-                if options.use_length_markers {
-                    result.push_str(&format!("[{}{delim_marker}]", arr.len()));
-                } else {
-                    result.push_str("[]");
-                }
+    pub fn preview(mut self, preview: PreviewConfig) -> Self {
+        self.options.preview = Some(preview);
+        self
+    }
 
-                result.push('{');
-                for (i, key) in keys.iter().enumerate() {
-                    if i > 0 {
-                        result.push_str(&options.delimiter);
-                    }
-                    result.push_str(key);
-                }
-                result.push_str("}:\n");
+    pub fn escape_delimiter_inline(mut self, escape_delimiter_inline: bool) -> Self {
+        self.options.escape_delimiter_inline = escape_delimiter_inline;
+        self
+    }
 
-                // Data rows
-                for obj_val in arr {
-                    result.push_str(&indent);
-                    result.push_str(&" ".repeat(options.indent));
+    pub fn compact_numbers(mut self, compact_numbers: bool) -> Self {
+        self.options.compact_numbers = compact_numbers;
+        self
+    }
 
-                    let obj = obj_val.as_object().unwrap();
-                    for (i, key) in keys.iter().enumerate() {
-                        if i > 0 {
-                            result.push_str(&options.delimiter);
-                        }
-                        let val = &obj[key];
-                        let val_str = encode_value(val, 0, options)?;
-                        result.push_str(&val_str);
-                    }
-                    result.push('\n');
-                }
+    pub fn length_marker_style(mut self, length_marker_style: LengthMarkerStyle) -> Self {
+        self.options.length_marker_style = length_marker_style;
+        self
+    }
 
-                return Ok(result.trim_end().to_string());
-            }
+    pub fn max_tabular_columns(mut self, max_tabular_columns: usize) -> Self {
+        self.options.max_tabular_columns = Some(max_tabular_columns);
+        self
+    }
 
-            // Check if all elements are primitives (inline array)
-            let all_primitives = arr.iter().all(|v| !v.is_object() && !v.is_array());
+    pub fn pairs_as_object(mut self, pairs_as_object: bool) -> Self {
+        self.options.pairs_as_object = pairs_as_object;
+        self
+    }
 
-            if all_primitives {
-                // Inline format: [N,]: val1,val2,val3
-                let mut result = String::new();
-
-                let delim_marker = if options.delimiter == "," {
-                    ""
-                } else if options.delimiter == "\t" {
-                    "\t"
-                } else if options.delimiter == "|" {
-                    "|"
-                } else {
-                    return Err(ToonError::InvalidDelimiter(options.delimiter.clone()));
-                };
+    pub fn index_expanded_items(mut self, index_expanded_items: bool) -> Self {
+        self.options.index_expanded_items = index_expanded_items;
+        self
+    }
 
-                if options.use_length_markers {
-                    result.push_str(&format!("[{}{delim_marker}]: ", arr.len()));
-                } else {
-                    result.push_str("[]: ");
-                }
+    pub fn tuple_marker(mut self, tuple_marker: bool) -> Self {
+        self.options.tuple_marker = tuple_marker;
+        self
+    }
 
-                for (i, val) in arr.iter().enumerate() {
-                    if i > 0 {
-                        result.push_str(&options.delimiter);
-                    }
-                    result.push_str(&encode_value(val, 0, options)?);
-                }
+    pub fn rename_keys(mut self, rename_keys: std::collections::HashMap<String, String>) -> Self {
+        self.options.rename_keys = rename_keys;
+        self
+    }
 
-                return Ok(result);
-            }
+    pub fn list_marker(mut self, list_marker: impl Into<String>) -> Self {
+        self.options.list_marker = list_marker.into();
+        self
+    }
 
-            // Mixed/nested array (expanded format with -)
-            let mut result = String::new();
+    pub fn redact_keys(mut self, redact_keys: Vec<String>) -> Self {
+        self.options.redact_keys = redact_keys;
+        self
+    }
 
-            let delim_marker = if options.delimiter == "," {
-                ""
-            } else if options.delimiter == "\t" {
-                "\t"
-            } else if options.delimiter == "|" {
-                "|"
-            } else {
-                return Err(ToonError::InvalidDelimiter(options.delimiter.clone()));
-            };
+    pub fn sort_rows_by(mut self, sort_rows_by: impl Into<String>) -> Self {
+        self.options.sort_rows_by = Some(sort_rows_by.into());
+        self
+    }
 
-            if options.use_length_markers {
-                result.push_str(&format!("[{}{delim_marker}]:\n", arr.len()));
-            } else {
-                result.push_str("[]:\n");
-            }
+    pub fn index_large_arrays(mut self, index_large_arrays: usize) -> Self {
+        self.options.index_large_arrays = Some(index_large_arrays);
+        self
+    }
 
-            for val in arr {
-                result.push_str(&indent);
-                result.push_str(&" ".repeat(options.indent));
-                result.push_str("- ");
-
-                if val.is_object() {
-                    let obj = val.as_object().unwrap();
-                    let mut first = true;
-                    for (key, v) in obj {
-                        if !first {
-                            result.push('\n');
-                            result.push_str(&indent);
-                            result.push_str(&" ".repeat(options.indent * 2));
-                        }
-                        first = false;
-
-                        let key_str = if is_valid_identifier(key) {
-                            key.clone()
-                        } else {
-                            quote_if_needed(key, &options.delimiter)
-                        };
-
-                        if v.is_object() || v.is_array() {
-                            result.push_str(&format!("{}:\n", key_str));
-                            let nested = encode_value(v, indent_level + 2, options)?;
-                            for line in nested.lines() {
-                                result.push_str(&indent);
-                                result.push_str(&" ".repeat(options.indent * 2));
-                                result.push_str(line);
-                                result.push('\n');
-                            }
-                        } else {
-                            result.push_str(&format!("{}: {}", key_str, encode_value(v, 0, options)?));
-                        }
-                    }
-                    result.push('\n');
-                } else {
-                    result.push_str(&encode_value(val, 0, options)?);
-                    result.push('\n');
-                }
-            }
+    pub fn emit_version(mut self, emit_version: bool) -> Self {
+        self.options.emit_version = emit_version;
+        self
+    }
 
-            Ok(result.trim_end().to_string())
-        }
-        Value::Object(obj) => {
-            if obj.is_empty() {
-                return Ok(String::new());
-            }
+    pub fn duck_typed_mappings(mut self, duck_typed_mappings: bool) -> Self {
+        self.options.duck_typed_mappings = duck_typed_mappings;
+        self
+    }
 
-            let mut result = String::new();
+    pub fn tabular_footer(mut self, tabular_footer: bool) -> Self {
+        self.options.tabular_footer = tabular_footer;
+        self
+    }
 
-            for (i, (key, val)) in obj.iter().enumerate() {
-                if i > 0 {
-                    result.push('\n');
-                }
+    pub fn lossless(mut self, lossless: bool) -> Self {
+        self.options.lossless = lossless;
+        self
+    }
 
-                result.push_str(&indent);
+    pub fn json_passthrough_keys(mut self, json_passthrough_keys: Vec<String>) -> Self {
+        self.options.json_passthrough_keys = json_passthrough_keys;
+        self
+    }
 
-                let key_str = if is_valid_identifier(key) {
-                    key.clone()
-                } else {
-                    quote_if_needed(key, &options.delimiter)
-                };
+    pub fn max_string_len(mut self, max_string_len: usize) -> Self {
+        self.options.max_string_len = Some(max_string_len);
+        self
+    }
 
-                if val.is_object() || val.is_array() {
-                    result.push_str(&format!("{}:\n", key_str));
-                    let nested = encode_value(val, indent_level + 1, options)?;
-                    for line in nested.lines() {
-                        result.push_str(&indent);
-                        result.push_str(&" ".repeat(options.indent));
-                        result.push_str(line);
-                        result.push('\n');
-                    }
-                    result = result.trim_end().to_string();
-                } else {
-                    result.push_str(&format!("{}: {}", key_str, encode_value(val, 0, options)?));
-                }
+    pub fn defensive_keys(mut self, defensive_keys: bool) -> Self {
+        self.options.defensive_keys = defensive_keys;
+        self
+    }
+
+    pub fn build(self) -> EncodeOptions {
+        self.options
+    }
+}
+
+/// Limits for a truncated, human-readable preview. The output decodes, with
+/// each `…(+N more)` marker as a string, but not back to the original.
+#[derive(Clone, Copy, Debug)]
+pub struct PreviewConfig {
+    /// Items kept from each array
+    pub max_items: usize,
+    /// Characters kept from each string
+    pub max_chars: usize,
+}
+
+/// Major version of the format written by `emit_version`
+pub const TOON_VERSION: u32 = 1;
+
+/// Start of the version line, followed by the version number
+const VERSION_PREFIX: &str = "#toon v";
+
+/// Key of the single-entry object standing in for a tuple under `tuple_marker`
+pub const TUPLE_KEY: &str = "__tuple__";
+
+/// Tag `items` as a tuple, the way the Python walk does under `tuple_marker`
+fn tag_tuple(items: Value) -> Value {
+    Value::Object(serde_json::Map::from_iter([(TUPLE_KEY.to_string(), items)]))
+}
+
+/// Items of an object that is a tagged tuple
+fn tuple_items(obj: &serde_json::Map<String, Value>) -> Option<&Vec<Value>> {
+    match obj.get(TUPLE_KEY) {
+        Some(Value::Array(items)) if obj.len() == 1 => Some(items),
+        _ => None,
+    }
+}
+
+/// Marker appended where a preview dropped `n` items or characters
+fn more_marker(n: usize) -> String {
+    format!("…(+{} more)", n)
+}
+
+/// `value` cut down for a preview. A cut array's marker is one more item,
+/// so the written `[N]` counts it and the preview still decodes.
+fn preview_value(value: &Value, preview: PreviewConfig) -> Value {
+    match value {
+        Value::String(s) => {
+            let len = s.chars().count();
+            if len <= preview.max_chars {
+                return value.clone();
+            }
+            let kept: String = s.chars().take(preview.max_chars).collect();
+            Value::String(kept + &more_marker(len - preview.max_chars))
+        }
+        Value::Array(arr) => {
+            let mut items: Vec<Value> = arr.iter().take(preview.max_items).map(|item| preview_value(item, preview)).collect();
+            if arr.len() > preview.max_items {
+                items.push(Value::String(more_marker(arr.len() - preview.max_items)));
+            }
+            Value::Array(items)
+        }
+        Value::Object(obj) => Value::Object(obj.iter().map(|(key, val)| (key.clone(), preview_value(val, preview))).collect()),
+        _ => value.clone(),
+    }
+}
+
+/// Default cap on the length of a token parsed as a number
+const DEFAULT_MAX_NUMBER_LEN: usize = 4096;
+
+/// Default cap on how deeply decoded blocks may nest
+const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Default `DecodeOptions.tab_width`: one leading tab is one indentation level
+const DEFAULT_TAB_WIDTH: usize = 1;
+
+/// Configuration options for TOON decoding
+#[pyclass]
+#[derive(Clone)]
+pub struct DecodeOptions {
+    #[pyo3(get, set)]
+    pub strict: bool,
+    /// Decode empty tabular cells as null instead of an empty string
+    #[pyo3(get, set)]
+    pub empty_cell_as_null: bool,
+    /// Delimiter for array headers that don't declare one (defaults to comma)
+    #[pyo3(get, set)]
+    pub delimiter: Option<String>,
+    /// Sniff the delimiter of undeclared array headers from their rows
+    #[pyo3(get, set)]
+    pub auto_delimiter: bool,
+    /// Turn `b64:` / `hex:` string tokens back into Python `bytes`
+    #[pyo3(get, set)]
+    pub decode_bytes_prefix: bool,
+    /// Literal read as boolean true
+    #[pyo3(get, set)]
+    pub true_repr: String,
+    /// Literal read as boolean false
+    #[pyo3(get, set)]
+    pub false_repr: String,
+    /// Tokens longer than this are kept as strings instead of parsed as numbers
+    #[pyo3(get, set)]
+    pub max_number_len: usize,
+    /// Unwrap a root object whose only key is this one
+    #[pyo3(get, set)]
+    pub unwrap_root: Option<String>,
+    /// Keep the text of number tokens (`1.50`, big integers) so re-encoding can reproduce it
+    #[pyo3(get, set)]
+    pub preserve_number_text: bool,
+    /// In lenient mode, collapse whitespace runs inside unquoted values to one space
+    #[pyo3(get, set)]
+    pub collapse_whitespace: bool,
+    /// Literal read as null
+    #[pyo3(get, set)]
+    pub null_repr: String,
+    /// Rebuild nested objects from dotted `a.b.c` keys
+    #[pyo3(get, set)]
+    pub expand_paths: bool,
+    /// Skip `# ...` comment lines and trailing `  # ...` comments after a
+    /// value or header; otherwise a leading `#` is ordinary content
+    #[pyo3(get, set)]
+    pub allow_comments: bool,
+    /// Read `1_000` as a number, ignoring underscores between digits
+    #[pyo3(get, set)]
+    pub allow_underscore_numbers: bool,
+    /// Deepest nesting accepted before decoding fails, which keeps deep input from overflowing the stack
+    #[pyo3(get, set)]
+    pub max_depth: usize,
+    /// Skip blank lines between the rows of a tabular or matrix block instead of ending it
+    #[pyo3(get, set)]
+    pub allow_blank_rows: bool,
+    /// Read `(N,): a,b` lines as tagged tuples, which become Python tuples
+    #[pyo3(get, set)]
+    pub tuple_marker: bool,
+    /// Marker starting each expanded array item
+    #[pyo3(get, set)]
+    pub list_marker: String,
+    /// Read `{i,value}` tables whose `i` counts up from 0 back as flat arrays
+    #[pyo3(get, set)]
+    pub unindex_arrays: bool,
+    /// Keys whose value is the rest of the line, kept verbatim as a string
+    #[pyo3(get, set)]
+    pub raw_keys: Vec<String>,
+    /// What to do with an integer too large for 64 bits
+    #[pyo3(get, set)]
+    pub on_number_overflow: NumberOverflow,
+    /// Columns each leading tab counts for in indentation; 0 keeps tabs out of it
+    #[pyo3(get, set)]
+    pub tab_width: usize,
+    /// Keys whose string values are JSON text to parse, as written by `json_passthrough_keys` on encode
+    #[pyo3(get, set)]
+    pub json_passthrough_keys: Vec<String>,
+    /// Turn objects keyed `"0"` to `"N-1"` into arrays after decoding
+    #[pyo3(get, set)]
+    pub numeric_keys_as_array: bool,
+    /// Read a tabular row of only empty cells as null, as written by
+    /// `tabular_allow_null_rows` on encode
+    #[pyo3(get, set)]
+    pub tabular_allow_null_rows: bool,
+}
+
+#[pymethods]
+impl DecodeOptions {
+    #[new]
+    #[pyo3(signature = (strict=true, empty_cell_as_null=false, delimiter=None, auto_delimiter=false, decode_bytes_prefix=false, true_repr="true".to_string(), false_repr="false".to_string(), max_number_len=DEFAULT_MAX_NUMBER_LEN, unwrap_root=None, preserve_number_text=false, collapse_whitespace=false, null_repr="null".to_string(), expand_paths=false, allow_comments=false, allow_underscore_numbers=false, max_depth=DEFAULT_MAX_DEPTH, allow_blank_rows=false, tuple_marker=false, list_marker="- ".to_string(), unindex_arrays=false, raw_keys=Vec::new(), on_number_overflow=NumberOverflow::AsFloat, tab_width=DEFAULT_TAB_WIDTH, json_passthrough_keys=Vec::new(), numeric_keys_as_array=false, tabular_allow_null_rows=false))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        strict: bool,
+        empty_cell_as_null: bool,
+        delimiter: Option<String>,
+        auto_delimiter: bool,
+        decode_bytes_prefix: bool,
+        true_repr: String,
+        false_repr: String,
+        max_number_len: usize,
+        unwrap_root: Option<String>,
+        preserve_number_text: bool,
+        collapse_whitespace: bool,
+        null_repr: String,
+        expand_paths: bool,
+        allow_comments: bool,
+        allow_underscore_numbers: bool,
+        max_depth: usize,
+        allow_blank_rows: bool,
+        tuple_marker: bool,
+        list_marker: String,
+        unindex_arrays: bool,
+        raw_keys: Vec<String>,
+        on_number_overflow: NumberOverflow,
+        tab_width: usize,
+        json_passthrough_keys: Vec<String>,
+        numeric_keys_as_array: bool,
+        tabular_allow_null_rows: bool,
+    ) -> Self {
+        DecodeOptions {
+            strict,
+            empty_cell_as_null,
+            delimiter,
+            auto_delimiter,
+            decode_bytes_prefix,
+            true_repr,
+            false_repr,
+            max_number_len,
+            unwrap_root,
+            preserve_number_text,
+            collapse_whitespace,
+            null_repr,
+            expand_paths,
+            allow_comments,
+            allow_underscore_numbers,
+            max_depth,
+            allow_blank_rows,
+            tuple_marker,
+            list_marker,
+            unindex_arrays,
+            raw_keys,
+            on_number_overflow,
+            tab_width,
+            json_passthrough_keys,
+            numeric_keys_as_array,
+            tabular_allow_null_rows,
+        }
+    }
+
+    /// Build options from a dict of field values; missing fields keep their defaults
+    #[staticmethod]
+    fn from_dict(py: Python, fields: &Bound<'_, PyDict>) -> PyResult<Self> {
+        set_fields(py, DecodeOptions::default(), fields, "DecodeOptions.from_dict()")
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            strict: true,
+            empty_cell_as_null: false,
+            delimiter: None,
+            auto_delimiter: false,
+            decode_bytes_prefix: false,
+            true_repr: "true".to_string(),
+            false_repr: "false".to_string(),
+            max_number_len: DEFAULT_MAX_NUMBER_LEN,
+            unwrap_root: None,
+            preserve_number_text: false,
+            collapse_whitespace: false,
+            null_repr: "null".to_string(),
+            expand_paths: false,
+            allow_comments: false,
+            allow_underscore_numbers: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            allow_blank_rows: false,
+            tuple_marker: false,
+            list_marker: "- ".to_string(),
+            unindex_arrays: false,
+            raw_keys: Vec::new(),
+            on_number_overflow: NumberOverflow::AsFloat,
+            tab_width: DEFAULT_TAB_WIDTH,
+            json_passthrough_keys: Vec::new(),
+            numeric_keys_as_array: false,
+            tabular_allow_null_rows: false,
+        }
+    }
+}
+
+impl DecodeOptions {
+    /// Start building options from the defaults
+    pub fn builder() -> DecodeOptionsBuilder {
+        DecodeOptionsBuilder::default()
+    }
+}
+
+/// Chainable builder for `DecodeOptions`; unset fields keep their defaults
+///
+/// ```
+/// use toon_tuna::DecodeOptions;
+///
+/// let options = DecodeOptions::builder()
+///     .strict(false)
+///     .delimiter("|")
+///     .build();
+///
+/// assert!(!options.strict);
+/// assert_eq!(options.delimiter.as_deref(), Some("|"));
+/// assert!(!options.empty_cell_as_null);
+/// ```
+#[derive(Clone, Default)]
+pub struct DecodeOptionsBuilder {
+    options: DecodeOptions,
+}
+
+impl DecodeOptionsBuilder {
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    pub fn empty_cell_as_null(mut self, empty_cell_as_null: bool) -> Self {
+        self.options.empty_cell_as_null = empty_cell_as_null;
+        self
+    }
+
+    pub fn delimiter(mut self, delimiter: impl Into<String>) -> Self {
+        self.options.delimiter = Some(delimiter.into());
+        self
+    }
+
+    pub fn auto_delimiter(mut self, auto_delimiter: bool) -> Self {
+        self.options.auto_delimiter = auto_delimiter;
+        self
+    }
+
+    pub fn decode_bytes_prefix(mut self, decode_bytes_prefix: bool) -> Self {
+        self.options.decode_bytes_prefix = decode_bytes_prefix;
+        self
+    }
+
+    pub fn true_repr(mut self, true_repr: impl Into<String>) -> Self {
+        self.options.true_repr = true_repr.into();
+        self
+    }
+
+    pub fn false_repr(mut self, false_repr: impl Into<String>) -> Self {
+        self.options.false_repr = false_repr.into();
+        self
+    }
+
+    pub fn max_number_len(mut self, max_number_len: usize) -> Self {
+        self.options.max_number_len = max_number_len;
+        self
+    }
+
+    pub fn unwrap_root(mut self, unwrap_root: impl Into<String>) -> Self {
+        self.options.unwrap_root = Some(unwrap_root.into());
+        self
+    }
+
+    pub fn preserve_number_text(mut self, preserve_number_text: bool) -> Self {
+        self.options.preserve_number_text = preserve_number_text;
+        self
+    }
+
+    pub fn collapse_whitespace(mut self, collapse_whitespace: bool) -> Self {
+        self.options.collapse_whitespace = collapse_whitespace;
+        self
+    }
+
+    pub fn null_repr(mut self, null_repr: impl Into<String>) -> Self {
+        self.options.null_repr = null_repr.into();
+        self
+    }
+
+    pub fn expand_paths(mut self, expand_paths: bool) -> Self {
+        self.options.expand_paths = expand_paths;
+        self
+    }
+
+    pub fn allow_comments(mut self, allow_comments: bool) -> Self {
+        self.options.allow_comments = allow_comments;
+        self
+    }
+
+    pub fn allow_underscore_numbers(mut self, allow_underscore_numbers: bool) -> Self {
+        self.options.allow_underscore_numbers = allow_underscore_numbers;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.options.max_depth = max_depth;
+        self
+    }
+
+    pub fn allow_blank_rows(mut self, allow_blank_rows: bool) -> Self {
+        self.options.allow_blank_rows = allow_blank_rows;
+        self
+    }
+
+    pub fn tuple_marker(mut self, tuple_marker: bool) -> Self {
+        self.options.tuple_marker = tuple_marker;
+        self
+    }
+
+    pub fn list_marker(mut self, list_marker: impl Into<String>) -> Self {
+        self.options.list_marker = list_marker.into();
+        self
+    }
+
+    pub fn unindex_arrays(mut self, unindex_arrays: bool) -> Self {
+        self.options.unindex_arrays = unindex_arrays;
+        self
+    }
+
+    pub fn raw_keys(mut self, raw_keys: Vec<String>) -> Self {
+        self.options.raw_keys = raw_keys;
+        self
+    }
+
+    pub fn on_number_overflow(mut self, on_number_overflow: NumberOverflow) -> Self {
+        self.options.on_number_overflow = on_number_overflow;
+        self
+    }
+
+    pub fn tab_width(mut self, tab_width: usize) -> Self {
+        self.options.tab_width = tab_width;
+        self
+    }
+
+    pub fn json_passthrough_keys(mut self, json_passthrough_keys: Vec<String>) -> Self {
+        self.options.json_passthrough_keys = json_passthrough_keys;
+        self
+    }
+
+    pub fn numeric_keys_as_array(mut self, numeric_keys_as_array: bool) -> Self {
+        self.options.numeric_keys_as_array = numeric_keys_as_array;
+        self
+    }
+
+    pub fn tabular_allow_null_rows(mut self, tabular_allow_null_rows: bool) -> Self {
+        self.options.tabular_allow_null_rows = tabular_allow_null_rows;
+        self
+    }
+
+    pub fn build(self) -> DecodeOptions {
+        self.options
+    }
+}
+
+/// Byte order mark some editors prepend; never written, skipped on decode
+const BOM: char = '\u{FEFF}';
+
+/// Check if a string needs quoting as a whole, from its start or its exact
+/// text. Single characters ([`forces_quotes`]) and numbers ([`NumberScan`])
+/// are found while [`quote_if_needed`] scans the string.
+fn needs_quoting(s: &str, options: &EncodeOptions) -> bool {
+    if s.is_empty() {
+        return true;
+    }
+
+    // A leading '#' would read back as a comment line under allow_comments,
+    // and a leading BOM would be stripped from the first line of the document
+    if s.starts_with('#') || s.starts_with(BOM) {
+        return true;
+    }
+
+    // A custom list marker at the start would read back as a list item
+    let bullet = options.list_marker.trim_end();
+    if !bullet.is_empty() && s.starts_with(bullet) {
+        return true;
+    }
+
+    // A key like `(2)` would read back as a tuple header
+    if options.tuple_marker && s.starts_with('(') {
+        return true;
+    }
+
+    // Check for reserved words and the configured literals
+    if matches!(s, "true" | "false" | "null")
+        || s == options.true_repr
+        || s == options.false_repr
+        || s == options.null_repr
+    {
+        return true;
+    }
+
+    // Words that parse as floats, and leading zeros like `007` or `0x1f`
+    let word = s.strip_prefix('+').unwrap_or(s);
+    ["inf", "infinity", "nan"].iter().any(|float| word.eq_ignore_ascii_case(float))
+        || s.starts_with('0') && s.len() > 1 && s.chars().nth(1) != Some('.')
+}
+
+/// Whether the characters so far read as a decimal float, as `str::parse::<f64>`
+/// takes them: `1`, `+1.5`, `.5`, `5.`, `1e3`
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum NumberScan {
+    Start,
+    Sign,
+    /// A `.` with no digits before it
+    Dot,
+    Int,
+    Frac,
+    Exp,
+    ExpSign,
+    ExpDigits,
+    NotNumber,
+}
+
+impl NumberScan {
+    fn next(self, ch: char) -> Self {
+        use NumberScan::*;
+        let digit = ch.is_ascii_digit();
+        match (self, ch) {
+            (Start, '+' | '-') => Sign,
+            (Start | Sign, '.') => Dot,
+            (Start | Sign | Int, _) if digit => Int,
+            (Int, '.') => Frac,
+            (Dot | Frac, _) if digit => Frac,
+            (Int | Frac, 'e' | 'E') => Exp,
+            (Exp, '+' | '-') => ExpSign,
+            (Exp | ExpSign | ExpDigits, _) if digit => ExpDigits,
+            _ => NotNumber,
+        }
+    }
+
+    fn is_number(self) -> bool {
+        matches!(self, NumberScan::Int | NumberScan::Frac | NumberScan::ExpDigits)
+    }
+}
+
+/// Whitespace, structural and control characters force quotes anywhere in a
+/// string. Interior spaces count too (`"hello world"`, as the original
+/// `test_quote_if_needed` expects), so an unquoted value is always one token.
+fn forces_quotes(ch: char) -> bool {
+    matches!(ch, ':' | '"' | '\\' | '[' | ']' | '{' | '}' | '-') || ch.is_whitespace() || ch.is_control()
+}
+
+/// Escape a string for TOON format
+fn escape_string(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for ch in s.chars() {
+        push_escaped(&mut result, ch);
+    }
+    result
+}
+
+/// Append `ch` to a quoted string, escaped if needed
+fn push_escaped(result: &mut String, ch: char) {
+    match ch {
+        '\\' => result.push_str("\\\\"),
+        '"' => result.push_str("\\\""),
+        '\n' => result.push_str("\\n"),
+        '\r' => result.push_str("\\r"),
+        '\t' => result.push_str("\\t"),
+        BOM => result.push_str("\\ufeff"),
+        // Printable characters, including astral ones like emoji, stay raw
+        _ if ch.is_control() => result.push_str(&format!("\\u{:04x}", ch as u32)),
+        _ => result.push(ch),
+    }
+}
+
+/// Quote a string if needed. One pass finds the first character forcing
+/// quotes and escapes from there on; strings needing no quotes (the common
+/// case) are scanned once and copied.
+fn quote_if_needed(s: &str, options: &EncodeOptions) -> String {
+    let delimiter = options.delimiter.as_str();
+    let mut quoted = needs_quoting(s, options).then(|| {
+        let mut result = String::with_capacity(s.len() + 2);
+        result.push('"');
+        result
+    });
+    // Unquoted text with its delimiters escaped, once the first one is found
+    let mut inline: Option<String> = None;
+    let mut delimiter_end = 0;
+    let mut number = NumberScan::Start;
+    // Whether the characters scanned so far need no escaping inside quotes
+    let mut plain_prefix = true;
+    for (i, ch) in s.char_indices() {
+        if let Some(result) = quoted.as_mut() {
+            push_escaped(result, ch);
+            continue;
+        }
+        number = number.next(ch);
+
+        let at_delimiter = i >= delimiter_end && s[i..].starts_with(delimiter);
+        // An escaped delimiter needs no quotes
+        if at_delimiter && options.escape_delimiter_inline {
+            plain_prefix &= ch != '\t';
+            let result = inline.get_or_insert_with(|| s[..i].to_string());
+            push_escaped_delimiter(result, delimiter);
+            delimiter_end = i + delimiter.len();
+            continue;
+        }
+        if at_delimiter || forces_quotes(ch) {
+            let mut result = String::with_capacity(s.len() + 2);
+            result.push('"');
+            if plain_prefix {
+                result.push_str(&s[..i]);
+            } else {
+                s[..i].chars().for_each(|c| push_escaped(&mut result, c));
             }
+            push_escaped(&mut result, ch);
+            quoted = Some(result);
+            continue;
+        }
+        plain_prefix &= ch != BOM;
+        if let Some(result) = inline.as_mut().filter(|_| i >= delimiter_end) {
+            result.push(ch);
+        }
+    }
+
+    match (quoted, inline) {
+        (Some(mut result), _) => {
+            result.push('"');
+            result
+        }
+        // Text that reads as a number has nothing to escape
+        (None, _) if number.is_number() => format!("\"{}\"", s),
+        (None, Some(result)) => result,
+        (None, None) => s.to_string(),
+    }
+}
+
+/// Backslash-escape the delimiter in an unquoted value (`a\,b`, `a\|b`, `a\tb`)
+fn push_escaped_delimiter(result: &mut String, delimiter: &str) {
+    if delimiter == "\t" {
+        result.push_str("\\t");
+    } else {
+        result.push('\\');
+        result.push_str(delimiter);
+    }
+}
+
+/// Undo [`push_escaped_delimiter`] in an unquoted value, for any of the delimiters
+fn unescape_delimiters(s: &str) -> Cow<'_, str> {
+    if !s.contains('\\') {
+        return Cow::Borrowed(s);
+    }
+    Cow::Owned(s.replace("\\,", ",").replace("\\|", "|").replace("\\t", "\t"))
+}
+
+/// Render an object key or tabular header field, quoting it when needed
+fn format_key(key: &str, options: &EncodeOptions) -> String {
+    if is_valid_identifier(key) {
+        key.to_string()
+    } else if options.defensive_keys || key.contains('.') {
+        // Malformed dotted keys are always quoted so they can't pass for paths,
+        // and defensive mode quotes anything a reader could misparse
+        format!("\"{}\"", escape_string(key))
+    } else {
+        quote_if_needed(key, options)
+    }
+}
+
+/// Check if a key is a valid identifier (doesn't need quoting)
+fn is_valid_identifier(s: &str) -> bool {
+    if s.is_empty() {
+        return false;
+    }
+
+    let mut chars = s.chars();
+    let first = chars.next().unwrap();
+
+    if !matches!(first, 'A'..='Z' | 'a'..='z' | '_') {
+        return false;
+    }
+
+    for ch in chars {
+        if !matches!(ch, 'A'..='Z' | 'a'..='z' | '0'..='9' | '_' | '.') {
+            return false;
+        }
+    }
+
+    // Dots only between segments: no trailing or empty segments
+    !s.ends_with('.') && !s.contains("..")
+}
+
+/// Keys of `obj` in output order: `key_order` entries first, then the rest,
+/// sorted with `sort_keys` or else in insertion order
+fn ordered_keys<'v>(obj: &'v serde_json::Map<String, Value>, options: &EncodeOptions) -> Vec<&'v String> {
+    let mut keys: Vec<&String> = obj.keys().collect();
+    if options.sort_keys {
+        keys.sort();
+    }
+    if !options.key_order.is_empty() {
+        let rank = |key: &String| options.key_order.iter().position(|k| k == key).unwrap_or(usize::MAX);
+        keys.sort_by_key(|key| rank(key));
+    }
+    keys
+}
+
+/// Placeholder written for the values of `redact_keys`
+const REDACTED: &str = "***";
+
+/// Whether the value under `key` is hidden by `redact_keys`
+fn is_redacted(key: &str, options: &EncodeOptions) -> bool {
+    options.redact_keys.iter().any(|redacted| redacted.eq_ignore_ascii_case(key))
+}
+
+/// Name `key` is written under, after `rename_keys`
+fn output_key<'k>(key: &'k str, options: &'k EncodeOptions) -> &'k str {
+    options.rename_keys.get(key).map_or(key, String::as_str)
+}
+
+/// Check if array contains uniform objects (all same keys, all primitive values)
+fn is_uniform_object_array(arr: &[Value], options: &EncodeOptions) -> Option<Vec<String>> {
+    if arr.is_empty() {
+        return None;
+    }
+
+    // With `tabular_allow_null_rows`, null elements are placeholders for whole rows
+    let allow_null = |item: &Value| options.tabular_allow_null_rows && item.is_null();
+
+    let first = arr.iter().find(|item| !allow_null(item))?;
+    if !first.is_object() {
+        return None;
+    }
+
+    let first_obj = first.as_object()?;
+    let keys: Vec<String> = ordered_keys(first_obj, options).into_iter().cloned().collect();
+
+    // Passed-through JSON is written as a string, so any value fits in a cell
+    let fits_cell =
+        |key: &String, val: &Value| !(val.is_object() || val.is_array()) || options.json_passthrough_keys.contains(key);
+
+    // Check all values are primitives
+    for (key, val) in first_obj {
+        if !fits_cell(key, val) {
+            return None;
+        }
+    }
+
+    // Check all other objects have same keys and primitive values
+    for item in arr.iter().filter(|item| !allow_null(item)) {
+        if !item.is_object() {
+            return None;
+        }
+        let obj = item.as_object()?;
+
+        if obj.len() != keys.len() {
+            return None;
+        }
+
+        for key in &keys {
+            if !obj.contains_key(key) {
+                return None;
+            }
+            if !fits_cell(key, &obj[key]) {
+                return None;
+            }
+        }
+    }
+
+    // Drop skipped columns only when they are skipped in every row
+    let keys: Vec<String> = keys
+        .into_iter()
+        .filter(|key| {
+            !arr.iter()
+                .filter(|item| item.is_object())
+                .all(|item| is_skipped(&item[key.as_str()], options))
+        })
+        .collect();
+
+    // A row of no columns would be a blank line, which ends the table
+    if keys.is_empty() {
+        return None;
+    }
+
+    // A null row is a line of empty cells, which needs at least one delimiter
+    if keys.len() < 2 && arr.iter().any(|item| item.is_null()) {
+        return None;
+    }
+
+    // Very wide rows read better expanded
+    if options.max_tabular_columns.is_some_and(|max| keys.len() > max) {
+        return None;
+    }
+
+    Some(keys)
+}
+
+/// Check if a field should be omitted under `skip_nulls` / `skip_empty`
+fn is_skipped(value: &Value, options: &EncodeOptions) -> bool {
+    match value {
+        Value::Null => options.skip_nulls,
+        Value::String(s) => options.skip_empty && s.is_empty(),
+        Value::Array(arr) => options.skip_empty && arr.is_empty(),
+        _ => false,
+    }
+}
+
+/// Ensure no two keys render to the same text
+fn check_key_collisions(
+    rendered: impl Iterator<Item = String>,
+    path: &str,
+) -> Result<(), ToonError> {
+    let mut seen = std::collections::HashSet::new();
+    for key in rendered {
+        if !seen.insert(key.clone()) {
+            return Err(ToonError::EncodingError(at_path(
+                format!("Key collision after rendering: {}", key),
+                path,
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Check if `obj` is an `enum.Enum` member
+fn is_enum_member(obj: &Bound<'_, PyAny>) -> bool {
+    obj.py()
+        .import("enum")
+        .and_then(|module| module.getattr("Enum"))
+        .and_then(|enum_type| obj.is_instance(&enum_type))
+        .unwrap_or(false)
+}
+
+/// Convert Python data to a JSON value. Floats are checked for finiteness in
+/// strict mode (serde_json would silently turn them into null) and `bytes`
+/// are written as prefixed tokens when `bytes_encoding` is set.
+fn python_to_value(obj: &Bound<'_, PyAny>, options: &EncodeOptions) -> Result<Value, ToonError> {
+    python_to_value_with(obj, options, None)
+}
+
+/// [`python_to_value`] with a `default` callable, which like `json.dumps`'s
+/// `default` turns an unsupported object into something that can be encoded
+fn python_to_value_with(
+    obj: &Bound<'_, PyAny>,
+    options: &EncodeOptions,
+    default: Option<&Bound<'_, PyAny>>,
+) -> Result<Value, ToonError> {
+    if let Ok(f) = obj.downcast::<PyFloat>() {
+        if options.strict && !f.value().is_finite() {
+            return Err(ToonError::EncodingError(format!(
+                "Non-finite float {} cannot be encoded",
+                f.value()
+            )));
+        }
+    } else if let Ok(int) = obj.downcast::<PyInt>() {
+        // depythonize turns ints wider than 64 bits into floats
+        if options.strict && int.extract::<i64>().is_err() && int.extract::<u64>().is_err() {
+            return Err(ToonError::EncodingError(format!(
+                "Integer {} does not fit in 64 bits and would lose precision",
+                int
+            )));
+        }
+    } else if let Ok(bytes) = obj.downcast::<PyBytes>() {
+        if let Some(encoding) = options.bytes_encoding {
+            return Ok(Value::String(encode_bytes(bytes.as_bytes(), encoding)));
+        }
+    } else if let Ok(dict) = obj.downcast::<PyDict>() {
+        let mut map = serde_json::Map::new();
+        for (key, val) in dict.iter() {
+            let key: String = key
+                .extract()
+                .map_err(|_| ToonError::PythonError(format!("Dict key {} is not a string", key)))?;
+            map.insert(key, python_to_value_with(&val, options, default)?);
+        }
+        return Ok(Value::Object(map));
+    } else if let Ok(list) = obj.downcast::<PyList>() {
+        if options.pairs_as_object && is_pair_list(list) {
+            return pairs_to_object(list, options, default);
+        }
+        return list.iter().map(|item| python_to_value_with(&item, options, default)).collect();
+    } else if let Ok(tuple) = obj.downcast::<PyTuple>() {
+        let items = tuple
+            .iter()
+            .map(|item| python_to_value_with(&item, options, default))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Only flat tuples fit on one `(N,):` line
+        if options.tuple_marker && items.iter().all(|v| !v.is_object() && !v.is_array()) {
+            return Ok(tag_tuple(Value::Array(items)));
+        }
+        return Ok(Value::Array(items));
+    } else if options.enums_by_value && is_enum_member(obj) {
+        let value = obj.getattr("value").map_err(|e| ToonError::PythonError(e.to_string()))?;
+        return python_to_value_with(&value, options, default);
+    } else if options.duck_typed_mappings && is_duck_mapping(obj) {
+        return duck_mapping_to_object(obj, options, default);
+    }
+
+    match (depythonize(obj), default) {
+        (Ok(value), _) => Ok(value),
+        (Err(_), Some(default)) => {
+            let substitute = default.call1((obj,)).map_err(|e| ToonError::PythonError(e.to_string()))?;
+            // Handing the object back would recurse forever
+            if substitute.is(obj) {
+                return Err(ToonError::PythonError(format!(
+                    "default returned the unsupported object {} unchanged",
+                    obj
+                )));
+            }
+            python_to_value_with(&substitute, options, Some(default))
+        }
+        (Err(e), None) => Err(ToonError::PythonError(e.to_string())),
+    }
+}
+
+/// Whether `obj` has the `keys()` and `__getitem__` of a mapping. Strings
+/// and other sequences lack `keys`, so they never match.
+fn is_duck_mapping(obj: &Bound<'_, PyAny>) -> bool {
+    obj.hasattr("keys").unwrap_or(false) && obj.hasattr("__getitem__").unwrap_or(false)
+}
+
+/// Build an object from a mapping-like `obj`, in the order of its `keys()`
+fn duck_mapping_to_object(
+    obj: &Bound<'_, PyAny>,
+    options: &EncodeOptions,
+    default: Option<&Bound<'_, PyAny>>,
+) -> Result<Value, ToonError> {
+    let py_err = |e: PyErr| ToonError::PythonError(e.to_string());
+    let mut map = serde_json::Map::new();
+    for key in obj.call_method0("keys").and_then(|keys| keys.try_iter()).map_err(py_err)? {
+        let key = key.map_err(py_err)?;
+        let val = obj.get_item(&key).map_err(py_err)?;
+        let key: String = key
+            .extract()
+            .map_err(|_| ToonError::PythonError(format!("Mapping key {} is not a string", key)))?;
+        map.insert(key, python_to_value_with(&val, options, default)?);
+    }
+    Ok(Value::Object(map))
+}
+
+/// Whether `list` is non-empty and holds only `(str, value)` 2-tuples
+fn is_pair_list(list: &Bound<'_, PyList>) -> bool {
+    !list.is_empty()
+        && list.iter().all(|item| {
+            item.downcast::<PyTuple>()
+                .is_ok_and(|pair| pair.len() == 2 && pair.get_item(0).is_ok_and(|key| key.downcast::<PyString>().is_ok()))
+        })
+}
+
+/// Build an object from `(key, value)` pairs in list order. A repeated key
+/// is an error in strict mode, otherwise the last pair wins.
+fn pairs_to_object(
+    list: &Bound<'_, PyList>,
+    options: &EncodeOptions,
+    default: Option<&Bound<'_, PyAny>>,
+) -> Result<Value, ToonError> {
+    let mut map = serde_json::Map::new();
+    for item in list.iter() {
+        let (key, val): (String, Bound<'_, PyAny>) =
+            item.extract().map_err(|e| ToonError::PythonError(e.to_string()))?;
+        if options.strict && map.contains_key(&key) {
+            return Err(ToonError::EncodingError(format!("Duplicate key '{}' in pairs", key)));
+        }
+        map.insert(key, python_to_value_with(&val, options, default)?);
+    }
+    Ok(Value::Object(map))
+}
+
+/// Path of the first NaN or infinite float in Python data, walking dicts,
+/// lists and tuples without converting anything
+fn first_non_finite(obj: &Bound<'_, PyAny>, path: &str) -> PyResult<Option<String>> {
+    if let Ok(f) = obj.downcast::<PyFloat>() {
+        return Ok((!f.value().is_finite()).then(|| path.to_string()));
+    }
+    if let Ok(dict) = obj.downcast::<PyDict>() {
+        for (key, val) in dict.iter() {
+            if let Some(found) = first_non_finite(&val, &key_path(path, &key.str()?.to_cow()?))? {
+                return Ok(Some(found));
+            }
+        }
+    } else if obj.downcast::<PyList>().is_ok() || obj.downcast::<PyTuple>().is_ok() {
+        for (i, item) in obj.try_iter()?.enumerate() {
+            if let Some(found) = first_non_finite(&item?, &format!("{}[{}]", path, i))? {
+                return Ok(Some(found));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Convert a decoded value to a Python object. `b64:` / `hex:` tokens
+/// become `bytes` when `decode_bytes_prefix` is set.
+fn value_to_python(py: Python, value: &Value, options: &DecodeOptions) -> PyResult<PyObject> {
+    match value {
+        Value::Null => Ok(py.None()),
+        Value::Bool(b) => b.into_py_any(py),
+        Value::Number(n) => number_to_python(py, n),
+        Value::String(s) if options.decode_bytes_prefix => match decode_bytes(s)? {
+            Some(bytes) => Ok(PyBytes::new(py, &bytes).into_any().unbind()),
+            None => s.into_py_any(py),
+        },
+        Value::String(s) => s.into_py_any(py),
+        Value::Array(arr) => {
+            let items = arr
+                .iter()
+                .map(|val| value_to_python(py, val, options))
+                .collect::<PyResult<Vec<_>>>()?;
+            Ok(PyList::new(py, items)?.into_any().unbind())
+        }
+        Value::Object(obj) if options.tuple_marker && tuple_items(obj).is_some() => {
+            let items = tuple_items(obj)
+                .unwrap()
+                .iter()
+                .map(|val| value_to_python(py, val, options))
+                .collect::<PyResult<Vec<_>>>()?;
+            Ok(PyTuple::new(py, items)?.into_any().unbind())
+        }
+        Value::Object(obj) => {
+            let dict = PyDict::new(py);
+            for (key, val) in obj {
+                dict.set_item(key, value_to_python(py, val, options)?)?;
+            }
+            Ok(dict.into_any().unbind())
+        }
+    }
+}
+
+/// Convert a number to a Python int or float. With the `arbitrary_precision`
+/// feature numbers keep their text, so integers too big for 64 bits stay exact.
+fn number_to_python(py: Python, n: &serde_json::Number) -> PyResult<PyObject> {
+    if let Some(i) = n.as_i64() {
+        i.into_py_any(py)
+    } else if let Some(u) = n.as_u64() {
+        u.into_py_any(py)
+    } else if n.is_f64() {
+        n.as_f64().unwrap_or(f64::NAN).into_py_any(py)
+    } else {
+        let int_type = py.get_type::<PyInt>();
+        Ok(int_type.call1((n.to_string(),))?.unbind())
+    }
+}
+
+/// Type name used in typed tabular headers
+fn column_type(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::Null => Some("null"),
+        Value::Bool(_) => Some("bool"),
+        Value::Number(n) if n.is_f64() => Some("float"),
+        Value::Number(_) => Some("int"),
+        Value::String(_) => Some("str"),
+        _ => None,
+    }
+}
+
+/// Widen a column's type to fit `value`: nulls fit any type, ints and floats
+/// mix to float, and any other mix leaves the column untyped (`None`)
+fn widen_column_type(ty: Option<&'static str>, value: &Value) -> Option<&'static str> {
+    match (ty?, column_type(value)?) {
+        ("null", other) | (other, "null") => Some(other),
+        (a, b) if a == b => Some(a),
+        ("int", "float") | ("float", "int") => Some("float"),
+        _ => None,
+    }
+}
+
+/// Types of a typed header's columns, widened over every row. Redacted and
+/// JSON passthrough columns hold text that reads back as something else, so
+/// they stay untyped.
+fn column_types<'a>(
+    rows: impl IntoIterator<Item = &'a serde_json::Map<String, Value>>,
+    keys: &[String],
+    options: &EncodeOptions,
+) -> Vec<Option<&'static str>> {
+    let mut types: Vec<_> = keys
+        .iter()
+        .map(|key| match is_redacted(key, options) || options.json_passthrough_keys.contains(key) {
+            true => None,
+            false => Some("null"),
+        })
+        .collect();
+    for row in rows {
+        for (ty, key) in types.iter_mut().zip(keys) {
+            *ty = widen_column_type(*ty, row.get(key).unwrap_or(&Value::Null));
+        }
+    }
+    types
+}
+
+/// Encode tabular rows, concurrently when `options.parallel` is set
+#[cfg(feature = "rayon")]
+fn encode_rows<F>(arr: &[Value], options: &EncodeOptions, encode_row: F) -> Result<Vec<String>, ToonError>
+where
+    F: Fn(usize, &Value) -> Result<String, ToonError> + Sync + Send,
+{
+    use rayon::prelude::*;
+
+    if options.parallel {
+        arr.par_iter().enumerate().map(|(i, v)| encode_row(i, v)).collect()
+    } else {
+        arr.iter().enumerate().map(|(i, v)| encode_row(i, v)).collect()
+    }
+}
+
+/// Encode tabular rows (serially; `options.parallel` needs the `rayon` feature)
+#[cfg(not(feature = "rayon"))]
+fn encode_rows<F>(arr: &[Value], _options: &EncodeOptions, encode_row: F) -> Result<Vec<String>, ToonError>
+where
+    F: Fn(usize, &Value) -> Result<String, ToonError>,
+{
+    arr.iter().enumerate().map(|(i, v)| encode_row(i, v)).collect()
+}
+
+/// Shortest text that parses back to exactly `f`: plain digits, or an
+/// exponent form like `1e-10` when that is shorter
+fn compact_float(f: f64) -> String {
+    let plain = f.to_string();
+    let exponent = exponent_float(f);
+    if exponent.len() < plain.len() {
+        exponent
+    } else {
+        plain
+    }
+}
+
+/// Shortest round-trip form from `ryu`, which may or may not use an exponent
+#[cfg(feature = "ryu")]
+fn exponent_float(f: f64) -> String {
+    let mut buffer = ryu::Buffer::new();
+    let s = buffer.format_finite(f);
+    s.strip_suffix(".0").unwrap_or(s).to_string()
+}
+
+/// Shortest round-trip exponent form (`ryu` feature makes this faster)
+#[cfg(not(feature = "ryu"))]
+fn exponent_float(f: f64) -> String {
+    format!("{:e}", f)
+}
+
+/// Fail once the output grows past `options.max_bytes`
+fn check_budget(len: usize, options: &EncodeOptions) -> Result<(), ToonError> {
+    match options.max_bytes {
+        Some(max_bytes) if len > max_bytes => Err(ToonError::EncodingError(format!(
+            "output exceeds max_bytes ({})",
+            max_bytes
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Append the value path (e.g. `users[3].name`) to an error message
+fn at_path(message: String, path: &str) -> String {
+    if path.is_empty() {
+        message
+    } else {
+        format!("{} at {}", message, path)
+    }
+}
+
+/// Path of an object field below `path`
+fn key_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Length-marker suffix for the configured delimiter. Commas are marked too
+/// (`[3,]`), as the README's examples and the original encoder tests show,
+/// so every header states its own delimiter.
+fn delimiter_marker(options: &EncodeOptions, path: &str) -> Result<&'static str, ToonError> {
+    match options.delimiter.as_str() {
+        "," => Ok(","),
+        "\t" => Ok("\t"),
+        "|" => Ok("|"),
+        other => Err(ToonError::InvalidDelimiter(at_path(other.to_string(), path))),
+    }
+}
+
+/// The configured list marker, rejected if it could be read as a key or value
+fn list_marker<'o>(options: &'o EncodeOptions, path: &str) -> Result<&'o str, ToonError> {
+    if is_ambiguous_list_marker(&options.list_marker) {
+        return Err(ToonError::EncodingError(at_path(
+            format!("List marker '{}' could be read as a key or value", options.list_marker),
+            path,
+        )));
+    }
+    Ok(&options.list_marker)
+}
+
+/// Whether a list marker is empty or holds characters that start or
+/// structure keys and values
+fn is_ambiguous_list_marker(marker: &str) -> bool {
+    let bullet = marker.trim_end_matches(' ');
+    bullet.is_empty()
+        || bullet.chars().any(|c| {
+            c.is_alphanumeric()
+                || c.is_whitespace()
+                || matches!(c, '"' | '\\' | ':' | '#' | '=' | '[' | ']' | '{' | '}' | '(' | ')' | ',' | '|')
+        })
+}
+
+/// Rows of a table sorted stably by `field`. Null placeholder rows go last.
+fn sort_rows(arr: &[Value], field: &str, path: &str) -> Result<Vec<Value>, ToonError> {
+    if arr.iter().any(|row| row.as_object().is_some_and(|obj| !obj.contains_key(field))) {
+        return Err(ToonError::EncodingError(at_path(
+            format!("Cannot sort rows by '{}': not every row has it", field),
+            path,
+        )));
+    }
+    let mut rows = arr.to_vec();
+    rows.sort_by(|a, b| match (a.get(field), b.get(field)) {
+        (Some(a), Some(b)) => compare_cells(a, b),
+        (a, b) => a.is_none().cmp(&b.is_none()),
+    });
+    Ok(rows)
+}
+
+/// Order of primitive cells: null, then booleans, numbers and strings, each
+/// compared by value
+fn compare_cells(a: &Value, b: &Value) -> std::cmp::Ordering {
+    let rank = |v: &Value| match v {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        _ => 4,
+    };
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => match (a.as_i64(), b.as_i64()) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(std::cmp::Ordering::Equal),
+        },
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Row width of a non-empty array of equal-length, non-empty arrays of numbers
+fn matrix_width(arr: &[Value]) -> Option<usize> {
+    let width = arr.first()?.as_array()?.len();
+    let rectangular = width > 0
+        && arr.iter().all(|row| {
+            row.as_array()
+                .is_some_and(|cells| cells.len() == width && cells.iter().all(Value::is_number))
+        });
+    rectangular.then_some(width)
+}
+
+/// Tabular header `[N,]{key1,key2,...}:`, with `key:type` for each column
+/// given a type in `types` (empty unless `options.typed_header` is set)
+fn tabular_header(
+    len: usize,
+    keys: &[String],
+    types: &[Option<&'static str>],
+    options: &EncodeOptions,
+    path: &str,
+) -> Result<String, ToonError> {
+    let mut header = length_bracket(len, options, path)?;
+
+    header.push('{');
+    for (i, key) in keys.iter().enumerate() {
+        if i > 0 {
+            header.push_str(&options.delimiter);
+        }
+        header.push_str(&format_key(output_key(key, options), options));
+        if let Some(ty) = types.get(i).copied().flatten() {
+            header.push(':');
+            header.push_str(ty);
+        }
+    }
+    header.push_str("}:");
+    header.push_str(&length_comment(len, options));
+
+    Ok(header)
+}
+
+/// Length bracket of an array header: `[3,]` for inline markers, else `[]`
+fn length_bracket(len: usize, options: &EncodeOptions, path: &str) -> Result<String, ToonError> {
+    let delim_marker = delimiter_marker(options, path)?;
+    match length_marker_style(options) {
+        LengthMarkerStyle::Inline => Ok(format!("[{}{delim_marker}]", len)),
+        _ => Ok("[]".to_string()),
+    }
+}
+
+/// Comment closing an array's header line under `LengthMarkerStyle::TrailingComment`
+fn length_comment(len: usize, options: &EncodeOptions) -> String {
+    match length_marker_style(options) {
+        LengthMarkerStyle::TrailingComment => format!("  # {} items", len),
+        _ => String::new(),
+    }
+}
+
+fn length_marker_style(options: &EncodeOptions) -> LengthMarkerStyle {
+    if options.use_length_markers {
+        options.length_marker_style
+    } else {
+        LengthMarkerStyle::Omitted
+    }
+}
+
+/// Comment line counting a table's rows and summing each numeric column.
+/// Null cells are skipped; a column with any other non-number is left out.
+fn tabular_footer(arr: &[Value], keys: &[String], options: &EncodeOptions) -> Result<String, ToonError> {
+    let mut footer = format!("# count={}", arr.len());
+    for key in keys.iter().filter(|key| !is_redacted(key, options)) {
+        let numbers: Option<Vec<&serde_json::Number>> = arr
+            .iter()
+            .filter_map(|row| row.get(key.as_str()).filter(|cell| !cell.is_null()))
+            .map(|cell| match cell {
+                Value::Number(n) => Some(n),
+                _ => None,
+            })
+            .collect();
+        let Some(numbers) = numbers.filter(|numbers| !numbers.is_empty()) else {
+            continue;
+        };
+
+        // Integer columns stay exact unless the sum overflows
+        let sum = numbers
+            .iter()
+            .try_fold(0i64, |sum, n| sum.checked_add(n.as_i64()?))
+            .map(Value::from)
+            .unwrap_or_else(|| Value::from(numbers.iter().filter_map(|n| n.as_f64()).sum::<f64>()));
+        let sum = encode_value_at(&sum, 0, options, "", 0)?;
+        footer.push_str(&format!(" sum({})={}", output_key(key, options), sum));
+    }
+    Ok(footer)
+}
+
+/// Cells of one tabular row, without indentation. `None` is a null
+/// placeholder row, written as a line of empty cells.
+fn encode_tabular_row(
+    obj: Option<&serde_json::Map<String, Value>>,
+    keys: &[String],
+    options: &EncodeOptions,
+    row_path: &str,
+) -> Result<String, ToonError> {
+    let Some(obj) = obj else {
+        return Ok(options.delimiter.repeat(keys.len() - 1));
+    };
+
+    let mut row = String::new();
+    for (i, key) in keys.iter().enumerate() {
+        if i > 0 {
+            row.push_str(&options.delimiter);
+        }
+        if is_redacted(key, options) {
+            row.push_str(REDACTED);
+            continue;
+        }
+        let json;
+        let val = match &obj[key] {
+            val if options.json_passthrough_keys.contains(key) => {
+                json = Value::String(val.to_string());
+                &json
+            }
+            val => val,
+        };
+        let val_str = encode_value_at(val, 0, options, &key_path(row_path, key), 0)?;
+        row.push_str(&val_str);
+    }
+    Ok(row)
+}
+
+/// Encode a value to TOON format
+fn encode_value(
+    value: &Value,
+    indent_level: usize,
+    options: &EncodeOptions,
+) -> Result<String, ToonError> {
+    encode_value_at(value, indent_level, options, "", 0)
+}
+
+/// Encode a value found at `path`, which is reported in errors. `written`
+/// counts the output bytes before this value, so `max_bytes` is checked
+/// against the running total of the document rather than this value alone.
+fn encode_value_at(
+    value: &Value,
+    indent_level: usize,
+    options: &EncodeOptions,
+    path: &str,
+    written: usize,
+) -> Result<String, ToonError> {
+    let indent = " ".repeat(indent_level * options.indent);
+
+    match value {
+        Value::Null => Ok(options.null_repr.clone()),
+        Value::Bool(true) => Ok(options.true_repr.clone()),
+        Value::Bool(false) => Ok(options.false_repr.clone()),
+        Value::Number(n) if options.preserve_number_text => Ok(n.to_string()),
+        Value::Number(n) => {
+            // Normalize numbers: no exponent, no trailing zeros. Formatting is
+            // locale-independent: always a `.` decimal point, never digit grouping.
+            if let Some(i) = n.as_i64() {
+                Ok(i.to_string())
+            } else if let Some(u) = n.as_u64() {
+                Ok(u.to_string())
+            } else if options.strict && is_integer_token(&n.to_string()) {
+                Err(ToonError::EncodingError(at_path(
+                    format!("Integer {} does not fit in 64 bits and would lose precision", n),
+                    path,
+                )))
+            } else if let Some(f) = n.as_f64().filter(|_| options.compact_numbers) {
+                Ok(compact_float(f))
+            } else if let Some(f) = n.as_f64() {
+                let s = format!("{}", f);
+                // Remove trailing zeros after decimal point
+                if s.contains('.') {
+                    let trimmed = s.trim_end_matches('0').trim_end_matches('.');
+                    Ok(trimmed.to_string())
+                } else {
+                    Ok(s)
+                }
+            } else {
+                Ok(n.to_string())
+            }
+        }
+        Value::String(s) => {
+            if let Some(max) = options.max_string_len.filter(|_| options.strict) {
+                let len = s.chars().count();
+                if len > max {
+                    return Err(ToonError::EncodingError(at_path(
+                        format!("String of {} characters exceeds max_string_len {}", len, max),
+                        path,
+                    )));
+                }
+            }
+            if options.always_quote_strings {
+                Ok(format!("\"{}\"", escape_string(s)))
+            } else {
+                Ok(quote_if_needed(s, options))
+            }
+        }
+        Value::Array(arr) => {
+            if arr.is_empty() {
+                let bracket = length_bracket(0, options, path)?;
+                return Ok(format!("{}{}:{}", indent, bracket, length_comment(0, options)));
+            }
+
+            // Check if it's a uniform object array (tabular format)
+            if let Some(keys) = is_uniform_object_array(arr, options) {
+                if options.strict {
+                    check_key_collisions(keys.iter().map(|k| output_key(k, options).trim().to_string()), path)?;
+                }
+
+                let sorted;
+                let arr = match &options.sort_rows_by {
+                    Some(field) => {
+                        sorted = sort_rows(arr, field, path)?;
+                        &sorted
+                    }
+                    None => arr,
+                };
+
+                let types = match options.typed_header {
+                    true => column_types(arr.iter().filter_map(Value::as_object), &keys, options),
+                    false => Vec::new(),
+                };
+                let mut result = indent.clone();
+                result.push_str(&tabular_header(arr.len(), &keys, &types, options, path)?);
+
+                // Data rows
+                let encode_row_cells = |row_index: usize, obj_val: &Value| -> Result<String, ToonError> {
+                    let row_path = format!("{}[{}]", path, row_index);
+                    encode_tabular_row(obj_val.as_object(), &keys, options, &row_path)
+                };
+
+                // Running byte count so an oversized table aborts mid-way, even in parallel
+                let row_overhead = indent.len() + options.indent + 1;
+                let written = AtomicUsize::new(written + result.len());
+
+                let encode_row = |row_index: usize, obj_val: &Value| -> Result<String, ToonError> {
+                    let row = encode_row_cells(row_index, obj_val)?;
+                    let size = row.len() + row_overhead;
+                    check_budget(written.fetch_add(size, Ordering::Relaxed) + size, options)?;
+                    Ok(row)
+                };
+
+                // Rows aren't trimmed: a tab-delimited null row is all tabs
+                for row in encode_rows(arr, options, encode_row)? {
+                    result.push('\n');
+                    result.push_str(&indent);
+                    result.push_str(&" ".repeat(options.indent));
+                    result.push_str(&row);
+                }
+
+                if options.tabular_footer {
+                    result.push('\n');
+                    result.push_str(&indent);
+                    result.push_str(&" ".repeat(options.indent));
+                    result.push_str(&tabular_footer(arr, &keys, options)?);
+                }
+
+                return Ok(result);
+            }
+
+            // Numeric matrix: [rows,][cols,]: header, one delimited line per row
+            if let Some(width) = matrix_width(arr).filter(|_| options.matrix_mode) {
+                let mut result = indent.clone();
+                result.push_str(&length_bracket(arr.len(), options, path)?);
+                result.push_str(&length_bracket(width, options, path)?);
+                result.push(':');
+                result.push_str(&length_comment(arr.len(), options));
+
+                let row_indent = " ".repeat((indent_level + 1) * options.indent);
+                for (row_index, row) in arr.iter().enumerate() {
+                    result.push('\n');
+                    result.push_str(&row_indent);
+                    for (i, cell) in row.as_array().unwrap().iter().enumerate() {
+                        if i > 0 {
+                            result.push_str(&options.delimiter);
+                        }
+                        let cell_path = format!("{}[{}][{}]", path, row_index, i);
+                        result.push_str(&encode_value_at(cell, 0, options, &cell_path, written + result.len())?);
+                    }
+                    check_budget(written + result.len(), options)?;
+                }
+
+                return Ok(result);
+            }
+
+            // Check if all elements are primitives (inline array)
+            let all_primitives = arr.iter().all(|v| !v.is_object() && !v.is_array());
+
+            // Long primitive arrays: [N,]{i,value}: one `index,item` row each
+            if all_primitives && options.index_large_arrays.is_some_and(|max| arr.len() > max) {
+                let mut result = indent.clone();
+                result.push_str(&length_bracket(arr.len(), options, path)?);
+                result.push_str(&format!("{{i{}value}}:", options.delimiter));
+                result.push_str(&length_comment(arr.len(), options));
+
+                let row_indent = " ".repeat((indent_level + 1) * options.indent);
+                for (i, val) in arr.iter().enumerate() {
+                    let item_path = format!("{}[{}]", path, i);
+                    result.push('\n');
+                    result.push_str(&format!("{}{}{}", row_indent, i, options.delimiter));
+                    result.push_str(&encode_value_at(val, 0, options, &item_path, written + result.len())?);
+                    check_budget(written + result.len(), options)?;
+                }
+
+                return Ok(result);
+            }
+
+            if all_primitives {
+                // Inline format: [N,]: val1,val2,val3
+                let mut result = indent.clone();
+                result.push_str(&length_bracket(arr.len(), options, path)?);
+                result.push_str(": ");
+
+                for (i, val) in arr.iter().enumerate() {
+                    if i > 0 {
+                        result.push_str(&options.delimiter);
+                    }
+                    let item_path = format!("{}[{}]", path, i);
+                    result.push_str(&encode_value_at(val, 0, options, &item_path, written + result.len())?);
+                    check_budget(written + result.len(), options)?;
+                }
+                result.push_str(&length_comment(arr.len(), options));
+
+                return Ok(result);
+            }
+
+            // Mixed/nested array (expanded format with -)
+            let marker = list_marker(options, path)?;
+            let mut result = indent.clone();
+            result.push_str(&length_bracket(arr.len(), options, path)?);
+            result.push(':');
+            result.push_str(&length_comment(arr.len(), options));
+            result.push('\n');
+
+            // Items sit one level below the header. Object items are encoded two
+            // levels down so their fields line up under the first one, which
+            // shares the line with the `- ` marker.
+            let item_indent = " ".repeat((indent_level + 1) * options.indent);
+            for (item_index, val) in arr.iter().enumerate() {
+                let item_path = format!("{}[{}]", path, item_index);
+                let item_level = if val.is_object() {
+                    indent_level + 2
+                } else {
+                    indent_level + 1
+                };
+                let nested = encode_value_at(val, item_level, options, &item_path, written + result.len())?;
+                let nested_indent = " ".repeat(item_level * options.indent);
+
+                let mut lines = nested.lines().peekable();
+
+                // Comments on the item's first key go above the `- ` marker
+                while let Some(comment) = lines.next_if(|line| line.trim_start().starts_with('#')) {
+                    result.push_str(&item_indent);
+                    result.push_str(comment.trim_start());
+                    result.push('\n');
+                }
+
+                let first = lines.next().unwrap_or("");
+                let first = first.strip_prefix(nested_indent.as_str()).unwrap_or(first);
+
+                let mut item = String::new();
+                if options.index_expanded_items {
+                    item.push_str(&format!("[{}]", item_index));
+                }
+                if !first.is_empty() {
+                    if !item.is_empty() {
+                        item.push(' ');
+                    }
+                    item.push_str(first);
+                }
+
+                result.push_str(&item_indent);
+                if item.is_empty() {
+                    result.push_str(marker.trim_end());
+                } else {
+                    result.push_str(marker);
+                    result.push_str(&item);
+                }
+                result.push('\n');
+                for line in lines {
+                    result.push_str(line);
+                    result.push('\n');
+                }
+                check_budget(written + result.len(), options)?;
+            }
+
+            // Only the last newline goes, as trailing tabs may be cells
+            result.pop();
+            Ok(result)
+        }
+        Value::Object(obj) if options.tuple_marker && tuple_items(obj).is_some() => {
+            // Tuple: (N,): val1,val2
+            let items = tuple_items(obj).unwrap();
+            let mut result = format!("{}({}{}):", indent, items.len(), delimiter_marker(options, path)?);
+            for (i, val) in items.iter().enumerate() {
+                result.push_str(if i > 0 { &options.delimiter } else { " " });
+                let item_path = format!("{}[{}]", path, i);
+                result.push_str(&encode_value_at(val, 0, options, &item_path, written + result.len())?);
+            }
+            Ok(result)
+        }
+        Value::Object(obj) => {
+            if obj.is_empty() {
+                return Ok(String::new());
+            }
+
+            let mut result = String::new();
+
+            // Last key of each lowercased spelling, for case-insensitive deduplication
+            let mut last_by_lower = std::collections::HashMap::new();
+            if options.case_insensitive_keys {
+                for key in obj.keys() {
+                    if let Some(prev_key) = last_by_lower.insert(key.to_lowercase(), key) {
+                        if options.strict {
+                            return Err(ToonError::EncodingError(at_path(
+                                format!("Keys '{}' and '{}' collide case-insensitively", prev_key, key),
+                                path,
+                            )));
+                        }
+                    }
+                }
+            }
+
+            if options.strict && !options.rename_keys.is_empty() {
+                check_key_collisions(obj.keys().map(|key| output_key(key, options).to_string()), path)?;
+            }
+
+            let entries = ordered_keys(obj, options)
+                .into_iter()
+                .filter(|key| !options.case_insensitive_keys || last_by_lower[&key.to_lowercase()] == *key)
+                .map(|key| (key, &obj[key]))
+                .filter(|(_, v)| !is_skipped(v, options));
+            for (i, (key, val)) in entries.enumerate() {
+                if i > 0 {
+                    result.push('\n');
+                    if options.section_spacing && indent_level == 0 && (val.is_object() || val.is_array()) {
+                        result.push('\n');
+                    }
+                }
+
+                if let Some(comment) = options.comments.get(&key_path(path, key)) {
+                    for line in comment.lines() {
+                        result.push_str(format!("{}# {}", indent, line).trim_end());
+                        result.push('\n');
+                    }
+                }
+
+                result.push_str(&indent);
+
+                let key_str = format_key(output_key(key, options), options);
+
+                if is_redacted(key, options) {
+                    result.push_str(&format!("{}: {}", key_str, REDACTED));
+                } else if options.json_passthrough_keys.contains(key) {
+                    let json = Value::String(val.to_string());
+                    let encoded = encode_value_at(&json, 0, options, &key_path(path, key), written + result.len())?;
+                    result.push_str(&format!("{}: {}", key_str, encoded));
+                } else if val.is_object() || val.is_array() {
+                    // Nested values come back already indented for their level
+                    let nested = encode_value_at(val, indent_level + 1, options, &key_path(path, key), written + result.len())?;
+                    result.push_str(&format!("{}:", key_str));
+                    if !nested.is_empty() {
+                        result.push('\n');
+                        result.push_str(&nested);
+                    }
+                } else {
+                    let encoded = encode_value_at(val, 0, options, &key_path(path, key), written + result.len())?;
+                    result.push_str(&format!("{}: {}", key_str, encoded));
+                }
+                check_budget(written + result.len(), options)?;
+            }
+
+            Ok(result)
+        }
+    }
+}
+
+/// Encode a truncated TOON preview for logs, keeping at most `preview.max_items`
+/// items per array and `preview.max_chars` characters per string
+pub fn encode_preview(value: &Value, preview: PreviewConfig, options: &EncodeOptions) -> Result<String, ToonError> {
+    let mut options = options.clone();
+    options.preview = Some(preview);
+    encode_document(value, &options)
+}
+
+/// Encode a complete TOON document, writing each comment above the key at its path
+pub fn encode_with_comments(value: &Value, comments: &Comments, options: &EncodeOptions) -> Result<String, ToonError> {
+    let mut options = options.clone();
+    options.comments = comments.clone();
+    encode_document(value, &options)
+}
+
+/// Check if `value` is an object whose only key is `key`
+fn is_wrapped_root(value: &Value, key: &str) -> bool {
+    value.as_object().is_some_and(|obj| obj.len() == 1 && obj.contains_key(key))
+}
+
+/// `options` with every setting that loses data, or that the default
+/// decoder can't read back, turned off for `lossless`
+fn lossless_options(options: &EncodeOptions) -> EncodeOptions {
+    let defaults = EncodeOptions::default();
+    EncodeOptions {
+        strict: true,
+        // Numbers keep their exact text: no trailing-zero trimming or exponent rewriting
+        preserve_number_text: true,
+        compact_numbers: false,
+        scalar_sentinel: true,
+        skip_nulls: false,
+        skip_empty: false,
+        case_insensitive_keys: false,
+        true_repr: defaults.true_repr,
+        false_repr: defaults.false_repr,
+        null_repr: defaults.null_repr,
+        root_key: None,
+        preview: None,
+        flatten_paths: false,
+        rename_keys: defaults.rename_keys,
+        redact_keys: defaults.redact_keys,
+        sort_rows_by: None,
+        index_large_arrays: None,
+        list_marker: defaults.list_marker,
+        tabular_footer: false,
+        tabular_allow_null_rows: false,
+        json_passthrough_keys: defaults.json_passthrough_keys,
+        // Length comments, tuple headers and comment lines need decode options to read back
+        length_marker_style: LengthMarkerStyle::Inline,
+        tuple_marker: false,
+        comments: Comments::new(),
+        // Without indentation nested objects would read back as siblings
+        indent: if options.indent == 0 { defaults.indent } else { options.indent },
+        ..options.clone()
+    }
+}
+
+/// Encode a complete TOON document
+pub fn encode_document(value: &Value, options: &EncodeOptions) -> Result<String, ToonError> {
+    let lossless;
+    let options = if options.lossless {
+        lossless = lossless_options(options);
+        &lossless
+    } else {
+        options
+    };
+
+    let wrapped;
+    let value = match &options.root_key {
+        Some(key) if !is_wrapped_root(value, key) => {
+            wrapped = Value::Object(serde_json::Map::from_iter([(key.clone(), value.clone())]));
+            &wrapped
+        }
+        _ => value,
+    };
+
+    let flattened;
+    let value = if options.flatten_paths {
+        flattened = flatten_object_tree(value)?;
+        &flattened
+    } else {
+        value
+    };
+
+    let truncated;
+    let value = match options.preview {
+        Some(preview) => {
+            truncated = preview_value(value, preview);
+            &truncated
+        }
+        None => value,
+    };
+
+    if options.strict && options.indent == 0 && needs_indent(value, options) {
+        return Err(ToonError::EncodingError(
+            "indent 0 can't express nesting; use indent > 0, or flatten_paths for nested objects".to_string(),
+        ));
+    }
+
+    let encoded = encode_value(value, 0, options)?;
+
+    let encoded = if options.scalar_sentinel && !value.is_object() && !value.is_array() {
+        format!("= {}", encoded)
+    } else {
+        encoded
+    };
+
+    let encoded = if options.emit_version {
+        format!("{}{}\n{}", VERSION_PREFIX, TOON_VERSION, encoded)
+    } else {
+        encoded
+    };
+
+    check_budget(encoded.len(), options)?;
+    Ok(encoded)
+}
+
+/// Check the column list of a stream of rows: non-empty, and in strict mode
+/// with no two columns written under the same name
+fn check_row_fields(fields: &[String], options: &EncodeOptions) -> Result<(), ToonError> {
+    if fields.is_empty() {
+        return Err(ToonError::EncodingError("encode_rows needs at least one field".to_string()));
+    }
+    if options.strict {
+        check_key_collisions(fields.iter().map(|field| output_key(field, options).to_string()), "")?;
+    }
+    Ok(())
+}
+
+/// Write `rows` to `writer` as a top-level tabular array with the given
+/// columns, one row at a time. Each row holds a primitive value for every
+/// field. The header states `len` rows when it is known up front, and it is
+/// an error for `rows` to yield a different number; otherwise the header is
+/// written as `[]`. A typed header can only see the first row, so a column
+/// that is null there stays untyped, and a later row that doesn't fit a
+/// column's type is an error.
+pub fn encode_to_writer<W: Write>(
+    rows: impl IntoIterator<Item = Result<serde_json::Map<String, Value>, ToonError>>,
+    len: Option<usize>,
+    fields: &[String],
+    options: &EncodeOptions,
+    writer: &mut W,
+) -> Result<(), ToonError> {
+    check_row_fields(fields, options)?;
+
+    let mut rows = rows.into_iter();
+    let first = rows.next().transpose()?;
+    let types: Vec<_> = match options.typed_header {
+        true => column_types(&first, fields, options)
+            .into_iter()
+            .map(|ty| ty.filter(|ty| *ty != "null"))
+            .collect(),
+        false => Vec::new(),
+    };
+
+    let unmarked;
+    let header_options = match len {
+        Some(_) => options,
+        None => {
+            unmarked = EncodeOptions { use_length_markers: false, ..options.clone() };
+            &unmarked
+        }
+    };
+    let mut header = if options.emit_version {
+        format!("{}{}\n", VERSION_PREFIX, TOON_VERSION)
+    } else {
+        String::new()
+    };
+    header.push_str(&tabular_header(len.unwrap_or(0), fields, &types, header_options, "")?);
+    check_budget(header.len(), options)?;
+    writer.write_all(header.as_bytes())?;
+
+    let row_indent = " ".repeat(options.indent);
+    let mut written = header.len();
+    let mut count = 0;
+    for row in first.into_iter().map(Ok).chain(rows) {
+        let row_path = format!("[{}]", count);
+        let row = row?;
+        for (field, ty) in fields.iter().zip(&types) {
+            let (Some(ty), Some(value)) = (*ty, row.get(field)) else {
+                continue;
+            };
+            if widen_column_type(Some(ty), value) != Some(ty) {
+                return Err(ToonError::EncodingError(at_path(
+                    format!("Field '{}' doesn't fit header type {}: {}", field, ty, value),
+                    &row_path,
+                )));
+            }
+        }
+        let line = format!("\n{}{}", row_indent, encode_tabular_row(Some(&row), fields, options, &row_path)?);
+        written += line.len();
+        check_budget(written, options)?;
+        writer.write_all(line.as_bytes())?;
+        count += 1;
+    }
+
+    match len {
+        Some(len) if len != count => Err(ToonError::EncodingError(format!(
+            "Header declares {} rows, but {} were written",
+            len, count
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Read the four hex digits of a `\uXXXX` escape
+fn read_hex4(chars: &mut std::str::Chars) -> Result<u32, ToonError> {
+    let hex: String = chars.take(4).collect();
+    if hex.len() != 4 {
+        return Err(ToonError::DecodingError(format!(
+            "Truncated unicode escape: \\u{}",
+            hex
+        )));
+    }
+    u32::from_str_radix(&hex, 16)
+        .map_err(|_| ToonError::DecodingError(format!("Invalid unicode escape: \\u{}", hex)))
+}
+
+/// Decode a `\uXXXX` escape (after the `u`), combining surrogate pairs
+fn unescape_unicode(chars: &mut std::str::Chars) -> Result<char, ToonError> {
+    let code = read_hex4(chars)?;
+
+    let code = match code {
+        0xD800..=0xDBFF => {
+            if chars.next() != Some('\\') || chars.next() != Some('u') {
+                return Err(ToonError::DecodingError(format!(
+                    "Unpaired high surrogate: \\u{:04X}",
+                    code
+                )));
+            }
+            let low = read_hex4(chars)?;
+            if !(0xDC00..=0xDFFF).contains(&low) {
+                return Err(ToonError::DecodingError(format!(
+                    "Invalid low surrogate: \\u{:04X}",
+                    low
+                )));
+            }
+            0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00)
+        }
+        0xDC00..=0xDFFF => {
+            return Err(ToonError::DecodingError(format!(
+                "Unpaired low surrogate: \\u{:04X}",
+                code
+            )))
+        }
+        _ => code,
+    };
+
+    char::from_u32(code)
+        .ok_or_else(|| ToonError::DecodingError(format!("Invalid code point: {:X}", code)))
+}
+
+/// Unescape a TOON string
+fn unescape_string(s: &str) -> Result<String, ToonError> {
+    let mut result = String::new();
+    let mut chars = s.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            match chars.next() {
+                Some('\\') => result.push('\\'),
+                Some('"') => result.push('"'),
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some('t') => result.push('\t'),
+                Some('u') => result.push(unescape_unicode(&mut chars)?),
+                Some(other) => {
+                    return Err(ToonError::DecodingError(format!(
+                        "Invalid escape sequence: \\{}",
+                        other
+                    )))
+                }
+                None => {
+                    return Err(ToonError::DecodingError(
+                        "Unterminated escape sequence".to_string(),
+                    ))
+                }
+            }
+        } else {
+            result.push(ch);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Split a line on the delimiter, ignoring delimiters inside quoted strings
+fn split_delimited<'a>(line: &'a str, delimiter: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (i, ch) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if ch == '\\' && (in_quotes || line[i + 1..].starts_with(delimiter)) {
+            // Escapes inside quotes, or an escaped delimiter outside them
+            escaped = true;
+        } else if ch == '"' {
+            in_quotes = !in_quotes;
+        } else if !in_quotes && line[i..].starts_with(delimiter) {
+            parts.push(&line[start..i]);
+            start = i + delimiter.len();
+        }
+    }
+    parts.push(&line[start..]);
+
+    parts
+}
+
+/// Find the colon separating a key (or array header) from its value,
+/// skipping colons inside quotes and typed header field lists
+fn find_key_colon(line: &str) -> Option<usize> {
+    let mut in_quotes = false;
+    let mut in_braces = false;
+    let mut escaped = false;
+
+    for (i, ch) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match ch {
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '{' if !in_quotes => in_braces = true,
+            '}' if !in_quotes => in_braces = false,
+            ':' if !in_quotes && !in_braces => return Some(i),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Check a decoded cell against a typed header column
+fn check_column_type(field: &str, expected: &str, value: &Value) -> Result<(), ToonError> {
+    let matches = match expected {
+        "null" => value.is_null(),
+        "bool" => value.is_boolean() || value.is_null(),
+        "int" => value.is_i64() || value.is_u64() || value.is_null(),
+        "float" => value.is_number() || value.is_null(),
+        "str" => value.is_string() || value.is_null(),
+        other => {
+            return Err(ToonError::DecodingError(format!(
+                "Unknown column type '{}' for field '{}'",
+                other, field
+            )))
+        }
+    };
+
+    if matches {
+        Ok(())
+    } else {
+        Err(ToonError::DecodingError(format!(
+            "Field '{}' expected {} but got {}",
+            field, expected, value
+        )))
+    }
+}
+
+/// Parse a value from a TOON string
+fn parse_value(s: &str, options: &DecodeOptions) -> Result<Value, ToonError> {
+    let s = s.trim();
+
+    if s.is_empty() {
+        return Ok(Value::String(String::new()));
+    }
+
+    // Quoted string
+    if s.starts_with('"') && s.ends_with('"') {
+        let inner = &s[1..s.len() - 1];
+        return Ok(Value::String(unescape_string(inner)?));
+    }
+
+    // Boolean, including the configured literals
+    if s == "true" || s == options.true_repr {
+        return Ok(Value::Bool(true));
+    }
+    if s == "false" || s == options.false_repr {
+        return Ok(Value::Bool(false));
+    }
+
+    // Null, including the configured literal
+    if s == "null" || s == options.null_repr {
+        return Ok(Value::Null);
+    }
+
+    // Overlong tokens are not worth parsing as numbers
+    if s.len() > options.max_number_len {
+        return Ok(Value::String(s.to_string()));
+    }
+
+    // Number, keeping its text as written when asked
+    let digits = match options.allow_underscore_numbers {
+        true => strip_digit_separators(s),
+        false => Cow::Borrowed(s),
+    };
+    if options.preserve_number_text {
+        if let Ok(n) = digits.parse::<serde_json::Number>() {
+            return Ok(Value::Number(n));
+        }
+    }
+    if let Ok(i) = digits.parse::<i64>() {
+        return Ok(Value::Number(i.into()));
+    }
+    if let Ok(u) = digits.parse::<u64>() {
+        return Ok(Value::Number(u.into()));
+    }
+    if is_integer_token(&digits) {
+        match options.on_number_overflow {
+            NumberOverflow::AsString => return Ok(Value::String(s.to_string())),
+            NumberOverflow::Error => {
+                return Err(ToonError::DecodingError(format!("Integer '{}' does not fit in 64 bits", s)))
+            }
+            NumberOverflow::AsFloat => {}
+        }
+    }
+    if let Ok(f) = digits.parse::<f64>() {
+        if let Some(n) = serde_json::Number::from_f64(f) {
+            return Ok(Value::Number(n));
+        }
+        // `inf`, `nan` and overflowing literals like `1e999` have no JSON
+        // number; lenient mode keeps the token as a string
+        if options.strict {
+            return Err(ToonError::DecodingError(format!(
+                "Number '{}' is not finite and has no JSON representation",
+                s
+            )));
+        }
+    }
+
+    // Control characters are only valid escaped inside quotes
+    if options.strict {
+        if let Some(ch) = s.chars().find(|c| c.is_control()) {
+            return Err(ToonError::DecodingError(format!(
+                "Unquoted value '{}' contains control character U+{:04X}",
+                s.escape_debug(),
+                ch as u32
+            )));
+        }
+    }
+
+    // The encoder quotes anything with whitespace, so an unquoted
+    // multi-word value is malformed
+    if s.contains(char::is_whitespace) {
+        if options.strict {
+            return Err(ToonError::DecodingError(format!(
+                "Unquoted value '{}' contains whitespace",
+                s
+            )));
+        }
+        if options.collapse_whitespace {
+            return Ok(Value::String(s.split_whitespace().collect::<Vec<_>>().join(" ")));
+        }
+    }
+
+    // Otherwise, it's a string
+    Ok(Value::String(unescape_delimiters(s).into_owned()))
+}
+
+/// Whether `s` is an optionally signed run of decimal digits
+fn is_integer_token(s: &str) -> bool {
+    let digits = s.strip_prefix(['-', '+']).unwrap_or(s);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Drop the underscores from `1_000`-style digit grouping. The token is left
+/// alone unless every underscore sits between two digits.
+fn strip_digit_separators(s: &str) -> Cow<'_, str> {
+    let bytes = s.as_bytes();
+    let grouped = s.contains('_')
+        && bytes.iter().enumerate().all(|(i, &b)| {
+            b != b'_'
+                || (i > 0 && bytes[i - 1].is_ascii_digit() && bytes.get(i + 1).is_some_and(u8::is_ascii_digit))
+        });
+    if grouped {
+        Cow::Owned(s.replace('_', ""))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+/// Number of tabular rows inspected when sniffing the delimiter
+const DELIMITER_SNIFF_ROWS: usize = 5;
+
+/// The length a `[N]` bracket declares; `[]` declares none
+fn declared_len(bracket: &str) -> Option<usize> {
+    bracket.trim_end_matches(|c: char| !c.is_ascii_digit()).parse().ok()
+}
+
+/// Delimiter declared by an array header's length marker (`[3|]`)
+fn header_delimiter(bracket: &str) -> Option<&'static str> {
+    if bracket.ends_with(',') {
+        Some(",")
+    } else if bracket.ends_with('|') {
+        Some("|")
+    } else if bracket.ends_with('\t') {
+        Some("\t")
+    } else {
+        None
+    }
+}
+
+/// Pick the delimiter that splits every sample into the same number of cells.
+/// With `expected` set, the cell count must equal it (e.g. an inline array's
+/// length marker); otherwise it must be more than one.
+fn sniff_delimiter(samples: &[&str], expected: Option<usize>) -> Option<&'static str> {
+    [",", "|", "\t"].into_iter().find(|candidate| {
+        let count = match expected {
+            Some(n) => n,
+            None => match samples.first() {
+                Some(first) => split_delimited(first, candidate).len(),
+                None => return false,
+            },
+        };
+        count > 1
+            && samples
+                .iter()
+                .all(|sample| split_delimited(sample, candidate).len() == count)
+    })
+}
+
+/// What a line looks like, decided before the parser acts on it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LineKind {
+    /// `key: value` or `key:` opening a nested block
+    KeyValue,
+    /// `key[N]: ...` or `[N]: ...`
+    ArrayHeader,
+    /// `key[N]{fields}:` or `[N]{fields}:`
+    TabularHeader,
+    /// `- item`
+    ListItem,
+    Blank,
+    /// `# ...`, skipped under `allow_comments`
+    Comment,
+    Unknown,
+}
+
+/// Classify a line's content (indentation already stripped)
+fn classify(content: &str) -> LineKind {
+    if content.is_empty() {
+        return LineKind::Blank;
+    }
+    if content.starts_with('#') {
+        return LineKind::Comment;
+    }
+    classify_content(content)
+}
+
+/// Classify a line that isn't read as a comment
+fn classify_content(content: &str) -> LineKind {
+    if content == "-" || content.starts_with("- ") {
+        return LineKind::ListItem;
+    }
+
+    let Some(colon_pos) = find_key_colon(content) else {
+        return LineKind::Unknown;
+    };
+    let key_part = content[..colon_pos].trim();
+    if key_part.starts_with('"') || !key_part.contains('[') {
+        LineKind::KeyValue
+    } else if key_part.ends_with('}') {
+        LineKind::TabularHeader
+    } else {
+        LineKind::ArrayHeader
+    }
+}
+
+/// Drop a leading byte order mark
+fn strip_bom(text: Cow<'_, str>) -> Cow<'_, str> {
+    match text {
+        Cow::Borrowed(s) => Cow::Borrowed(s.strip_prefix(BOM).unwrap_or(s)),
+        Cow::Owned(mut s) => {
+            if s.starts_with(BOM) {
+                s.drain(..BOM.len_utf8());
+            }
+            Cow::Owned(s)
+        }
+    }
+}
+
+/// A line of input with its indentation split off. Indentation is leading
+/// tabs, each `tab_width` columns wide, then spaces; a tab after a space is
+/// content, like the empty first cell of a tab-delimited row.
+struct Line<'a> {
+    number: usize,
+    /// Indentation width in columns
+    indent: usize,
+    /// Byte offset where the content starts
+    start: usize,
+    text: Cow<'a, str>,
+    /// End of the content, past which only trailing spaces or `\r` remain
+    end: usize,
+    kind: LineKind,
+}
+
+impl<'a> Line<'a> {
+    fn new(number: usize, text: Cow<'a, str>, tab_width: usize) -> Self {
+        let tabs = match tab_width {
+            0 => 0,
+            _ => text.len() - text.trim_start_matches('\t').len(),
+        };
+        let spaces = text[tabs..].len() - text[tabs..].trim_start_matches(' ').len();
+        let start = tabs + spaces;
+        let end = start.max(text.trim_end_matches([' ', '\r']).len());
+        let kind = classify(&text[start..end]);
+        Line {
+            number,
+            indent: tabs * tab_width + spaces,
+            start,
+            text,
+            end,
+            kind,
+        }
+    }
+
+    /// A line classified as `options` read it: comments and list markers applied
+    fn read(number: usize, text: Cow<'a, str>, options: &DecodeOptions) -> Self {
+        let mut line = Line::new(number, text, options.tab_width);
+        if options.allow_comments {
+            line.strip_trailing_comment();
+        } else if line.kind == LineKind::Comment {
+            // Without allow_comments a leading '#' is ordinary content
+            line.kind = classify_content(line.content());
+        }
+        if options.list_marker != "- " {
+            line.apply_list_marker(&options.list_marker);
+        }
+        line
+    }
+
+    fn content(&self) -> &str {
+        &self.text[self.start..self.end]
+    }
+
+    /// Whether this line opens a field of a top-level object
+    fn starts_root_field(&self, options: &DecodeOptions) -> bool {
+        let content = self.content();
+        self.indent == 0
+            && matches!(self.kind, LineKind::KeyValue | LineKind::ArrayHeader | LineKind::TabularHeader)
+            && !content.starts_with('[')
+            && !(options.tuple_marker && tuple_header(content).is_some())
+    }
+
+    /// Classify list items by a custom `list_marker` instead of `- `
+    fn apply_list_marker(&mut self, marker: &str) {
+        let content = self.content();
+        if content == marker.trim_end() || content.starts_with(marker) {
+            self.kind = LineKind::ListItem;
+        } else if self.kind == LineKind::ListItem {
+            self.kind = LineKind::Unknown;
+        }
+    }
+
+    /// Whether a tab comes before the content, in or just after the indentation
+    fn leading_tab(&self) -> bool {
+        self.text.trim_start_matches(' ').starts_with('\t')
+    }
+
+    /// Cut a trailing comment: a '#' after whitespace, outside quotes
+    fn strip_trailing_comment(&mut self) {
+        let content = self.content();
+        let mut in_quotes = false;
+        let mut escaped = false;
+        let mut prev = None;
+        for (i, ch) in content.char_indices() {
+            if escaped {
+                escaped = false;
+            } else if ch == '\\' && in_quotes {
+                escaped = true;
+            } else if ch == '"' {
+                in_quotes = !in_quotes;
+            } else if ch == '#' && !in_quotes && prev.is_some_and(char::is_whitespace) {
+                self.end = self.start + content[..i].trim_end().len();
+                self.kind = classify(self.content());
+                return;
+            }
+            prev = Some(ch);
+        }
+    }
+}
+
+/// Indentation-driven TOON parser. Lines are pulled from `source` as the
+/// parser needs them, so only a few lines of lookahead are held at a time.
+struct Decoder<'a, I> {
+    source: I,
+    /// Lines read from `source` but not consumed yet
+    buffer: VecDeque<Line<'a>>,
+    lines_read: usize,
+    /// First error from `source`, which ends the input early
+    read_error: Option<std::io::Error>,
+    /// Indentation unit, taken from the first indented line
+    unit: Option<usize>,
+    options: &'a DecodeOptions,
+    /// Path of the value being parsed, in the encoder's `a.b[0]` form
+    path: String,
+    /// Comment lines waiting for the next key
+    pending_comments: Vec<String>,
+    comments: Comments,
+    /// Nested blocks currently open, checked against `max_depth`
+    depth: usize,
+    /// Line spans of the values parsed so far; only recorded when `Some`
+    spans: Option<Spans>,
+    /// JSON Pointer of the value being parsed, kept while recording spans
+    pointer: String,
+    /// Number of the last line consumed
+    last_line: usize,
+    /// Arrays currently open; `expand_paths` leaves keys inside them alone
+    arrays: usize,
+    /// The input is a document still being received, so a block it ends
+    /// inside may fall short of its declared length
+    snapshot: bool,
+    /// Whether a block ended with the input before its declared length
+    incomplete: bool,
+}
+
+impl<'a, I> Decoder<'a, I>
+where
+    I: Iterator<Item = std::io::Result<Cow<'a, str>>>,
+{
+    fn new(source: I, options: &'a DecodeOptions) -> Self {
+        Decoder {
+            source,
+            buffer: VecDeque::new(),
+            lines_read: 0,
+            read_error: None,
+            unit: None,
+            options,
+            path: String::new(),
+            pending_comments: Vec::new(),
+            comments: Comments::new(),
+            depth: 0,
+            spans: None,
+            pointer: String::new(),
+            last_line: 0,
+            arrays: 0,
+            snapshot: false,
+            incomplete: false,
+        }
+    }
+
+    fn error(line: &Line, message: &str) -> ToonError {
+        ToonError::DecodingError(format!("{} at line {}", message, line.number))
+    }
+
+    /// Whether a block that has `found` of its `declared` items was cut off by
+    /// the end of a snapshot's input, which is recorded as `incomplete`
+    fn left_open(&mut self, declared: usize, found: usize) -> bool {
+        let open = self.snapshot && found < declared && self.peek().is_none();
+        self.incomplete |= open;
+        open
+    }
+
+    /// Run `parse` one level deeper, failing once `max_depth` is reached
+    fn nested<T>(
+        &mut self,
+        line: &Line,
+        parse: impl FnOnce(&mut Self) -> Result<T, ToonError>,
+    ) -> Result<T, ToonError> {
+        if self.depth >= self.options.max_depth {
+            let message = format!("Nesting deeper than max_depth {}", self.options.max_depth);
+            return Err(Self::error(line, &message));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn default_delimiter(&self) -> &'a str {
+        self.options.delimiter.as_deref().unwrap_or(",")
+    }
+
+    /// Read from `source` until `n` lines are buffered; false if input ran out
+    fn fill(&mut self, n: usize) -> bool {
+        while self.buffer.len() < n {
+            match self.source.next() {
+                Some(Ok(text)) => {
+                    self.lines_read += 1;
+                    let text = if self.lines_read == 1 { strip_bom(text) } else { text };
+                    let line = Line::read(self.lines_read, text, self.options);
+                    if self.unit.is_none() && line.kind != LineKind::Blank && line.indent > 0 {
+                        self.unit = Some(line.indent);
+                    }
+                    self.buffer.push_back(line);
+                }
+                Some(Err(err)) => {
+                    self.read_error.get_or_insert(err);
+                    return false;
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Next raw line (blank lines included), without consuming it
+    fn peek_raw(&mut self) -> Option<&Line<'a>> {
+        self.fill(1);
+        self.buffer.front()
+    }
+
+    /// Next line that isn't blank or a comment, without consuming it.
+    /// Skipped comments are held for the next key.
+    fn peek(&mut self) -> Option<&Line<'a>> {
+        while let Some(line) = self.peek_raw() {
+            match line.kind {
+                LineKind::Blank => {}
+                LineKind::Comment => {
+                    let text = &line.content()[1..];
+                    let text = text.strip_prefix(' ').unwrap_or(text).to_string();
+                    self.pending_comments.push(text);
+                }
+                _ => break,
+            }
+            self.buffer.pop_front();
+        }
+        self.buffer.front()
+    }
+
+    /// Next row of a block whose header sits at `header_indent`, or `None` once
+    /// a blank line or a line back at the header's level ends the block. With
+    /// `allow_blank_rows`, blank lines followed by another row are skipped.
+    fn peek_row(&mut self, header_indent: usize) -> Option<&Line<'a>> {
+        if self.options.allow_blank_rows {
+            let mut blanks = 0;
+            while self.fill(blanks + 1) && self.buffer[blanks].kind == LineKind::Blank {
+                blanks += 1;
+            }
+            if self.buffer.get(blanks).is_some_and(|line| line.indent > header_indent) {
+                self.buffer.drain(..blanks);
+            }
+        }
+        // Comment lines among the rows, like a `tabular_footer`, are skipped
+        if self.options.allow_comments {
+            while self
+                .peek_raw()
+                .is_some_and(|next| next.kind == LineKind::Comment && next.indent > header_indent)
+            {
+                self.buffer.pop_front();
+            }
+        }
+        self.peek_raw()
+            .filter(|next| next.kind != LineKind::Blank && next.indent > header_indent)
+    }
+
+    /// Consume the line returned by the last `peek`
+    fn advance(&mut self) -> Line<'a> {
+        let line = self.buffer.pop_front().expect("advance follows a successful peek");
+        self.last_line = line.number;
+        line
+    }
+
+    /// Record the one-line span of row `index` of the array being parsed
+    fn record_row_span(&mut self, index: usize, line_number: usize) {
+        if let Some(spans) = &mut self.spans {
+            let mut pointer = self.pointer.clone();
+            push_pointer_segment(&mut pointer, &index.to_string());
+            spans.insert(pointer, (line_number, line_number));
+        }
+    }
+
+    /// Run `parse` for the child `segment` of the current value, recording
+    /// the lines from `start` to the last one it consumed
+    fn spanned<T>(&mut self, segment: &str, start: usize, parse: impl FnOnce(&mut Self) -> T) -> T {
+        if self.spans.is_none() {
+            return parse(self);
+        }
+        let parent_len = self.pointer.len();
+        push_pointer_segment(&mut self.pointer, segment);
+        let result = parse(self);
+        let end = self.last_line.max(start);
+        if let Some(spans) = &mut self.spans {
+            spans.insert(self.pointer.clone(), (start, end));
+        }
+        self.pointer.truncate(parent_len);
+        result
+    }
+
+    /// Parse the whole input, returning the value and what was learned about the source
+    /// Parse the whole input, before `numeric_keys_as_array` and `unwrap_root`
+    fn parse(&mut self) -> Result<Value, ToonError> {
+        if is_ambiguous_list_marker(&self.options.list_marker) {
+            return Err(ToonError::DecodingError(format!(
+                "List marker '{}' could be read as a key or value",
+                self.options.list_marker
+            )));
+        }
+        #[cfg(not(feature = "arbitrary_precision"))]
+        if self.options.preserve_number_text {
+            return Err(ToonError::DecodingError(
+                "preserve_number_text needs the arbitrary_precision feature".to_string(),
+            ));
+        }
+        self.check_version()?;
+        let value = self.parse_document();
+
+        // A read failure truncates the input, so it explains any parse error
+        if let Some(err) = self.read_error.take() {
+            return Err(err.into());
+        }
+        value
+    }
+
+    fn finish(mut self) -> Result<(Value, DocumentMeta), ToonError> {
+        let value = reshape_root(self.parse()?, self.options);
+        let meta = DocumentMeta {
+            indent: self.unit,
+            comments: self.comments,
+            spans: self.spans.unwrap_or_default(),
+        };
+        Ok((value, meta))
+    }
+
+    /// Consume a leading `#toon vN` line, rejecting major versions other than ours
+    fn check_version(&mut self) -> Result<(), ToonError> {
+        if !self.fill(1) {
+            return Ok(());
+        }
+        let line = &self.buffer[0];
+        let Some(version) = line.content().strip_prefix(VERSION_PREFIX) else {
+            return Ok(());
+        };
+        let major = version.split('.').next().unwrap_or_default();
+        match major.parse::<u32>() {
+            Ok(TOON_VERSION) => {}
+            Ok(_) => {
+                return Err(Self::error(line, &format!("Unsupported TOON version 'v{}'", version)));
+            }
+            Err(_) => {
+                return Err(Self::error(line, &format!("Malformed TOON version 'v{}'", version)));
+            }
+        }
+        self.buffer.pop_front();
+        Ok(())
+    }
+
+    fn parse_document(&mut self) -> Result<Value, ToonError> {
+        let options = self.options;
+        let Some(first) = self.peek() else {
+            return Ok(Value::Object(serde_json::Map::new()));
+        };
+        let start = first.number;
+
+        let value = if let Some(scalar) = first.content().strip_prefix("= ") {
+            // Top-level scalar written with the `= ` sentinel
+            let value = parse_value(scalar, options)?;
+            self.advance();
+            value
+        } else if first.content().starts_with('[') {
+            let line = self.advance();
+            self.parse_array_line(&line, line.content(), line.indent)?
+        } else if options.tuple_marker && tuple_header(first.content()).is_some() {
+            let line = self.advance();
+            self.parse_tuple(&line, line.content())?
+        } else {
+            let indent = first.indent;
+            let mut map = serde_json::Map::new();
+            self.parse_object(indent, &mut map)?;
+            Value::Object(map)
+        };
+
+        if let Some(line) = self.peek() {
+            return Err(Self::error(line, "Unexpected content"));
+        }
+
+        if let Some(spans) = &mut self.spans {
+            spans.insert(String::new(), (start, self.last_line));
+        }
+        Ok(value)
+    }
+
+    /// Parse `key: value` lines at exactly `indent` into `map`
+    fn parse_object(
+        &mut self,
+        indent: usize,
+        map: &mut serde_json::Map<String, Value>,
+    ) -> Result<(), ToonError> {
+        let options = self.options;
+        while let Some(next) = self.peek() {
+            if next.indent < indent || (next.indent == indent && next.kind == LineKind::ListItem) {
+                break;
+            }
+            if next.indent > indent && options.strict {
+                return Err(Self::error(next, "Unexpected indentation"));
+            }
+
+            // Lenient mode reads an over-indented field as one more field here
+            let line = self.advance();
+            match line.kind {
+                LineKind::KeyValue | LineKind::ArrayHeader | LineKind::TabularHeader => {
+                    self.parse_field(&line, line.content(), line.indent, map)?
+                }
+                _ if options.strict => {
+                    return Err(Self::error(&line, &format!("Unrecognized line '{}'", line.content())))
+                }
+                // Lenient mode drops lines it can't make sense of
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse one field whose key sits at column `indent`
+    fn parse_field(
+        &mut self,
+        line: &Line,
+        content: &str,
+        indent: usize,
+        map: &mut serde_json::Map<String, Value>,
+    ) -> Result<(), ToonError> {
+        let colon_pos = find_key_colon(content).ok_or_else(|| Self::error(line, "Expected 'key: value'"))?;
+
+        let key_part = content[..colon_pos].trim();
+        let value_part = content[colon_pos + 1..].trim();
+
+        // Array header pattern: key[N]{...}: or key[N]:
+        let (key, header) = match key_part.find('[').filter(|_| !key_part.starts_with('"')) {
+            Some(bracket_start) => (
+                parse_key(key_part[..bracket_start].trim())?,
+                Some(&key_part[bracket_start..]),
+            ),
+            None => (parse_key(key_part)?, None),
+        };
+
+        let field_path = key_path(&self.path, &key);
+        if !self.pending_comments.is_empty() {
+            self.comments.insert(field_path.clone(), self.pending_comments.join("\n"));
+            self.pending_comments.clear();
+        }
+
+        let parent_path = std::mem::replace(&mut self.path, field_path);
+        let value = self.spanned(&key, line.number, |d| match header {
+            Some(header) => d.parse_array(line, header, value_part, indent),
+            // Raw keys take the rest of the line as-is, delimiters, colons and all
+            None if !value_part.is_empty() && d.options.raw_keys.contains(&key) => {
+                Ok(Value::String(value_part.to_string()))
+            }
+            None if !value_part.is_empty() && d.options.json_passthrough_keys.contains(&key) => {
+                parse_json_passthrough(&key, parse_value(value_part, d.options)?)
+            }
+            None if !value_part.is_empty() => parse_value(value_part, d.options),
+            None => d.nested(line, |d| d.parse_nested(indent)),
+        })?;
+        self.path = parent_path;
+
+        // Only bare keys outside arrays are paths; quoted keys and keys inside
+        // arrays, tabular fields included, keep their dots
+        let (map, key) = match key_part.starts_with('"') || self.arrays > 0 {
+            false if self.options.expand_paths => self.path_target(line, map, &key)?,
+            _ => (map, key),
+        };
+
+        // A nested block would silently replace an earlier sibling
+        if self.options.strict && map.contains_key(&key) {
+            return Err(Self::error(line, &format!("Duplicate key '{}'", key)));
+        }
+
+        map.insert(key, value);
+        Ok(())
+    }
+
+    /// Object and key a dotted `a.b.c` key lands at, creating the objects on
+    /// the way. Keys with an empty segment are kept whole.
+    fn path_target<'m>(
+        &self,
+        line: &Line,
+        map: &'m mut serde_json::Map<String, Value>,
+        key: &str,
+    ) -> Result<(&'m mut serde_json::Map<String, Value>, String), ToonError> {
+        let segments: Vec<&str> = key.split('.').collect();
+        if segments.iter().any(|segment| segment.is_empty()) {
+            return Ok((map, key.to_string()));
+        }
+        let (last, parents) = segments.split_last().expect("split yields at least one segment");
+        let mut target = map;
+        for (i, segment) in parents.iter().enumerate() {
+            let entry = target
+                .entry(segment.to_string())
+                .or_insert_with(|| Value::Object(serde_json::Map::new()));
+            if !entry.is_object() {
+                // A scalar already at this prefix is replaced by the nested object
+                if self.options.strict {
+                    return Err(Self::error(
+                        line,
+                        &format!("Key '{}' conflicts with the value at '{}'", key, segments[..=i].join(".")),
+                    ));
+                }
+                *entry = Value::Object(serde_json::Map::new());
+            }
+            target = entry.as_object_mut().expect("entry was just made an object");
+        }
+        Ok((target, last.to_string()))
+    }
+
+    /// Parse the block under a `key:` line whose key sits at `parent_indent`
+    fn parse_nested(&mut self, parent_indent: usize) -> Result<Value, ToonError> {
+        let options = self.options;
+        match self.peek() {
+            Some(next) if next.indent > parent_indent => {
+                if next.content().starts_with('[') {
+                    let line = self.advance();
+                    self.parse_array_line(&line, line.content(), line.indent)
+                } else if options.tuple_marker && tuple_header(next.content()).is_some() {
+                    let line = self.advance();
+                    self.parse_tuple(&line, line.content())
+                } else {
+                    let indent = next.indent;
+                    let mut map = serde_json::Map::new();
+                    self.parse_object(indent, &mut map)?;
+                    Ok(Value::Object(map))
+                }
+            }
+            // `key:` with nothing below it is an empty object
+            _ => Ok(Value::Object(serde_json::Map::new())),
+        }
+    }
+
+    /// Parse a `(N,): a,b` line into a tagged tuple
+    fn parse_tuple(&mut self, line: &Line, content: &str) -> Result<Value, ToonError> {
+        let (bracket, value_part) = tuple_header(content).expect("caller checked for a tuple header");
+        let header = format!("[{}]", bracket);
+        self.parse_array(line, &header, value_part, line.indent).map(tag_tuple)
+    }
+
+    /// Parse an array whose whole header line is `content`
+    fn parse_array_line(&mut self, line: &Line, content: &str, header_indent: usize) -> Result<Value, ToonError> {
+        let colon_pos =
+            find_key_colon(content).ok_or_else(|| Self::error(line, "Expected ':' after array header"))?;
+        let header = content[..colon_pos].trim();
+        let value_part = content[colon_pos + 1..].trim();
+        self.parse_array(line, header, value_part, header_indent)
+    }
+
+    /// Parse an array from its `[N]{fields}` header, inline values and the
+    /// rows or items indented below `header_indent`
+    fn parse_array(
+        &mut self,
+        line: &Line,
+        header: &str,
+        value_part: &str,
+        header_indent: usize,
+    ) -> Result<Value, ToonError> {
+        self.arrays += 1;
+        let value = self.parse_array_contents(line, header, value_part, header_indent);
+        self.arrays -= 1;
+        value
+    }
+
+    fn parse_array_contents(
+        &mut self,
+        line: &Line,
+        header: &str,
+        value_part: &str,
+        header_indent: usize,
+    ) -> Result<Value, ToonError> {
+        let bracket_end = header
+            .find(']')
+            .ok_or_else(|| Self::error(line, "Unterminated array header"))?;
+        let bracket = &header[1..bracket_end];
+        let rest = header[bracket_end + 1..].trim();
+
+        // Tabular array: [N]{fields}:
+        if let Some(fields_str) = rest.strip_prefix('{') {
+            let fields_str = fields_str
+                .strip_suffix('}')
+                .ok_or_else(|| Self::error(line, "Unterminated field list"))?;
+            return self.parse_tabular(line, bracket, fields_str, header_indent);
+        }
+
+        // Matrix: [rows][cols]:
+        if let Some(width) = rest.strip_prefix('[') {
+            let width = width
+                .strip_suffix(']')
+                .ok_or_else(|| Self::error(line, "Unterminated matrix header"))?;
+            return self.parse_matrix(bracket, width, header_indent);
+        }
+
+        // Inline primitive array: [N]: val1,val2,val3
+        if !value_part.is_empty() {
+            let delimiter = match header_delimiter(bracket) {
+                Some(declared) => declared,
+                None if self.options.auto_delimiter && self.options.delimiter.is_none() => {
+                    let length = bracket.trim().parse::<usize>().ok();
+                    sniff_delimiter(&[value_part], length).unwrap_or(self.default_delimiter())
+                }
+                None => self.default_delimiter(),
+            };
+
+            let mut parts = split_delimited(value_part, delimiter);
+
+            // Empty strings are written quoted, so an empty last cell is a stray
+            // trailing delimiter (`[3,]: 1,2,3,`)
+            if parts.len() > 1 && parts.last().is_some_and(|s| s.trim().is_empty()) {
+                if self.options.strict {
+                    let message = match declared_len(bracket) {
+                        Some(length) => format!("Trailing delimiter after inline array of {} items", length),
+                        None => "Trailing delimiter after inline array".to_string(),
+                    };
+                    return Err(Self::error(line, &message));
+                }
+                parts.pop();
+            }
+
+            let values: Vec<Value> = parts
+                .into_iter()
+                .map(|s| parse_value(s.trim(), self.options))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(Value::Array(values));
+        }
+
+        // Expanded array: `- item` lines
+        let options = self.options;
+        let mut items = Vec::new();
+        while let Some(next) = self.peek() {
+            if next.indent <= header_indent {
+                break;
+            }
+            if next.kind != LineKind::ListItem {
+                if options.strict {
+                    let message = format!("Expected '{}' list item", options.list_marker);
+                    return Err(Self::error(next, &message));
+                }
+                // Lenient mode skips lines among the items that aren't items
+                self.advance();
+                continue;
+            }
+            let line = self.advance();
+
+            let item_path = format!("{}[{}]", self.path, items.len());
+            let parent_path = std::mem::replace(&mut self.path, item_path);
+            let index = items.len().to_string();
+            let item = self.spanned(&index, line.number, |d| d.nested(&line, |d| d.parse_item(&line)))?;
+            self.path = parent_path;
+            items.push(item);
+        }
+
+        if let Some(declared) = declared_len(bracket) {
+            self.left_open(declared, items.len());
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn parse_tabular(
+        &mut self,
+        line: &Line,
+        bracket: &str,
+        fields_str: &str,
+        header_indent: usize,
+    ) -> Result<Value, ToonError> {
+        let delimiter = match header_delimiter(bracket) {
+            Some(declared) => declared,
+            None if self.options.auto_delimiter && self.options.delimiter.is_none() => {
+                // Look ahead at up to DELIMITER_SNIFF_ROWS rows without consuming them
+                let mut rows = 0;
+                while rows < DELIMITER_SNIFF_ROWS
+                    && self.fill(rows + 1)
+                    && self.buffer[rows].kind != LineKind::Blank
+                    && self.buffer[rows].indent > header_indent
+                {
+                    rows += 1;
+                }
+                let mut samples = vec![fields_str];
+                samples.extend(self.buffer.iter().take(rows).map(|l| l.content()));
+                sniff_delimiter(&samples, None).unwrap_or(self.default_delimiter())
+            }
+            None => self.default_delimiter(),
+        };
+
+        let fields = parse_fields(fields_str, delimiter)?;
+
+        // A repeated field would silently overwrite the earlier column
+        if self.options.strict {
+            for (i, (field, _)) in fields.iter().enumerate() {
+                if fields[..i].iter().any(|(earlier, _)| earlier == field) {
+                    return Err(Self::error(line, &format!("Duplicate field '{}' in tabular header", field)));
+                }
+            }
+        }
+
+        // Leading tabs are empty cells, so tab-delimited rows take exactly
+        // one indent unit of spaces, telling indentation and cells apart
+        let tab_rows = delimiter == "\t" && self.options.strict;
+        let row_indent = header_indent + self.unit.unwrap_or(2);
+
+        let mut rows = Vec::new();
+        while self.peek_row(header_indent).is_some() {
+            let line = self.advance();
+            self.record_row_span(rows.len(), line.number);
+            if tab_rows && line.text.starts_with('\t') {
+                return Err(Self::error(&line, "Tab-delimited rows must be indented with spaces"));
+            }
+            if tab_rows && line.indent != row_indent {
+                let message = format!("Tab-delimited row indented {} spaces, expected {}", line.indent, row_indent);
+                return Err(Self::error(&line, &message));
+            }
+
+            // Keep tabs when they are cell separators (null rows are all separators)
+            let data_line = if delimiter == "\t" {
+                line.content()
+            } else {
+                line.content().trim()
+            };
+            let values: Vec<&str> = split_delimited(data_line, delimiter);
+
+            // Short rows would drop fields and long rows would drop cells. A
+            // snapshot's last row may still be short of cells.
+            if self.options.strict && values.len() != fields.len() && !self.left_open(fields.len(), values.len()) {
+                return Err(Self::error(
+                    &line,
+                    &format!("Tabular row has {} cells, expected {}", values.len(), fields.len()),
+                ));
+            }
+
+            // A line of empty cells is a null row
+            if self.options.tabular_allow_null_rows
+                && values.len() == fields.len()
+                && values.iter().all(|v| v.trim().is_empty())
+            {
+                rows.push(Value::Null);
+                continue;
+            }
+
+            let mut row_obj = serde_json::Map::new();
+            for ((field, ty), value) in fields.iter().zip(values.iter()) {
+                let value = if self.options.empty_cell_as_null && value.trim().is_empty() {
+                    Value::Null
+                } else {
+                    parse_value(value, self.options)?
+                };
+                let value = match self.options.json_passthrough_keys.contains(field) {
+                    true => parse_json_passthrough(field, value)?,
+                    false => value,
+                };
+                if let (Some(ty), true) = (ty, self.options.strict) {
+                    check_column_type(field, ty, &value)?;
+                }
+                row_obj.insert(field.clone(), value);
+            }
+            rows.push(Value::Object(row_obj));
+        }
+
+        // A tab-indented header's siblings start with tabs too
+        if tab_rows && !line.text.starts_with('\t') {
+            if let Some(next) = self.peek_raw().filter(|next| next.leading_tab()) {
+                return Err(Self::error(next, "Tab-delimited rows must be indented with spaces"));
+            }
+        }
+
+        // An `{i,value}` table counting up from 0 is an indexed flat array
+        if self.options.unindex_arrays && fields.len() == 2 && fields[0].0 == "i" && fields[1].0 == "value" {
+            let indexed = rows.iter().enumerate().all(|(i, row)| row.get("i").and_then(Value::as_u64) == Some(i as u64));
+            if indexed {
+                rows = rows.into_iter().map(|mut row| row["value"].take()).collect();
+            }
+        }
+
+        // `[]` headers (length markers omitted) declare no count
+        if let Some(declared) = declared_len(bracket) {
+            if !self.left_open(declared, rows.len()) && self.options.strict && declared != rows.len() {
+                return Err(Self::error(
+                    line,
+                    &format!("Tabular header declares {} rows, found {}", declared, rows.len()),
+                ));
+            }
+        }
+
+        Ok(Value::Array(rows))
+    }
+
+    /// Parse the rows of a matrix, one delimited line of cells per inner array
+    fn parse_matrix(&mut self, bracket: &str, width_bracket: &str, header_indent: usize) -> Result<Value, ToonError> {
+        let delimiter = header_delimiter(bracket).unwrap_or(self.default_delimiter());
+        let width = declared_len(width_bracket);
+
+        let mut rows = Vec::new();
+        while self.peek_row(header_indent).is_some() {
+            let line = self.advance();
+            self.record_row_span(rows.len(), line.number);
+
+            let cells = split_delimited(line.content(), delimiter)
+                .into_iter()
+                .map(|s| parse_value(s.trim(), self.options))
+                .collect::<Result<Vec<_>, _>>()?;
+            if let (Some(width), true) = (width, self.options.strict) {
+                if cells.len() != width && !self.left_open(width, cells.len()) {
+                    return Err(Self::error(
+                        &line,
+                        &format!("Matrix row has {} cells, expected {}", cells.len(), width),
+                    ));
+                }
+            }
+            rows.push(Value::Array(cells));
+        }
+
+        if let Some(declared) = declared_len(bracket) {
+            self.left_open(declared, rows.len());
+        }
+        Ok(Value::Array(rows))
+    }
+
+    /// Parse a `- ` list item; object items keep their other fields one
+    /// indentation unit below the marker
+    fn parse_item(&mut self, line: &Line) -> Result<Value, ToonError> {
+        let bullet = self.options.list_marker.trim_end();
+        let content = line.content().strip_prefix(bullet).unwrap_or_default().trim_start();
+        let content = strip_item_index(content);
+        if self.options.tuple_marker && tuple_header(content).is_some() {
+            return self.parse_tuple(line, content);
+        }
+
+        match classify(content) {
+            LineKind::Blank => Ok(Value::Object(serde_json::Map::new())),
+            LineKind::ArrayHeader | LineKind::TabularHeader if content.starts_with('[') => {
+                self.parse_array_line(line, content, line.indent)
+            }
+            LineKind::KeyValue | LineKind::ArrayHeader | LineKind::TabularHeader => {
+                let field_indent = line.indent + self.unit.unwrap_or(2);
+                let mut map = serde_json::Map::new();
+                self.parse_field(line, content, field_indent, &mut map)?;
+                self.parse_object(field_indent, &mut map)?;
+                Ok(Value::Object(map))
+            }
+            _ => parse_value(content, self.options),
+        }
+    }
+}
+
+/// Parse the JSON text written for a `json_passthrough_keys` value; values
+/// that aren't strings are kept as they are
+fn parse_json_passthrough(key: &str, value: Value) -> Result<Value, ToonError> {
+    match value {
+        Value::String(json) => serde_json::from_str(&json)
+            .map_err(|e| ToonError::DecodingError(format!("Invalid JSON under '{}': {}", key, e))),
+        value => Ok(value),
+    }
+}
+
+/// Split a `(N,): a,b` tuple line into its length bracket and values
+fn tuple_header(content: &str) -> Option<(&str, &str)> {
+    let (bracket, value_part) = content.strip_prefix('(')?.split_once("):")?;
+    let digits = bracket.trim_end_matches([',', '|', '\t']);
+    (!digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())).then(|| (bracket, value_part.trim()))
+}
+
+/// Drop the `[0]` index written by `index_expanded_items` from the start of
+/// a list item. Array headers never match, as their brackets hold more than
+/// digits or are followed by more than a space.
+fn strip_item_index(content: &str) -> &str {
+    let Some(rest) = content.strip_prefix('[') else {
+        return content;
+    };
+    match rest.split_once(']') {
+        Some((digits, after))
+            if !digits.is_empty()
+                && digits.bytes().all(|b| b.is_ascii_digit())
+                && (after.is_empty() || after.starts_with(' ')) =>
+        {
+            after.trim_start()
+        }
+        _ => content,
+    }
+}
+
+/// Split a tabular header's field list. Field names may be quoted and may
+/// carry a `:type` suffix.
+fn parse_fields<'f>(fields_str: &'f str, delimiter: &str) -> Result<Vec<(String, Option<&'f str>)>, ToonError> {
+    split_delimited(fields_str, delimiter)
+        .into_iter()
+        .map(|s| {
+            let s = s.trim();
+            match find_key_colon(s) {
+                Some(colon_pos) => Ok((parse_key(s[..colon_pos].trim())?, Some(s[colon_pos + 1..].trim()))),
+                None => Ok((parse_key(s)?, None)),
+            }
+        })
+        .collect()
+}
+
+/// Parse a possibly quoted object key
+fn parse_key(key_part: &str) -> Result<String, ToonError> {
+    if key_part.len() >= 2 && key_part.starts_with('"') && key_part.ends_with('"') {
+        unescape_string(&key_part[1..key_part.len() - 1])
+    } else {
+        Ok(unescape_delimiters(key_part).into_owned())
+    }
+}
+
+/// Facts about a decoded document's source, for writing it back the same way
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DocumentMeta {
+    /// Leading spaces of the first indented line; `None` if nothing is indented
+    pub indent: Option<usize>,
+    /// Comments keyed by the path of the key below them
+    pub comments: Comments,
+    /// Source lines of each value; filled only by [`decode_with_spans`]
+    pub spans: Spans,
+}
+
+/// Decode TOON format to JSON Value
+pub fn decode(toon_str: &str, options: &DecodeOptions) -> Result<Value, ToonError> {
+    decode_with_meta(toon_str, options).map(|(value, _)| value)
+}
+
+/// Decode TOON format, also returning each `#` comment attached to the path
+/// of the key below it. Comments with no key after them are dropped.
+/// Comments are read whatever `allow_comments` says.
+pub fn decode_with_comments(toon_str: &str, options: &DecodeOptions) -> Result<(Value, Comments), ToonError> {
+    let options = DecodeOptions {
+        allow_comments: true,
+        ..options.clone()
+    };
+    decode_with_meta(toon_str, &options).map(|(value, meta)| (value, meta.comments))
+}
+
+/// Decode TOON format, also returning the source's indentation width and comments
+pub fn decode_with_meta(toon_str: &str, options: &DecodeOptions) -> Result<(Value, DocumentMeta), ToonError> {
+    let lines = toon_str.lines().map(|line| Ok(Cow::Borrowed(line)));
+    Decoder::new(lines, options).finish()
+}
+
+/// Decode TOON format, also returning the first and last source line of
+/// every key, list item and row, keyed by JSON Pointer (`""` for the root).
+/// Pointers follow the document as written, before `expand_paths` or
+/// `unwrap_root` reshape it.
+pub fn decode_with_spans(toon_str: &str, options: &DecodeOptions) -> Result<(Value, Spans), ToonError> {
+    let lines = toon_str.lines().map(|line| Ok(Cow::Borrowed(line)));
+    let mut decoder = Decoder::new(lines, options);
+    decoder.spans = Some(Spans::new());
+    decoder.finish().map(|(value, meta)| (value, meta.spans))
+}
+
+/// Append `/segment` to a JSON Pointer, escaping `~` and `/` as RFC 6901 requires
+fn push_pointer_segment(pointer: &mut String, segment: &str) {
+    pointer.push('/');
+    for ch in segment.chars() {
+        match ch {
+            '~' => pointer.push_str("~0"),
+            '/' => pointer.push_str("~1"),
+            _ => pointer.push(ch),
+        }
+    }
+}
+
+/// Decode a document into one flat object mapping the JSON Pointer of each
+/// leaf to its value, e.g. `/users/0/name`. Empty arrays and objects have no
+/// leaves, so they are kept as values; a scalar root is keyed by `""`.
+pub fn decode_flat(toon_str: &str) -> Result<Value, ToonError> {
+    let value = decode(toon_str, &DecodeOptions::default())?;
+    let mut result = serde_json::Map::new();
+    pointer_leaves(&value, &mut String::new(), &mut result);
+    Ok(Value::Object(result))
+}
+
+fn pointer_leaves(value: &Value, pointer: &mut String, result: &mut serde_json::Map<String, Value>) {
+    let len = pointer.len();
+    match value {
+        Value::Object(obj) if !obj.is_empty() => {
+            for (key, val) in obj {
+                push_pointer_segment(pointer, key);
+                pointer_leaves(val, pointer, result);
+                pointer.truncate(len);
+            }
+        }
+        Value::Array(arr) if !arr.is_empty() => {
+            for (i, item) in arr.iter().enumerate() {
+                push_pointer_segment(pointer, &i.to_string());
+                pointer_leaves(item, pointer, result);
+                pointer.truncate(len);
+            }
+        }
+        _ => {
+            result.insert(pointer.clone(), value.clone());
+        }
+    }
+}
+
+/// Describe a document's keys without decoding its values. Nested objects
+/// map to their own schema, tabular arrays to their column names, other
+/// arrays to `"array"` and everything else to `"scalar"`. Array items and
+/// rows are skipped unread.
+pub fn decode_schema(toon_str: &str) -> Result<Value, ToonError> {
+    let mut schema = Value::Object(serde_json::Map::new());
+    // Keys of the objects enclosing the current line, with the indent of each key's line
+    let mut open: Vec<(usize, String)> = Vec::new();
+    // Indent of the last array header; deeper lines are its items or rows
+    let mut array_indent = None;
+
+    for (i, text) in toon_str.lines().enumerate() {
+        let text = if i == 0 { text.trim_start_matches(BOM) } else { text };
+        let line = Line::new(i + 1, Cow::Borrowed(text), DEFAULT_TAB_WIDTH);
+        if matches!(line.kind, LineKind::Blank | LineKind::Comment) {
+            continue;
+        }
+        if array_indent.is_some_and(|indent| line.indent > indent) {
+            continue;
+        }
+        array_indent = None;
+        while open.last().is_some_and(|(indent, _)| line.indent <= *indent) {
+            open.pop();
+        }
+
+        let content = line.content();
+        let Some(node) = open.iter().try_fold(&mut schema, |node, (_, key)| node.get_mut(key)) else {
+            continue;
+        };
+
+        // A keyless header is the array value of the enclosing key, or the root
+        if content.starts_with('[') {
+            let header = &content[..find_key_colon(content).unwrap_or(content.len())];
+            *node = header_schema(header.trim())?;
+            array_indent = Some(line.indent);
+            continue;
+        }
+        if content.starts_with("= ") && open.is_empty() {
+            schema = Value::String("scalar".to_string());
+            continue;
+        }
+
+        let (Some(colon_pos), Value::Object(map)) = (find_key_colon(content), node) else {
+            continue;
+        };
+        let key_part = content[..colon_pos].trim();
+        let value_part = content[colon_pos + 1..].trim();
+        match key_part.find('[').filter(|_| !key_part.starts_with('"')) {
+            Some(bracket_start) => {
+                map.insert(parse_key(key_part[..bracket_start].trim())?, header_schema(&key_part[bracket_start..])?);
+                array_indent = Some(line.indent);
+            }
+            None if !value_part.is_empty() => {
+                map.insert(parse_key(key_part)?, Value::String("scalar".to_string()));
+            }
+            None => {
+                let key = parse_key(key_part)?;
+                map.insert(key.clone(), Value::Object(serde_json::Map::new()));
+                open.push((line.indent, key));
+            }
+        }
+    }
+
+    Ok(schema)
+}
+
+/// Schema of an array header: its column names if tabular, else `"array"`
+fn header_schema(header: &str) -> Result<Value, ToonError> {
+    let fields = header
+        .strip_prefix('[')
+        .and_then(|header| header.split_once(']'))
+        .and_then(|(bracket, rest)| Some((bracket, rest.trim().strip_prefix('{')?.strip_suffix('}')?)));
+    match fields {
+        Some((bracket, fields_str)) => {
+            let delimiter = header_delimiter(bracket).unwrap_or(",");
+            let columns = parse_fields(fields_str, delimiter)?;
+            Ok(columns.into_iter().map(|(field, _)| Value::String(field)).collect())
+        }
+        None => Ok(Value::String("array".to_string())),
+    }
+}
+
+/// Hash of a document's decoded value, equal for documents that decode to
+/// equal values however they are formatted. Object keys are sorted first, so
+/// key order doesn't count; array order does.
+pub fn content_hash(toon_str: &str) -> Result<u64, ToonError> {
+    let value = decode(toon_str, &DecodeOptions::default())?;
+    let mut hasher = Fnv1a::default();
+    hash_value(&value, &mut hasher);
+    Ok(hasher.finish())
+}
+
+/// 64-bit FNV-1a; unlike `DefaultHasher` its output is fixed across Rust releases
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        // Little-endian on every platform, so the hash doesn't depend on the machine
+        self.write(&i.to_le_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Feed a value to the hasher with a type tag and length before each part,
+/// so no two distinct values share a byte stream
+fn hash_value(value: &Value, hasher: &mut Fnv1a) {
+    let hash_str = |s: &str, hasher: &mut Fnv1a| {
+        hasher.write_u64(s.len() as u64);
+        hasher.write(s.as_bytes());
+    };
+    match value {
+        Value::Null => hasher.write_u8(0),
+        Value::Bool(b) => {
+            hasher.write_u8(1);
+            hasher.write_u8(u8::from(*b));
+        }
+        Value::Number(n) => {
+            hasher.write_u8(2);
+            hash_str(&n.to_string(), hasher);
+        }
+        Value::String(s) => {
+            hasher.write_u8(3);
+            hash_str(s, hasher);
+        }
+        Value::Array(arr) => {
+            hasher.write_u8(4);
+            hasher.write_u64(arr.len() as u64);
+            for item in arr {
+                hash_value(item, hasher);
+            }
+        }
+        Value::Object(obj) => {
+            hasher.write_u8(5);
+            hasher.write_u64(obj.len() as u64);
+            let mut entries: Vec<_> = obj.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            for (key, val) in entries {
+                hash_str(key, hasher);
+                hash_value(val, hasher);
+            }
+        }
+    }
+}
+
+/// Decode a TOON file, reading it line by line instead of loading it whole
+pub fn decode_file(path: &Path, options: &DecodeOptions) -> Result<Value, ToonError> {
+    let file = File::open(path)?;
+    let lines = BufReader::new(file).lines().map(|line| line.map(Cow::Owned));
+    Decoder::new(lines, options).finish().map(|(value, _)| value)
+}
+
+/// Decoder fed a document in chunks, e.g. as it streams from an LLM. Chunks
+/// may end mid-line; the unfinished line is held until the rest arrives.
+/// Top-level fields of an object document are decoded once the next one
+/// starts, so only the field still being received is decoded again.
+#[pyclass]
+pub struct StreamingDecoder {
+    options: DecodeOptions,
+    /// Top-level fields decoded so far
+    closed: serde_json::Map<String, Value>,
+    /// Lines the closed fields were decoded from
+    closed_lines: usize,
+    /// Indentation unit, from the first indented line
+    unit: Option<usize>,
+    /// Whether the document is an object, once its first line is known
+    object_root: Option<bool>,
+    /// Complete lines after the closed fields
+    lines: Vec<String>,
+    /// Line in `lines` where a top-level field starts, if any has
+    field_start: Option<usize>,
+    /// Text after the last newline, waiting for the rest of its line
+    partial: String,
+}
+
+impl StreamingDecoder {
+    pub fn new(options: DecodeOptions) -> Self {
+        StreamingDecoder {
+            options,
+            closed: serde_json::Map::new(),
+            closed_lines: 0,
+            unit: None,
+            object_root: None,
+            lines: Vec::new(),
+            field_start: None,
+            partial: String::new(),
+        }
+    }
+
+    /// Add the next chunk of input. Fails if a top-level field it completes
+    /// doesn't decode.
+    pub fn feed(&mut self, chunk: &str) -> Result<(), ToonError> {
+        self.partial.push_str(chunk);
+        if let Some(end) = self.partial.rfind('\n') {
+            let rest = self.partial.split_off(end + 1);
+            let complete = std::mem::replace(&mut self.partial, rest);
+            for line in complete.lines() {
+                self.push_line(line)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// True while a line is only partly received or a block has fewer rows or
+    /// items than its header declares
+    pub fn needs_more(&self) -> bool {
+        !self.partial.is_empty() || self.decode_tail(true).is_ok_and(|(_, incomplete)| incomplete)
+    }
+
+    /// Decode the complete lines received so far, leaving the input buffered.
+    /// A block cut off by the end of the input may be short of rows, items or
+    /// cells even under `strict`.
+    pub fn snapshot(&self) -> Result<Value, ToonError> {
+        let (tail, _) = self.decode_tail(true)?;
+        Ok(reshape_root(self.merge_closed(self.closed.clone(), tail)?, &self.options))
+    }
+
+    /// Decode everything fed, including a final line without a newline, and
+    /// reset so the decoder can take another document
+    pub fn finish(&mut self) -> Result<Value, ToonError> {
+        let partial = std::mem::take(&mut self.partial);
+        let result = self.decode_rest(&partial);
+        *self = StreamingDecoder::new(std::mem::take(&mut self.options));
+        Ok(reshape_root(result?, &self.options))
+    }
+
+    fn decode_rest(&mut self, partial: &str) -> Result<Value, ToonError> {
+        if !partial.is_empty() {
+            self.push_line(partial)?;
+        }
+        let (tail, _) = self.decode_tail(false)?;
+        let closed = std::mem::take(&mut self.closed);
+        self.merge_closed(closed, tail)
+    }
+
+    fn push_line(&mut self, text: &str) -> Result<(), ToonError> {
+        let first = self.closed_lines + self.lines.len() == 0;
+        let line = Line::read(0, if first { strip_bom(Cow::Borrowed(text)) } else { Cow::Borrowed(text) }, &self.options);
+        if self.unit.is_none() && line.kind != LineKind::Blank && line.indent > 0 {
+            self.unit = Some(line.indent);
+        }
+        let starts_field = line.starts_root_field(&self.options);
+        // The first line with content, past a `#toon vN` header, shows the root's shape
+        let is_version = first && line.content().starts_with(VERSION_PREFIX);
+        if self.object_root.is_none() && !is_version && !matches!(line.kind, LineKind::Blank | LineKind::Comment) {
+            self.object_root = Some(starts_field);
+        }
+
+        // Dotted keys under expand_paths may add to an earlier field
+        let closes = starts_field && self.object_root == Some(true) && !self.options.expand_paths;
+        if closes && self.field_start.is_some() {
+            let (value, _) = self.decode_tail(false)?;
+            let closed = std::mem::take(&mut self.closed);
+            self.closed = match self.merge_closed(closed, value)? {
+                Value::Object(map) => map,
+                _ => unreachable!("fields decode to an object"),
+            };
+            self.closed_lines += self.lines.len();
+            self.lines.clear();
+            self.field_start = None;
+        }
+        if starts_field && self.field_start.is_none() {
+            self.field_start = Some(self.lines.len());
+        }
+        self.lines.push(text.to_string());
+        Ok(())
+    }
+
+    /// Decode the lines after the closed fields, and whether a block in them
+    /// was cut off short of its declared length (only looked for in a snapshot)
+    fn decode_tail(&self, snapshot: bool) -> Result<(Value, bool), ToonError> {
+        let lines = self.lines.iter().map(|line| Ok(Cow::Borrowed(line.as_str())));
+        let mut decoder = Decoder::new(lines, &self.options);
+        decoder.lines_read = self.closed_lines;
+        decoder.unit = self.unit;
+        decoder.snapshot = snapshot;
+        let value = decoder.parse()?;
+        Ok((value, decoder.incomplete))
+    }
+
+    /// Add the fields of `tail` to the closed fields
+    fn merge_closed(&self, mut closed: serde_json::Map<String, Value>, tail: Value) -> Result<Value, ToonError> {
+        if self.closed_lines == 0 {
+            return Ok(tail);
+        }
+        let Value::Object(tail) = tail else {
+            unreachable!("a document with closed fields is an object")
+        };
+        for (key, value) in tail {
+            if self.options.strict && closed.contains_key(&key) {
+                let number = self.closed_lines + self.field_start.unwrap_or_default() + 1;
+                return Err(ToonError::DecodingError(format!("Duplicate key '{}' at line {}", key, number)));
+            }
+            closed.insert(key, value);
+        }
+        Ok(Value::Object(closed))
+    }
+}
+
+#[pymethods]
+impl StreamingDecoder {
+    #[new]
+    #[pyo3(signature = (options=None))]
+    fn py_new(options: Option<&DecodeOptions>) -> Self {
+        StreamingDecoder::new(options.cloned().unwrap_or_default())
+    }
+
+    #[pyo3(name = "feed")]
+    fn py_feed(&mut self, chunk: &str) -> PyResult<()> {
+        Ok(self.feed(chunk)?)
+    }
+
+    #[getter(needs_more)]
+    fn py_needs_more(&self) -> bool {
+        self.needs_more()
+    }
+
+    #[pyo3(name = "snapshot")]
+    fn py_snapshot(&self, py: Python) -> PyResult<PyObject> {
+        value_to_python(py, &self.snapshot()?, &self.options)
+    }
+
+    #[pyo3(name = "finish")]
+    fn py_finish(&mut self, py: Python) -> PyResult<PyObject> {
+        let value = self.finish()?;
+        value_to_python(py, &value, &self.options)
+    }
+}
+
+/// Deep-merge two values, concatenating arrays found at matching keys
+pub fn merge(a: &Value, b: &Value) -> Value {
+    merge_with(a, b, true)
+}
+
+/// Deep-merge two values. Objects are merged key by key, `b` wins on scalar
+/// conflicts, and arrays at matching keys are concatenated when
+/// `concat_arrays` is set or replaced by `b` otherwise.
+pub fn merge_with(a: &Value, b: &Value, concat_arrays: bool) -> Value {
+    match (a, b) {
+        (Value::Object(a_obj), Value::Object(b_obj)) => {
+            let mut result = a_obj.clone();
+            for (key, b_val) in b_obj {
+                let merged = match result.get(key) {
+                    Some(a_val) => merge_with(a_val, b_val, concat_arrays),
+                    None => b_val.clone(),
+                };
+                result.insert(key.clone(), merged);
+            }
+            Value::Object(result)
+        }
+        (Value::Array(a_arr), Value::Array(b_arr)) if concat_arrays => {
+            let mut result = a_arr.clone();
+            result.extend(b_arr.iter().cloned());
+            Value::Array(result)
+        }
+        _ => b.clone(),
+    }
+}
+
+/// Byte sizes of a value written as compact JSON and as TOON
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Savings {
+    pub json_bytes: usize,
+    pub toon_bytes: usize,
+}
+
+impl Savings {
+    /// TOON size as a fraction of the JSON size; below 1.0 means TOON is smaller
+    pub fn ratio(&self) -> f64 {
+        self.toon_bytes as f64 / self.json_bytes as f64
+    }
+}
+
+/// Measure `value` as compact JSON and as TOON encoded with `options`
+pub fn savings(value: &Value, options: &EncodeOptions) -> Result<Savings, ToonError> {
+    let json = serde_json::to_string(value).map_err(|e| ToonError::EncodingError(e.to_string()))?;
+    let toon = encode_document(value, options)?;
+    Ok(Savings {
+        json_bytes: json.len(),
+        toon_bytes: toon.len(),
+    })
+}
+
+/// Re-emit TOON in canonical form, so documents holding equal data come out
+/// identical whatever their delimiters, indentation, quoting or key order
+pub fn normalize(toon_str: &str, options: &DecodeOptions) -> Result<String, ToonError> {
+    encode_document(&decode(toon_str, options)?, &EncodeOptions::canonical())
+}
+
+/// Rewrite a document's tables, inline arrays and array headers from the
+/// `from` delimiter to `to` without decoding it. Cells are re-split with the
+/// quote-aware splitter, and unquoted cells holding the new delimiter are
+/// quoted. Arrays declaring another delimiter, and all other lines, are kept
+/// as written.
+pub fn retarget_delimiter(toon_str: &str, from: &str, to: &str) -> Result<String, ToonError> {
+    for delimiter in [from, to] {
+        if !matches!(delimiter, "," | "|" | "\t") {
+            return Err(ToonError::InvalidDelimiter(delimiter.to_string()));
+        }
+    }
+    let options = EncodeOptions {
+        delimiter: to.to_string(),
+        ..EncodeOptions::default()
+    };
+
+    let mut result = String::with_capacity(toon_str.len());
+    let mut unit = None;
+    let mut table: Option<TableRows> = None;
+    for (i, text) in toon_str.lines().enumerate() {
+        if i > 0 {
+            result.push('\n');
+        }
+        let line = Line::new(i + 1, Cow::Borrowed(text), DEFAULT_TAB_WIDTH);
+        if line.kind == LineKind::Blank {
+            table = None;
+            result.push_str(text);
+            continue;
+        }
+        if unit.is_none() && line.indent > 0 {
+            unit = Some(line.indent);
+        }
+
+        let rewritten = match table {
+            Some((header_indent, rewrite)) if line.indent > header_indent => {
+                // Comment lines among rows, like a `tabular_footer`, stay as they are
+                (rewrite && line.kind != LineKind::Comment)
+                    .then(|| retarget_cells(line.content(), from, to, &options))
+            }
+            _ => {
+                let header = retarget_header(line.content(), line.indent, unit.unwrap_or(2), from, to, &options);
+                table = header.as_ref().and_then(|(_, rows)| *rows);
+                header.and_then(|(rewritten, _)| rewritten)
+            }
+        };
+        match rewritten {
+            Some(content) => {
+                result.push_str(&text[..line.start]);
+                result.push_str(&content);
+                result.push_str(&text[line.end..]);
+            }
+            None => result.push_str(text),
+        }
+    }
+    if toon_str.ends_with('\n') {
+        result.push('\n');
+    }
+
+    Ok(result)
+}
+
+/// Rows below an array header: the indent they must pass, and whether
+/// [`retarget_delimiter`] rewrites them
+type TableRows = (usize, bool);
+
+/// Rewrite an array header line for [`retarget_delimiter`]. Returns `None`
+/// if the line isn't an array header; otherwise the new content (`None` if
+/// the array uses another delimiter) and, if rows follow, the indent they
+/// must pass and whether they are to be rewritten.
+fn retarget_header(
+    content: &str,
+    indent: usize,
+    unit: usize,
+    from: &str,
+    to: &str,
+    options: &EncodeOptions,
+) -> Option<(Option<String>, Option<TableRows>)> {
+    // A keyed header on a list item belongs to the item's first field
+    let (rest, keyed_indent) = match content.strip_prefix('-') {
+        Some(item) if classify(content) == LineKind::ListItem => (strip_item_index(item.trim_start()), indent + unit),
+        _ => (content, indent),
+    };
+    let prefix = &content[..content.len() - rest.len()];
+
+    let colon_pos = find_key_colon(rest)?;
+    let key_part = rest[..colon_pos].trim_end();
+    let bracket_start = key_part.find('[').filter(|_| !key_part.starts_with('"'))?;
+    let header_indent = if bracket_start == 0 { indent } else { keyed_indent };
+
+    let header = &key_part[bracket_start..];
+    let bracket_end = header.find(']')?;
+    let bracket = &header[1..bracket_end];
+    let count = bracket.trim_end_matches([',', '|', '\t']);
+    if !count.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let after = &header[bracket_end + 1..];
+    let has_rows = after.starts_with('{') || after.starts_with('[');
+    let rows = has_rows.then_some((header_indent, header_delimiter(bracket).unwrap_or(",") == from));
+    if header_delimiter(bracket).unwrap_or(",") != from {
+        return Some((None, rows));
+    }
+
+    let after = if let Some(fields_str) = after.strip_prefix('{').and_then(|a| a.strip_suffix('}')) {
+        format!("{{{}}}", retarget_cells(fields_str, from, to, options))
+    } else if let Some(width) = after.strip_prefix('[').and_then(|a| a.strip_suffix(']')) {
+        format!("[{}]", retarget_bracket(width, to))
+    } else if after.is_empty() {
+        String::new()
+    } else {
+        return None;
+    };
+
+    // Inline values follow the colon; a `# N items` comment is kept as is
+    let value_part = &rest[colon_pos + 1..];
+    let values = value_part.trim_start_matches(' ');
+    let value_part = if values.is_empty() || values.starts_with('#') {
+        value_part.to_string()
+    } else {
+        format!("{}{}", &value_part[..value_part.len() - values.len()], retarget_cells(values, from, to, options))
+    };
+
+    let rewritten = format!(
+        "{}{}[{}]{}{}:{}",
+        prefix,
+        &key_part[..bracket_start],
+        retarget_bracket(bracket, to),
+        after,
+        &rest[key_part.len()..colon_pos],
+        value_part
+    );
+    Some((Some(rewritten), rows))
+}
+
+/// Swap the delimiter marker of a length bracket; a bare count gets one
+/// unless the new delimiter is the default comma
+fn retarget_bracket(bracket: &str, to: &str) -> String {
+    let count = bracket.trim_end_matches([',', '|', '\t']);
+    if count.len() < bracket.len() || to != "," {
+        format!("{}{}", count, to)
+    } else {
+        count.to_string()
+    }
+}
+
+/// Re-join `from`-delimited cells with `to`. Quoted cells are kept; unquoted
+/// ones are re-quoted if they hold the new delimiter or escaped delimiters.
+fn retarget_cells(cells: &str, from: &str, to: &str, options: &EncodeOptions) -> String {
+    let cells: Vec<String> = split_delimited(cells, from)
+        .into_iter()
+        .map(|cell| {
+            let token = cell.trim_matches(' ');
+            if token.starts_with('"') {
+                return token.to_string();
+            }
+            match unescape_delimiters(token) {
+                Cow::Borrowed(value) if !value.contains(to) => value.to_string(),
+                value => quote_if_needed(&value, options),
+            }
+        })
+        .collect();
+    cells.join(to)
+}
+
+/// Path of the first place where `a` and `b` differ, in the encoder's
+/// `a.b[0]` form (empty for the root), or `None` if they are equal
+pub fn first_difference(a: &Value, b: &Value) -> Option<String> {
+    first_difference_at(a, b, "")
+}
+
+fn first_difference_at(a: &Value, b: &Value, path: &str) -> Option<String> {
+    match (a, b) {
+        (Value::Object(a_obj), Value::Object(b_obj)) => {
+            for (key, a_val) in a_obj {
+                let child = key_path(path, key);
+                match b_obj.get(key) {
+                    Some(b_val) => {
+                        if let Some(diff) = first_difference_at(a_val, b_val, &child) {
+                            return Some(diff);
+                        }
+                    }
+                    None => return Some(child),
+                }
+            }
+            b_obj
+                .keys()
+                .find(|key| !a_obj.contains_key(*key))
+                .map(|key| key_path(path, key))
+        }
+        (Value::Array(a_arr), Value::Array(b_arr)) => {
+            for (i, (a_item, b_item)) in a_arr.iter().zip(b_arr).enumerate() {
+                if let Some(diff) = first_difference_at(a_item, b_item, &format!("{}[{}]", path, i)) {
+                    return Some(diff);
+                }
+            }
+            // A length mismatch points at the first missing or extra item
+            (a_arr.len() != b_arr.len()).then(|| format!("{}[{}]", path, a_arr.len().min(b_arr.len())))
+        }
+        _ => (a != b).then(|| path.to_string()),
+    }
+}
+
+/// Flatten nested objects into `separator`-joined keys (`a.b.c`), recursing
+/// into array elements so arrays of nested records become tabular. A key that
+/// already contains `separator` would read back as a path, so it is an error.
+pub fn flatten(value: &Value, separator: &str) -> Result<Value, ToonError> {
+    match value {
+        Value::Object(obj) => {
+            let mut result = serde_json::Map::new();
+            flatten_into(obj, "", separator, true, &mut result)?;
+            Ok(Value::Object(result))
+        }
+        Value::Array(arr) => arr.iter().map(|v| flatten(v, separator)).collect(),
+        _ => Ok(value.clone()),
+    }
+}
+
+/// Whether encoding `value` indents any line below another: nested objects,
+/// rows and list items, and arrays `index_large_arrays` expands
+fn needs_indent(value: &Value, options: &EncodeOptions) -> bool {
+    let nests = |value: &Value| match value {
+        Value::Object(obj) => !obj.is_empty(),
+        Value::Array(arr) => {
+            arr.iter().any(|item| item.is_object() || item.is_array())
+                || options.index_large_arrays.is_some_and(|max| arr.len() > max)
+        }
+        _ => false,
+    };
+    match value {
+        Value::Object(obj) => obj.values().any(nests),
+        _ => nests(value),
+    }
+}
+
+/// Flatten for `flatten_paths`: only the objects outside arrays, which are
+/// all that `expand_paths` nests again
+fn flatten_object_tree(value: &Value) -> Result<Value, ToonError> {
+    match value {
+        Value::Object(obj) => {
+            let mut result = serde_json::Map::new();
+            flatten_into(obj, "", ".", false, &mut result)?;
+            Ok(Value::Object(result))
+        }
+        _ => Ok(value.clone()),
+    }
+}
+
+fn flatten_into(
+    obj: &serde_json::Map<String, Value>,
+    prefix: &str,
+    separator: &str,
+    into_arrays: bool,
+    result: &mut serde_json::Map<String, Value>,
+) -> Result<(), ToonError> {
+    for (key, val) in obj {
+        if key.contains(separator) {
+            return Err(ToonError::EncodingError(format!(
+                "Key '{}' contains the path separator '{}'",
+                key, separator
+            )));
+        }
+        let full_key = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}{}{}", prefix, separator, key)
+        };
+        match val {
+            // Empty objects have no leaves, so keep them as values
+            Value::Object(nested) if !nested.is_empty() => {
+                flatten_into(nested, &full_key, separator, into_arrays, result)?
+            }
+            _ if into_arrays => {
+                result.insert(full_key, flatten(val, separator)?);
+            }
+            _ => {
+                result.insert(full_key, val.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rebuild nested objects from `separator`-joined keys; the inverse of
+/// [`flatten`]. Every key is split, so a key that held the separator before
+/// flattening can't be told apart (which is why [`flatten`] rejects them).
+pub fn unflatten(value: &Value, separator: &str) -> Value {
+    match value {
+        Value::Object(obj) => {
+            let mut result = Value::Object(serde_json::Map::new());
+            for (key, val) in obj {
+                let mut target = &mut result;
+                let mut parts = key.split(separator).peekable();
+                while let Some(part) = parts.next() {
+                    let map = target.as_object_mut().unwrap();
+                    if parts.peek().is_none() {
+                        map.insert(part.to_string(), unflatten(val, separator));
+                        break;
+                    }
+                    let entry = map
+                        .entry(part.to_string())
+                        .or_insert_with(|| Value::Object(serde_json::Map::new()));
+                    // A scalar already sitting at this prefix is replaced by the nested object
+                    if !entry.is_object() {
+                        *entry = Value::Object(serde_json::Map::new());
+                    }
+                    target = entry;
+                }
+            }
+            result
+        }
+        Value::Array(arr) => Value::Array(arr.iter().map(|v| unflatten(v, separator)).collect()),
+        _ => value.clone(),
+    }
+}
+
+/// Apply `numeric_keys_as_array` and `unwrap_root` to a decoded document
+fn reshape_root(value: Value, options: &DecodeOptions) -> Value {
+    let value = if options.numeric_keys_as_array {
+        numeric_keys_to_arrays(value)
+    } else {
+        value
+    };
+    match (&options.unwrap_root, value) {
+        (Some(key), Value::Object(mut obj)) if obj.len() == 1 && obj.contains_key(key) => obj.remove(key).unwrap(),
+        (_, value) => value,
+    }
+}
+
+/// Turn objects whose keys are exactly `"0"` to `"N-1"`, in any order, into
+/// arrays, innermost first. Empty objects and keys like `"01"` are left alone.
+fn numeric_keys_to_arrays(value: Value) -> Value {
+    match value {
+        Value::Object(obj) => {
+            let obj: serde_json::Map<String, Value> =
+                obj.into_iter().map(|(key, val)| (key, numeric_keys_to_arrays(val))).collect();
+            let indices: Option<Vec<usize>> = obj
+                .keys()
+                .map(|key| key.parse::<usize>().ok().filter(|i| i.to_string() == *key))
+                .collect();
+            // Keys are unique, so indices all below the length cover 0..N
+            match indices {
+                Some(indices) if !obj.is_empty() && indices.iter().all(|&i| i < obj.len()) => {
+                    let mut items = vec![Value::Null; obj.len()];
+                    for (i, (_, val)) in indices.into_iter().zip(obj) {
+                        items[i] = val;
+                    }
+                    Value::Array(items)
+                }
+                _ => Value::Object(obj),
+            }
+        }
+        Value::Array(arr) => Value::Array(arr.into_iter().map(numeric_keys_to_arrays).collect()),
+        value => value,
+    }
+}
+
+/// Set pyclass fields from a dict, reusing the pyo3 setters' type conversion
+fn set_fields<T>(py: Python, options: T, fields: &Bound<'_, PyDict>, caller: &str) -> PyResult<T>
+where
+    T: PyClass + Clone,
+    PyClassInitializer<T>: From<T>,
+{
+    let bound = Bound::new(py, options)?;
+    let object = bound.as_any();
+    for (key, value) in fields {
+        let name = key.str()?;
+        if !object.hasattr(&name)? {
+            return Err(pyo3::exceptions::PyTypeError::new_err(format!(
+                "{} got an unexpected option '{}'",
+                caller, name
+            )));
+        }
+        object.setattr(&name, value)?;
+    }
+    let options = bound.borrow().clone();
+    Ok(options)
+}
+
+/// Apply keyword overrides such as `indent=4` on top of `options`
+fn with_overrides(
+    py: Python,
+    options: EncodeOptions,
+    overrides: Option<&Bound<'_, PyDict>>,
+) -> PyResult<EncodeOptions> {
+    match overrides {
+        Some(overrides) => set_fields(py, options, overrides, "encode()"),
+        None => Ok(options),
+    }
+}
+
+/// Encode Python data to TOON format
+#[pyfunction]
+#[pyo3(signature = (data, options=None, default=None, **overrides))]
+pub fn encode(
+    py: Python,
+    data: &Bound<'_, PyAny>,
+    options: Option<&EncodeOptions>,
+    default: Option<&Bound<'_, PyAny>>,
+    overrides: Option<&Bound<'_, PyDict>>,
+) -> PyResult<String> {
+    let opts = with_overrides(py, options.cloned().unwrap_or_default(), overrides)?;
+
+    // Convert Python object to serde_json::Value
+    let value = python_to_value_with(data, &opts, default)?;
+
+    // Encode to TOON
+    let result = encode_document(&value, &opts)?;
+
+    Ok(result)
+}
+
+/// Convert the requested columns of one Python row dict
+fn python_row(
+    row: &Bound<'_, PyAny>,
+    fields: &[String],
+    options: &EncodeOptions,
+    row_path: &str,
+) -> Result<serde_json::Map<String, Value>, ToonError> {
+    let dict = row
+        .downcast::<PyDict>()
+        .map_err(|_| ToonError::EncodingError(at_path("Rows must be dicts".to_string(), row_path)))?;
+
+    // Only the requested columns are converted
+    let mut obj = serde_json::Map::new();
+    for field in fields {
+        let value = match dict.get_item(field).map_err(|e| ToonError::PythonError(e.to_string()))? {
+            Some(value) => python_to_value(&value, options)?,
+            None if options.strict => {
+                return Err(ToonError::EncodingError(at_path(format!("Missing field '{}'", field), row_path)))
+            }
+            None => Value::Null,
+        };
+        if value.is_object() || value.is_array() {
+            return Err(ToonError::EncodingError(at_path(
+                format!("Field '{}' is not a primitive", field),
+                row_path,
+            )));
+        }
+        obj.insert(field.clone(), value);
+    }
+    Ok(obj)
+}
+
+/// Encode an iterable of dicts as a top-level tabular array with the given
+/// columns, converting and writing one row at a time
+#[pyfunction]
+#[pyo3(signature = (rows, fields, options=None))]
+pub fn encode_toon_rows(rows: &Bound<'_, PyAny>, fields: Vec<String>, options: Option<&EncodeOptions>) -> PyResult<String> {
+    let opts = options.cloned().unwrap_or_default();
+
+    check_row_fields(&fields, &opts)?;
+
+    let row_indent = " ".repeat(opts.indent);
+    let mut body = String::new();
+    let mut types = column_types([], &fields, &opts);
+    let mut count = 0;
+
+    for (row_index, row) in rows.try_iter()?.enumerate() {
+        let row_path = format!("[{}]", row_index);
+        let obj = python_row(&row?, &fields, &opts, &row_path)?;
+
+        body.push('\n');
+        body.push_str(&row_indent);
+        body.push_str(&encode_tabular_row(Some(&obj), &fields, &opts, &row_path)?);
+        check_budget(body.len(), &opts)?;
+
+        for (ty, field) in types.iter_mut().zip(&fields) {
+            *ty = widen_column_type(*ty, &obj[field]);
+        }
+        count += 1;
+    }
+
+    if !opts.typed_header {
+        types.clear();
+    }
+    let mut result = if opts.emit_version {
+        format!("{}{}\n", VERSION_PREFIX, TOON_VERSION)
+    } else {
+        String::new()
+    };
+    result.push_str(&tabular_header(count, &fields, &types, &opts, "")?);
+    result.push_str(&body);
+    check_budget(result.len(), &opts)?;
+
+    Ok(result)
+}
+
+/// Decode TOON format to Python data
+#[pyfunction]
+#[pyo3(signature = (toon_str, options=None))]
+pub fn decode_toon(py: Python, toon_str: &str, options: Option<&DecodeOptions>) -> PyResult<PyObject> {
+    let opts = options.cloned().unwrap_or_default();
+
+    // Decode from TOON
+    let value = decode(toon_str, &opts)?;
+
+    // Convert to Python object
+    value_to_python(py, &value, &opts)
+}
+
+/// Encode an iterable of dicts to a tabular TOON file, converting and writing
+/// one row at a time. The header states the row count if the iterable has a
+/// length, else it is written as `[]`.
+#[pyfunction]
+#[pyo3(signature = (rows, path, fields, options=None))]
+pub fn encode_toon_to_file(
+    rows: &Bound<'_, PyAny>,
+    path: PathBuf,
+    fields: Vec<String>,
+    options: Option<&EncodeOptions>,
+) -> PyResult<()> {
+    let opts = options.cloned().unwrap_or_default();
+
+    check_row_fields(&fields, &opts)?;
+    let len = rows.len().ok();
+    let rows = rows.try_iter()?.enumerate().map(|(row_index, row)| {
+        let row = row.map_err(|e| ToonError::PythonError(e.to_string()))?;
+        python_row(&row, &fields, &opts, &format!("[{}]", row_index))
+    });
+
+    let mut writer = BufWriter::new(File::create(&path).map_err(ToonError::from)?);
+    encode_to_writer(rows, len, &fields, &opts, &mut writer)?;
+    writer.flush().map_err(ToonError::from)?;
+    Ok(())
+}
+
+/// Decode a TOON file to Python data, reading it line by line
+#[pyfunction]
+#[pyo3(signature = (path, options=None))]
+pub fn decode_toon_file(py: Python, path: PathBuf, options: Option<&DecodeOptions>) -> PyResult<PyObject> {
+    let opts = options.cloned().unwrap_or_default();
+
+    let value = decode_file(&path, &opts)?;
+
+    value_to_python(py, &value, &opts)
+}
+
+/// Merge two TOON documents and re-encode the result
+#[pyfunction]
+#[pyo3(signature = (a_str, b_str, options=None, concat_arrays=true, decode_options=None))]
+pub fn merge_toon(
+    a_str: &str,
+    b_str: &str,
+    options: Option<&EncodeOptions>,
+    concat_arrays: bool,
+    decode_options: Option<&DecodeOptions>,
+) -> PyResult<String> {
+    let opts = options.cloned().unwrap_or_default();
+    let decode_opts = decode_options.cloned().unwrap_or_default();
+
+    let a = decode(a_str, &decode_opts)?;
+    let b = decode(b_str, &decode_opts)?;
+    let merged = merge_with(&a, &b, concat_arrays);
+
+    Ok(encode_document(&merged, &opts)?)
+}
+
+/// Flatten nested objects into dotted keys and encode to TOON format
+#[pyfunction]
+#[pyo3(signature = (data, options=None, separator="."))]
+pub fn encode_flattened(
+    _py: Python,
+    data: &Bound<'_, PyAny>,
+    options: Option<&EncodeOptions>,
+    separator: &str,
+) -> PyResult<String> {
+    let opts = options.cloned().unwrap_or_default();
+
+    let value = python_to_value(data, &opts)?;
+    let result = encode_document(&flatten(&value, separator)?, &opts)?;
+
+    Ok(result)
+}
+
+/// Decode TOON format and rebuild nesting from dotted keys
+#[pyfunction]
+#[pyo3(signature = (toon_str, options=None, separator="."))]
+pub fn decode_unflatten(
+    py: Python,
+    toon_str: &str,
+    options: Option<&DecodeOptions>,
+    separator: &str,
+) -> PyResult<PyObject> {
+    let opts = options.cloned().unwrap_or_default();
+
+    let value = decode(toon_str, &opts)?;
+
+    value_to_python(py, &unflatten(&value, separator), &opts)
+}
+
+/// Encode Python data to TOON format with comments above the keys at the given paths
+#[pyfunction]
+#[pyo3(signature = (data, comments, options=None))]
+pub fn encode_toon_with_comments(
+    data: &Bound<'_, PyAny>,
+    comments: Comments,
+    options: Option<&EncodeOptions>,
+) -> PyResult<String> {
+    let opts = options.cloned().unwrap_or_default();
+
+    let value = python_to_value(data, &opts)?;
+
+    Ok(encode_with_comments(&value, &comments, &opts)?)
+}
+
+/// Find the first NaN or infinite float in Python data, without encoding it.
+/// Returns its path (empty for the root), or None if every float is finite.
+#[pyfunction]
+pub fn validate_finite(data: &Bound<'_, PyAny>) -> PyResult<Option<String>> {
+    first_non_finite(data, "")
+}
+
+/// Re-emit TOON in canonical form
+#[pyfunction]
+#[pyo3(signature = (toon_str, options=None))]
+pub fn normalize_toon(toon_str: &str, options: Option<&DecodeOptions>) -> PyResult<String> {
+    let opts = options.cloned().unwrap_or_default();
+
+    Ok(normalize(toon_str, &opts)?)
+}
+
+/// Rewrite a TOON document's tables and inline arrays to another delimiter
+#[pyfunction]
+pub fn retarget_toon_delimiter(toon_str: &str, from_delimiter: &str, to_delimiter: &str) -> PyResult<String> {
+    Ok(retarget_delimiter(toon_str, from_delimiter, to_delimiter)?)
+}
+
+/// Compare the byte size of Python data as compact JSON and as TOON
+#[pyfunction]
+#[pyo3(signature = (data, options=None))]
+pub fn toon_savings(py: Python, data: &Bound<'_, PyAny>, options: Option<&EncodeOptions>) -> PyResult<PyObject> {
+    let opts = options.cloned().unwrap_or_default();
+
+    let value = python_to_value(data, &opts)?;
+    let savings = savings(&value, &opts)?;
+
+    let result = PyDict::new(py);
+    result.set_item("json_bytes", savings.json_bytes)?;
+    result.set_item("toon_bytes", savings.toon_bytes)?;
+    result.set_item("ratio", savings.ratio())?;
+    Ok(result.into())
+}
+
+/// Encode Python data to a truncated TOON preview for logs
+#[pyfunction]
+#[pyo3(signature = (data, max_items=10, max_chars=80, options=None))]
+pub fn encode_toon_preview(
+    data: &Bound<'_, PyAny>,
+    max_items: usize,
+    max_chars: usize,
+    options: Option<&EncodeOptions>,
+) -> PyResult<String> {
+    let opts = options.cloned().unwrap_or_default();
+
+    let value = python_to_value(data, &opts)?;
+
+    Ok(encode_preview(&value, PreviewConfig { max_items, max_chars }, &opts)?)
+}
+
+/// Decode TOON format to Python data plus its comments keyed by path
+#[pyfunction]
+#[pyo3(signature = (toon_str, options=None))]
+pub fn decode_toon_with_comments(
+    py: Python,
+    toon_str: &str,
+    options: Option<&DecodeOptions>,
+) -> PyResult<(PyObject, Comments)> {
+    let opts = options.cloned().unwrap_or_default();
+
+    let (value, comments) = decode_with_comments(toon_str, &opts)?;
+
+    Ok((value_to_python(py, &value, &opts)?, comments))
+}
+
+/// Decode TOON format to Python data plus a dict mapping JSON Pointers to
+/// `(first_line, last_line)` source spans
+#[pyfunction]
+#[pyo3(signature = (toon_str, options=None))]
+pub fn decode_toon_with_spans(
+    py: Python,
+    toon_str: &str,
+    options: Option<&DecodeOptions>,
+) -> PyResult<(PyObject, Spans)> {
+    let opts = options.cloned().unwrap_or_default();
+
+    let (value, spans) = decode_with_spans(toon_str, &opts)?;
+
+    Ok((value_to_python(py, &value, &opts)?, spans))
+}
+
+/// Decode TOON format to a flat dict mapping JSON Pointers to leaf values
+#[pyfunction]
+pub fn decode_toon_flat(py: Python, toon_str: &str) -> PyResult<PyObject> {
+    let flat = decode_flat(toon_str)?;
+
+    value_to_python(py, &flat, &DecodeOptions::default())
+}
+
+/// Describe a TOON document's keys and tabular columns without decoding values
+#[pyfunction]
+pub fn decode_toon_schema(py: Python, toon_str: &str) -> PyResult<PyObject> {
+    let schema = decode_schema(toon_str)?;
+
+    value_to_python(py, &schema, &DecodeOptions::default())
+}
+
+/// Hash a TOON document's decoded value, ignoring formatting and key order
+#[pyfunction]
+pub fn toon_content_hash(toon_str: &str) -> PyResult<u64> {
+    Ok(content_hash(toon_str)?)
+}
+
+/// Decode TOON format to Python data plus a dict describing the source:
+/// `indent` (None if nothing is indented) and `comments`
+#[pyfunction]
+#[pyo3(signature = (toon_str, options=None))]
+pub fn decode_toon_with_meta(
+    py: Python,
+    toon_str: &str,
+    options: Option<&DecodeOptions>,
+) -> PyResult<(PyObject, PyObject)> {
+    let opts = options.cloned().unwrap_or_default();
+
+    let (value, meta) = decode_with_meta(toon_str, &opts)?;
+
+    let result = PyDict::new(py);
+    result.set_item("indent", meta.indent)?;
+    result.set_item("comments", meta.comments)?;
+    Ok((value_to_python(py, &value, &opts)?, result.into()))
+}
+
+/// Check that `data` survives an encode/decode cycle unchanged. Returns a
+/// bool, or `(bool, path)` with the first differing path when `with_path` is set.
+#[pyfunction]
+#[pyo3(signature = (data, encode_options=None, decode_options=None, with_path=false))]
+pub fn roundtrip_ok(
+    py: Python,
+    data: &Bound<'_, PyAny>,
+    encode_options: Option<&EncodeOptions>,
+    decode_options: Option<&DecodeOptions>,
+    with_path: bool,
+) -> PyResult<PyObject> {
+    let encode_opts = encode_options.cloned().unwrap_or_default();
+    let decode_opts = decode_options.cloned().unwrap_or_default();
+
+    let value = python_to_value(data, &encode_opts)?;
+    let encoded = encode_document(&value, &encode_opts)?;
+
+    // Output the decoder rejects differs at the root
+    let difference = match decode(&encoded, &decode_opts) {
+        Ok(decoded) => first_difference(&value, &decoded),
+        Err(_) => Some(String::new()),
+    };
+
+    let ok = difference.is_none();
+    if with_path {
+        (ok, difference).into_py_any(py)
+    } else {
+        ok.into_py_any(py)
+    }
+}
+
+/// Python module
+#[pymodule]
+fn _toon_tuna(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(encode, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_toon_rows, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_toon, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_toon_file, m)?)?;
+    m.add_function(wrap_pyfunction!(merge_toon, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_flattened, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_unflatten, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_toon_with_comments, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_toon_with_comments, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_toon_with_spans, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_toon_to_file, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_toon_with_meta, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_toon_flat, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_toon_schema, m)?)?;
+    m.add_function(wrap_pyfunction!(toon_content_hash, m)?)?;
+    m.add_function(wrap_pyfunction!(roundtrip_ok, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_toon_preview, m)?)?;
+    m.add_function(wrap_pyfunction!(toon_savings, m)?)?;
+    m.add_function(wrap_pyfunction!(normalize_toon, m)?)?;
+    m.add_function(wrap_pyfunction!(retarget_toon_delimiter, m)?)?;
+    m.add_function(wrap_pyfunction!(validate_finite, m)?)?;
+    m.add_class::<EncodeOptions>()?;
+    m.add_class::<DecodeOptions>()?;
+    m.add_class::<BytesEncoding>()?;
+    m.add_class::<LengthMarkerStyle>()?;
+    m.add_class::<NumberOverflow>()?;
+    m.add_class::<StreamingDecoder>()?;
+    m.add("ToonError", m.py().get_type::<exceptions::ToonError>())?;
+    m.add("ToonEncodeError", m.py().get_type::<exceptions::ToonEncodeError>())?;
+    m.add("ToonDecodeError", m.py().get_type::<exceptions::ToonDecodeError>())?;
+    m.add("ToonDelimiterError", m.py().get_type::<exceptions::ToonDelimiterError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_if_needed() {
+        let opts = EncodeOptions::default();
+        assert_eq!(quote_if_needed("hello", &opts), "hello");
+        assert_eq!(quote_if_needed("hello world", &opts), "\"hello world\"");
+        assert_eq!(quote_if_needed("true", &opts), "\"true\"");
+        assert_eq!(quote_if_needed("123", &opts), "\"123\"");
+    }
+
+    #[test]
+    fn test_wire_format() {
+        // Length markers carry the delimiter and strings with any space are
+        // quoted, matching the README examples
+        let data = serde_json::json!({
+            "users": [{"id": 1, "name": "Ada Lovelace"}],
+            "tags": ["a b", "c"],
+            "none": []
+        });
+        assert_eq!(
+            encode_document(&data, &EncodeOptions::default()).unwrap(),
+            "users:\n  [1,]{id,name}:\n    1,\"Ada Lovelace\"\ntags:\n  [2,]: \"a b\",c\nnone:\n  [0,]:"
+        );
+        let pipe = EncodeOptions::builder().delimiter("|").build();
+        assert!(encode_document(&data, &pipe).unwrap().starts_with("users:\n  [1|]{id|name}:"));
+    }
+
+    #[test]
+    fn test_encode_simple_object() {
+        let data = serde_json::json!({
+            "id": 123,
+            "name": "Alice"
+        });
+
+        let opts = EncodeOptions::default();
+        let result = encode_value(&data, 0, &opts).unwrap();
+
+        assert!(result.contains("id: 123"));
+        assert!(result.contains("name: Alice"));
+    }
+
+    #[test]
+    fn test_encode_tabular_array() {
+        let data = serde_json::json!({
+            "users": [
+                {"id": 1, "name": "Alice"},
+                {"id": 2, "name": "Bob"}
+            ]
+        });
+
+        let opts = EncodeOptions::default();
+        let result = encode_value(&data, 0, &opts).unwrap();
+
+        assert!(result.contains("[2,]{id,name}:"));
+        assert!(result.contains("1,Alice"));
+        assert!(result.contains("2,Bob"));
+    }
+
+    #[test]
+    fn test_encode_primitive_array() {
+        let data = serde_json::json!({
+            "tags": [1, 2, 3]
+        });
+
+        let opts = EncodeOptions::default();
+        let result = encode_value(&data, 0, &opts).unwrap();
+
+        assert!(result.contains("tags:"));
+        assert!(result.contains("[3,]: 1,2,3"));
+    }
+
+    #[test]
+    fn test_merge_nested_objects() {
+        let a = serde_json::json!({
+            "user": {"id": 1, "name": "Alice", "meta": {"role": "admin"}}
+        });
+        let b = serde_json::json!({
+            "user": {"name": "Alicia", "meta": {"team": "core"}}
+        });
+
+        let merged = merge(&a, &b);
+
+        assert_eq!(
+            merged,
+            serde_json::json!({
+                "user": {"id": 1, "name": "Alicia", "meta": {"role": "admin", "team": "core"}}
+            })
+        );
+    }
+
+    #[test]
+    fn test_merge_arrays() {
+        let a = serde_json::json!({"tags": [1, 2]});
+        let b = serde_json::json!({"tags": [3]});
+
+        assert_eq!(merge(&a, &b), serde_json::json!({"tags": [1, 2, 3]}));
+        assert_eq!(merge_with(&a, &b, false), serde_json::json!({"tags": [3]}));
+    }
+
+    #[test]
+    fn test_strict_rejects_u64_overflow() {
+        let data = serde_json::json!({"max": u64::MAX, "min": i64::MIN});
+        let result = encode_value(&data, 0, &EncodeOptions::default()).unwrap();
+        assert_eq!(result, "max: 18446744073709551615\nmin: -9223372036854775808");
+        assert_eq!(decode(&result, &DecodeOptions::default()).unwrap(), data);
+    }
+
+    /// Only numbers that keep their text can hold an integer past 64 bits
+    #[cfg(feature = "arbitrary_precision")]
+    #[test]
+    fn test_strict_rejects_integer_past_64_bits() {
+        let data: Value = serde_json::from_str(r#"{"big": 18446744073709551616}"#).unwrap();
+        let strict = EncodeOptions::default();
+        assert!(matches!(
+            encode_value(&data, 0, &strict),
+            Err(ToonError::EncodingError(_))
+        ));
+
+        let lenient = EncodeOptions {
+            strict: false,
+            ..EncodeOptions::default()
+        };
+        let result = encode_value(&data, 0, &lenient).unwrap();
+        assert!(result.contains("big: 18446744073709552000"));
+    }
+
+    #[test]
+    fn test_strict_rejects_header_key_collision() {
+        let data = serde_json::json!({
+            "rows": [{"a": 1, "a ": 2}, {"a": 3, "a ": 4}]
+        });
+
+        let strict = EncodeOptions::default();
+        assert!(matches!(
+            encode_value(&data, 0, &strict),
+            Err(ToonError::EncodingError(_))
+        ));
+
+        let lenient = EncodeOptions {
+            strict: false,
+            ..EncodeOptions::default()
+        };
+        assert!(encode_value(&data, 0, &lenient).is_ok());
+    }
+
+    #[test]
+    fn test_always_quote_strings_roundtrip() {
+        let data = serde_json::json!({
+            "users": [
+                {"id": 1, "name": "Alice"},
+                {"id": 2, "name": "Bob, Jr."}
+            ]
+        });
+
+        let opts = EncodeOptions {
+            always_quote_strings: true,
+            ..EncodeOptions::default()
+        };
+        let result = encode_value(&data, 0, &opts).unwrap();
+
+        assert!(result.contains("1,\"Alice\""));
+        assert!(result.contains("2,\"Bob, Jr.\""));
+
+        let decoded = decode(&result, &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_decode_strict_rejects_nested_key_collision() {
+        let toon = "items: 5\nitems:\n  [2,]: 1,2";
+
+        assert!(matches!(
+            decode(toon, &DecodeOptions::default()),
+            Err(ToonError::DecodingError(_))
+        ));
+
+        let lenient = DecodeOptions {
+            strict: false,
+            ..DecodeOptions::default()
+        };
+        let decoded = decode(toon, &lenient).unwrap();
+        assert_eq!(decoded, serde_json::json!({"items": [1, 2]}));
+    }
+
+    #[test]
+    fn test_typed_header_roundtrip() {
+        let data = serde_json::json!({
+            "items": [
+                {"id": 1, "price": 9.5, "active": true, "name": "Apple"},
+                {"id": 2, "price": 3.25, "active": false, "name": "Pear"}
+            ]
+        });
+
+        let opts = EncodeOptions::builder().typed_header(true).build();
+        let result = encode_value(&data, 0, &opts).unwrap();
+
+        assert!(result.contains("[2,]{id:int,price:float,active:bool,name:str}:"));
+
+        let decoded = decode(&result, &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_typed_header_widens_over_all_rows() {
+        let data = serde_json::json!([
+            {"n": 1, "first_null": null, "mixed": "x", "all_null": null},
+            {"n": 1.5, "first_null": 2, "mixed": 2, "all_null": null},
+            {"n": null, "first_null": 3, "mixed": true, "all_null": null}
+        ]);
+
+        let opts = EncodeOptions::builder().typed_header(true).build();
+        let result = encode_value(&data, 0, &opts).unwrap();
+
+        assert!(result.starts_with("[3,]{n:float,first_null:int,mixed,all_null:null}:"));
+        assert_eq!(decode(&result, &DecodeOptions::default()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_typed_header_streaming_checks_later_rows() {
+        let rows = |data: Value| data.as_array().unwrap().iter().map(|row| Ok(row.as_object().unwrap().clone())).collect::<Vec<_>>();
+        let fields = vec!["a".to_string(), "b".to_string()];
+        let opts = EncodeOptions::builder().typed_header(true).build();
+
+        // A null in the first row leaves its column untyped
+        let mut out = Vec::new();
+        encode_to_writer(rows(serde_json::json!([{"a": 1, "b": null}, {"a": 2.5, "b": "x"}])), None, &fields, &opts, &mut out)
+            .unwrap_err();
+        let mut out = Vec::new();
+        encode_to_writer(rows(serde_json::json!([{"a": 1.5, "b": null}, {"a": 2, "b": "x"}])), None, &fields, &opts, &mut out)
+            .unwrap();
+        let toon = String::from_utf8(out).unwrap();
+        assert!(toon.starts_with("[]{a:float,b}:"));
+        assert_eq!(
+            decode(&toon, &DecodeOptions::default()).unwrap(),
+            serde_json::json!([{"a": 1.5, "b": null}, {"a": 2, "b": "x"}])
+        );
+    }
+
+    #[test]
+    fn test_typed_header_rejects_mismatch() {
+        let toon = "items:\n  [2,]{id:int,name:str}:\n    1,Apple\n    two,Pear";
+
+        assert!(matches!(
+            decode(toon, &DecodeOptions::default()),
+            Err(ToonError::DecodingError(_))
+        ));
+
+        let lenient = DecodeOptions {
+            strict: false,
+            ..DecodeOptions::default()
+        };
+        let decoded = decode(toon, &lenient).unwrap();
+        assert_eq!(decoded["items"][1]["id"], "two");
+    }
+
+    #[test]
+    fn test_inline_array_roundtrip_with_delimiter_in_value() {
+        let data = serde_json::json!({"tags": ["a,b", "c"]});
+
+        let opts = EncodeOptions::default();
+        let result = encode_value(&data, 0, &opts).unwrap();
+        assert!(result.contains("[2,]: \"a,b\",c"));
+
+        let decoded = decode(&result, &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_scalar_sentinel_roundtrip() {
+        let opts = EncodeOptions::builder().scalar_sentinel(true).build();
+
+        for data in [
+            serde_json::json!(5),
+            serde_json::json!("hello world"),
+            serde_json::json!(true),
+        ] {
+            let result = encode_document(&data, &opts).unwrap();
+            assert!(result.starts_with("= "));
+
+            let decoded = decode(&result, &DecodeOptions::default()).unwrap();
+            assert_eq!(decoded, data);
+        }
+    }
+
+    #[test]
+    fn test_decode_empty_cell() {
+        let toon = "rows:\n  [1,]{a,b,c}:\n    1,,3";
+
+        let decoded = decode(toon, &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded["rows"][0]["b"], "");
+
+        let opts = DecodeOptions {
+            empty_cell_as_null: true,
+            ..DecodeOptions::default()
+        };
+        let decoded = decode(toon, &opts).unwrap();
+        assert_eq!(decoded["rows"][0]["b"], Value::Null);
+        assert_eq!(decoded["rows"][0]["c"], 3);
+    }
+
+    #[test]
+    fn test_parallel_matches_serial() {
+        let rows: Vec<Value> = (0..1000)
+            .map(|i| serde_json::json!({"id": i, "name": format!("user {}", i), "score": i as f64 / 4.0}))
+            .collect();
+        let data = serde_json::json!({ "users": rows });
+
+        let serial = encode_value(&data, 0, &EncodeOptions::default()).unwrap();
+        let parallel_opts = EncodeOptions::builder().parallel(true).build();
+        let parallel = encode_value(&data, 0, &parallel_opts).unwrap();
+
+        assert_eq!(serial, parallel);
+    }
+
+    #[test]
+    fn test_astral_char_roundtrip() {
+        let data = serde_json::json!({"emoji": "smile 😀"});
+
+        let result = encode_value(&data, 0, &EncodeOptions::default()).unwrap();
+        assert_eq!(result, "emoji: \"smile 😀\"");
+        assert_eq!(decode(&result, &DecodeOptions::default()).unwrap(), data);
+
+        let escaped = "emoji: \"smile \\uD83D\\uDE00\"";
+        assert_eq!(decode(escaped, &DecodeOptions::default()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_unescape_rejects_lone_surrogate() {
+        assert!(matches!(
+            unescape_string("\\uD83D"),
+            Err(ToonError::DecodingError(_))
+        ));
+        assert!(matches!(
+            unescape_string("\\uDE00x"),
+            Err(ToonError::DecodingError(_))
+        ));
+        assert_eq!(unescape_string("\\u00e9").unwrap(), "é");
+    }
+
+    #[test]
+    fn test_skip_nulls_in_object() {
+        let data = serde_json::json!({"id": 1, "note": null, "tags": [], "name": ""});
+
+        let opts = EncodeOptions::builder().skip_nulls(true).build();
+        let result = encode_value(&data, 0, &opts).unwrap();
+        assert!(!result.contains("note"));
+        assert!(result.contains("tags:"));
+
+        let opts = EncodeOptions::builder().skip_nulls(true).skip_empty(true).build();
+        let result = encode_value(&data, 0, &opts).unwrap();
+        assert_eq!(result, "id: 1");
+    }
+
+    #[test]
+    fn test_skip_nulls_in_tabular_array() {
+        let data = serde_json::json!({
+            "rows": [
+                {"id": 1, "note": null, "email": null},
+                {"id": 2, "note": null, "email": "b@x.io"}
+            ]
+        });
+
+        let opts = EncodeOptions::builder().skip_nulls(true).build();
+        let result = encode_value(&data, 0, &opts).unwrap();
+
+        // `note` is null in every row; `email` is only sometimes null
+        assert!(result.contains("[2,]{id,email}:"));
+        assert!(result.contains("1,null"));
+        assert!(result.contains("2,b@x.io"));
+    }
+
+    #[test]
+    fn test_error_reports_value_path() {
+        let data = serde_json::json!({
+            "config": {"db": {"hosts": ["a", "b"]}}
+        });
+
+        let opts = EncodeOptions::builder().delimiter(";").build();
+        let err = encode_value(&data, 0, &opts).unwrap_err();
+        assert!(err.to_string().contains("at config.db.hosts"));
+
+        let data = serde_json::json!({"users": [{"id": "a"}, {"id": "too long"}]});
+        let opts = EncodeOptions::builder().max_string_len(3).build();
+        let err = encode_value(&data, 0, &opts).unwrap_err();
+        assert!(err.to_string().contains("at users[1].id"));
+    }
+
+    #[test]
+    fn test_auto_delimiter() {
+        let opts = DecodeOptions {
+            auto_delimiter: true,
+            ..DecodeOptions::default()
+        };
+        let expected = serde_json::json!({
+            "users": [{"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}]
+        });
+
+        for toon in [
+            "users:\n  [2]{id,name}:\n    1,Alice\n    2,Bob",
+            "users:\n  [2]{id|name}:\n    1|Alice\n    2|Bob",
+            "users:\n  [2]{id\tname}:\n    1\tAlice\n    2\tBob",
+        ] {
+            assert_eq!(decode(toon, &opts).unwrap(), expected);
+        }
+
+        let decoded = decode("tags:\n  [3]: a|b|c", &opts).unwrap();
+        assert_eq!(decoded, serde_json::json!({"tags": ["a", "b", "c"]}));
+    }
+
+    #[test]
+    fn test_decode_declared_delimiter() {
+        let data = serde_json::json!({
+            "users": [{"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}],
+            "tags": ["a", "b"]
+        });
+
+        let opts = EncodeOptions::builder().delimiter("|").build();
+        let result = encode_value(&data, 0, &opts).unwrap();
+
+        assert_eq!(decode(&result, &DecodeOptions::default()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_tabular_null_rows_roundtrip() {
+        let data = serde_json::json!({
+            "users": [
+                {"id": 1, "name": "Alice"},
+                null,
+                {"id": 3, "name": "Carol"}
+            ]
+        });
+
+        let result = encode_value(&data, 0, &EncodeOptions::default()).unwrap();
+        assert!(result.contains("- null"));
+
+        for delimiter in [",", "\t"] {
+            let opts = EncodeOptions::builder()
+                .delimiter(delimiter)
+                .tabular_allow_null_rows(true)
+                .build();
+            let result = encode_value(&data, 0, &opts).unwrap();
+            assert!(result.contains("{id"));
+
+            let decode_options = DecodeOptions::builder().tabular_allow_null_rows(true).build();
+            let decoded = decode(&result, &decode_options).unwrap();
+            assert_eq!(decoded, data);
+        }
+
+        // Without the option a row of empty cells is an object of empty values
+        let input = "users[2,]{id,name}:\n  1,Alice\n  ,";
+        assert_eq!(
+            decode(input, &DecodeOptions::default()).unwrap(),
+            serde_json::json!({"users": [{"id": 1, "name": "Alice"}, {"id": "", "name": ""}]})
+        );
+        let empty_as_null = DecodeOptions::builder().empty_cell_as_null(true).build();
+        assert_eq!(
+            decode(input, &empty_as_null).unwrap(),
+            serde_json::json!({"users": [{"id": 1, "name": "Alice"}, {"id": null, "name": null}]})
+        );
+    }
+
+    #[test]
+    fn test_max_bytes_budget() {
+        let rows: Vec<Value> = (0..10_000)
+            .map(|i| serde_json::json!({"id": i, "name": format!("user{}", i)}))
+            .collect();
+        let data = serde_json::json!({"users": rows, "total": 10_000});
+
+        let opts = EncodeOptions::builder().max_bytes(256).build();
+        let err = encode_document(&data, &opts).unwrap_err();
+        assert!(err.to_string().contains("output exceeds max_bytes (256)"));
+
+        let full = encode_document(&data, &EncodeOptions::default()).unwrap();
+        let opts = EncodeOptions::builder().max_bytes(full.len()).build();
+        assert_eq!(encode_document(&data, &opts).unwrap(), full);
+    }
+
+    #[test]
+    fn test_max_bytes_counts_whole_document() {
+        // Each sibling is small and the nested object alone fits the budget,
+        // but together with the text before it the output doesn't
+        let mut body = serde_json::Map::new();
+        for i in 0..50 {
+            body.insert(format!("k{}", i), Value::from(i));
+        }
+        let opts = EncodeOptions::builder().max_bytes(500).max_string_len(350).build();
+        assert!(encode_document(&Value::Object(body.clone()), &opts).is_ok());
+
+        // The budget runs out among the siblings, before the string that's too long
+        body.insert("tail".to_string(), Value::from("x".repeat(400)));
+        let data = serde_json::json!({"head": "h".repeat(300), "body": body});
+        let err = encode_document(&data, &opts).unwrap_err();
+        assert!(err.to_string().contains("output exceeds max_bytes (500)"));
+    }
+
+    #[test]
+    fn test_case_insensitive_key_collision() {
+        let data = serde_json::json!({"Name": "Alice", "id": 1, "name": "Bob"});
+
+        let strict = EncodeOptions::builder().case_insensitive_keys(true).build();
+        let err = encode_value(&data, 0, &strict).unwrap_err();
+        assert!(err.to_string().contains("'Name' and 'name'"));
+
+        let lenient = EncodeOptions::builder()
+            .case_insensitive_keys(true)
+            .strict(false)
+            .build();
+        assert_eq!(encode_value(&data, 0, &lenient).unwrap(), "id: 1\nname: Bob");
+    }
+
+    #[test]
+    fn test_case_insensitive_keys_without_collision() {
+        let data = serde_json::json!({"Name": "Alice", "id": 1});
+
+        let opts = EncodeOptions::builder().case_insensitive_keys(true).build();
+        assert_eq!(encode_value(&data, 0, &opts).unwrap(), "Name: Alice\nid: 1");
+    }
+
+    #[test]
+    fn test_bytes_tokens_roundtrip() {
+        let bytes = b"\x00\x01hi\xff";
+
+        for encoding in [BytesEncoding::Base64, BytesEncoding::Hex] {
+            let token = encode_bytes(bytes, encoding);
+            assert_eq!(decode_bytes(&token).unwrap().unwrap(), bytes);
+
+            let data = serde_json::json!({"payload": token});
+            let result = encode_value(&data, 0, &EncodeOptions::default()).unwrap();
+            assert_eq!(decode(&result, &DecodeOptions::default()).unwrap(), data);
+        }
+
+        assert_eq!(encode_bytes(bytes, BytesEncoding::Hex), "hex:00016869ff");
+        assert_eq!(decode_bytes("plain").unwrap(), None);
+        assert!(decode_bytes("hex:abc").is_err());
+    }
+
+    #[test]
+    fn test_nested_object_under_list_item_roundtrip() {
+        let value = serde_json::json!({"a": {"b": [{"c": {"d": 1}}]}});
+        for indent in [2, 4] {
+            let options = EncodeOptions {
+                indent,
+                ..EncodeOptions::default()
+            };
+            let toon = encode_value(&value, 0, &options).unwrap();
+            let decoded = decode(&toon, &DecodeOptions::default()).unwrap();
+            assert_eq!(decoded, value);
+        }
+    }
+
+    #[test]
+    fn test_mixed_array_roundtrip() {
+        let value = serde_json::json!({
+            "items": [1, {"name": "x", "tags": ["a", "b"], "meta": {"k": true}}, [1, 2]],
+            "after": "ok"
+        });
+        let toon = encode_value(&value, 0, &EncodeOptions::default()).unwrap();
+        let decoded = decode(&toon, &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn test_section_spacing() {
+        let data = serde_json::json!({"name": "app", "db": {"host": "x"}, "cache": {"ttl": 5}});
+        let options = EncodeOptions::builder().section_spacing(true).build();
+        let result = encode_value(&data, 0, &options).unwrap();
+        assert_eq!(result, "name: app\n\ndb:\n  host: x\n\ncache:\n  ttl: 5");
+        assert_eq!(decode(&result, &DecodeOptions::default()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_quoted_header_field_roundtrip() {
+        let data = serde_json::json!({"people": [
+            {"first name": "Ada", "id": 1},
+            {"first name": "Alan", "id": 2}
+        ]});
+        let result = encode_value(&data, 0, &EncodeOptions::default()).unwrap();
+        assert!(result.contains("{\"first name\",id}:"));
+        assert_eq!(decode(&result, &DecodeOptions::default()).unwrap(), data);
+
+        let typed = EncodeOptions::builder().typed_header(true).build();
+        let result = encode_value(&data, 0, &typed).unwrap();
+        assert_eq!(decode(&result, &DecodeOptions::default()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_flatten_roundtrip() {
+        let data = serde_json::json!({"users": [
+            {"id": 1, "address": {"city": "Paris", "geo": {"lat": 48.8}}},
+            {"id": 2, "address": {"city": "Rome", "geo": {"lat": 41.9}}}
+        ]});
+        let flat = flatten(&data, ".").unwrap();
+        let result = encode_value(&flat, 0, &EncodeOptions::default()).unwrap();
+        assert!(result.contains("{id,address.city,address.geo.lat}:"));
+
+        let decoded = decode(&result, &DecodeOptions::default()).unwrap();
+        assert_eq!(unflatten(&decoded, "."), data);
+    }
+
+    #[test]
+    fn test_custom_bool_literals_roundtrip() {
+        let data = serde_json::json!({
+            "on": true,
+            "off": false,
+            "label": "T",
+            "flags": [true, false, true],
+            "rows": [{"id": 1, "ok": true}, {"id": 2, "ok": false}]
+        });
+        let options = EncodeOptions::builder().true_repr("T").false_repr("F").build();
+        let result = encode_value(&data, 0, &options).unwrap();
+        assert!(result.contains("on: T\n"));
+        assert!(result.contains("label: \"T\""));
+
+        let decode_options = DecodeOptions {
+            true_repr: "T".to_string(),
+            false_repr: "F".to_string(),
+            ..DecodeOptions::default()
+        };
+        assert_eq!(decode(&result, &decode_options).unwrap(), data);
+    }
+
+    #[test]
+    fn test_long_number_token_is_string() {
+        let digits = "9".repeat(10_000);
+        let start = std::time::Instant::now();
+        let decoded = decode(&format!("n: {}", digits), &DecodeOptions::default()).unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        assert_eq!(decoded, serde_json::json!({"n": digits}));
+
+        let options = DecodeOptions {
+            max_number_len: 3,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(decode("n: 1234", &options).unwrap(), serde_json::json!({"n": "1234"}));
+        assert_eq!(decode("n: 123", &options).unwrap(), serde_json::json!({"n": 123}));
+    }
+
+    #[test]
+    fn test_unrecognized_line() {
+        let input = "a: 1\n!!garbage\nb: 2";
+        let err = decode(input, &DecodeOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("!!garbage"));
+
+        let lenient = DecodeOptions {
+            strict: false,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(decode(input, &lenient).unwrap(), serde_json::json!({"a": 1, "b": 2}));
+
+        // Comment lines are only skipped under allow_comments
+        let comments = DecodeOptions::builder().allow_comments(true).build();
+        assert_eq!(decode("# note\na: 1", &comments).unwrap(), serde_json::json!({"a": 1}));
+        assert!(decode("# note\na: 1", &DecodeOptions::default()).is_err());
+
+        // Otherwise a leading '#' is read as content, in either mode
+        let input = "#tag: 1\nitems[1]:\n  - #x";
+        let expected = serde_json::json!({"#tag": 1, "items": ["#x"]});
+        assert_eq!(decode(input, &DecodeOptions::default()).unwrap(), expected);
+        assert_eq!(decode(input, &lenient).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_lenient_recovers_from_misplaced_lines() {
+        let lenient = DecodeOptions::builder().strict(false).build();
+
+        // An over-indented field is read as a sibling
+        let input = "a: 1\n    b: 2\nc:\n  d: 3\n      e: 4";
+        assert!(decode(input, &DecodeOptions::default()).unwrap_err().to_string().contains("Unexpected indentation"));
+        assert_eq!(decode(input, &lenient).unwrap(), serde_json::json!({"a": 1, "b": 2, "c": {"d": 3, "e": 4}}));
+
+        // Lines among list items that aren't items are skipped
+        let input = "items[2,]:\n  - 1\n  stray: x\n  - 2\nnext: 3";
+        assert!(decode(input, &DecodeOptions::default()).unwrap_err().to_string().contains("Expected '- ' list item"));
+        assert_eq!(decode(input, &lenient).unwrap(), serde_json::json!({"items": [1, 2], "next": 3}));
+    }
+
+    #[test]
+    fn test_leading_hash_key_is_quoted() {
+        // Read back with allow_comments, an unquoted `#tag: #x` line would be a comment
+        let data = serde_json::json!({"#tag": "#x", "b": 1});
+        let result = encode_value(&data, 0, &EncodeOptions::default()).unwrap();
+        assert_eq!(result, "\"#tag\": \"#x\"\nb: 1");
+        assert_eq!(decode(&result, &DecodeOptions::default()).unwrap(), data);
+        let comments = DecodeOptions::builder().allow_comments(true).build();
+        assert_eq!(decode(&result, &comments).unwrap(), data);
+    }
+
+    #[test]
+    fn test_root_key_roundtrip() {
+        let data = serde_json::json!([{"id": 1}, {"id": 2}]);
+        let options = EncodeOptions::builder().root_key("items").build();
+        let result = encode_document(&data, &options).unwrap();
+        assert!(result.starts_with("items:\n"));
+
+        // Already wrapped roots are left alone
+        let wrapped = serde_json::json!({"items": data.clone()});
+        assert_eq!(encode_document(&wrapped, &options).unwrap(), result);
+
+        let decode_options = DecodeOptions {
+            unwrap_root: Some("items".to_string()),
+            ..DecodeOptions::default()
+        };
+        assert_eq!(decode(&result, &decode_options).unwrap(), data);
+        assert_eq!(decode(&result, &DecodeOptions::default()).unwrap(), wrapped);
+    }
+
+    #[test]
+    fn test_comments_roundtrip() {
+        let input = "# service name\nname: api\ndb:\n  # primary only\n  host: x\nusers:\n  [1,]:\n    # first user\n    - id: 1\n      meta:\n        x: 1";
+        let (value, comments) = decode_with_comments(input, &DecodeOptions::default()).unwrap();
+        assert_eq!(value, serde_json::json!({"name": "api", "db": {"host": "x"}, "users": [{"id": 1, "meta": {"x": 1}}]}));
+        assert_eq!(comments["name"], "service name");
+        assert_eq!(comments["db.host"], "primary only");
+        assert_eq!(comments["users[0].id"], "first user");
+
+        let result = encode_with_comments(&value, &comments, &EncodeOptions::default()).unwrap();
+        assert_eq!(result, input);
+    }
+
+    #[test]
+    fn test_dotted_identifiers() {
+        assert!(is_valid_identifier("a.b"));
+        assert!(!is_valid_identifier("a."));
+        assert!(!is_valid_identifier("a..b"));
+
+        let data = serde_json::json!({"a.b": 1, "a.": 2, "a..b": 3});
+        let result = encode_value(&data, 0, &EncodeOptions::default()).unwrap();
+        assert_eq!(result, "a.b: 1\n\"a.\": 2\n\"a..b\": 3");
+        assert_eq!(decode(&result, &DecodeOptions::default()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_empty_array_markers() {
+        let data = serde_json::json!({"items": [], "nested": {"tags": []}});
+        let cases = [
+            (EncodeOptions::default(), "items:\n  [0,]:"),
+            (EncodeOptions::builder().delimiter("|").build(), "items:\n  [0|]:"),
+            (EncodeOptions::builder().delimiter("\t").build(), "items:\n  [0\t]:"),
+            (EncodeOptions::builder().use_length_markers(false).build(), "items:\n  []:"),
+        ];
+        for (options, expected) in cases {
+            let result = encode_value(&data, 0, &options).unwrap();
+            assert!(result.starts_with(expected), "{result}");
+            assert_eq!(decode(&result, &DecodeOptions::default()).unwrap(), data);
+        }
+    }
+
+    #[cfg(feature = "arbitrary_precision")]
+    #[test]
+    fn test_preserve_number_text() {
+        let input = "price: 1.50\nbig: 123456789012345678901234567890\nplain: 7";
+        let decode_options = DecodeOptions {
+            preserve_number_text: true,
+            ..DecodeOptions::default()
+        };
+        let value = decode(input, &decode_options).unwrap();
+        assert_eq!(value["price"].as_f64(), Some(1.5));
+
+        let options = EncodeOptions::builder().preserve_number_text(true).build();
+        assert_eq!(encode_value(&value, 0, &options).unwrap(), input);
+    }
+
+    #[cfg(not(feature = "arbitrary_precision"))]
+    #[test]
+    fn test_preserve_number_text_needs_feature() {
+        let decode_options = DecodeOptions::builder().preserve_number_text(true).build();
+        let err = decode("price: 1.50", &decode_options).unwrap_err();
+        assert!(err.to_string().contains("arbitrary_precision"));
+    }
+
+    /// Numbers decode and encode the same whether or not the
+    /// `arbitrary_precision` feature is on
+    #[test]
+    fn test_number_normalization_without_preserve_text() {
+        let input = "price: 1.50\nbig: 123456789012345678901234567890\nplain: 7\nexp: 1e3\nneg: -0.25\nmax: 18446744073709551615";
+        let value = decode(input, &DecodeOptions::default()).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"price": 1.5, "big": 1.2345678901234568e29, "plain": 7, "exp": 1000.0, "neg": -0.25, "max": u64::MAX})
+        );
+        assert_eq!(
+            encode_value(&value, 0, &EncodeOptions::default()).unwrap(),
+            "price: 1.5\nbig: 123456789012345680000000000000\nplain: 7\nexp: 1000\nneg: -0.25\nmax: 18446744073709551615"
+        );
+    }
+
+    #[test]
+    fn test_tabular_empty_string_cell_roundtrip() {
+        let data = serde_json::json!({"rows": [
+            {"a": "", "b": "x", "c": ""},
+            {"a": "y", "b": "", "c": "z"},
+            // All-empty rows must not be mistaken for null rows
+            {"a": "", "b": "", "c": ""}
+        ]});
+        for delimiter in [",", "\t", "|"] {
+            let options = EncodeOptions::builder().delimiter(delimiter).build();
+            let result = encode_value(&data, 0, &options).unwrap();
+            assert!(result.contains("\"\""), "{result}");
+            assert_eq!(decode(&result, &DecodeOptions::default()).unwrap(), data);
+
+            let empty_as_null = DecodeOptions {
+                empty_cell_as_null: true,
+                ..DecodeOptions::default()
+            };
+            assert_eq!(decode(&result, &empty_as_null).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_decode_file() {
+        let data = serde_json::json!({
+            "name": "api",
+            "users": [{"id": 1, "name": "Ada"}, {"id": 2, "name": "Alan"}],
+            "nested": {"items": [1, {"k": "v"}]}
+        });
+        let path = std::env::temp_dir().join(format!("toon_tuna_decode_file_{}.toon", std::process::id()));
+        let toon = encode_value(&data, 0, &EncodeOptions::default()).unwrap();
+        std::fs::write(&path, toon.replace('\n', "\r\n")).unwrap();
+
+        let decoded = decode_file(&path, &DecodeOptions::default());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(decoded.unwrap(), data);
+
+        assert!(matches!(
+            decode_file(&path, &DecodeOptions::default()),
+            Err(ToonError::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_unquoted_whitespace_value() {
+        let input = "greeting: hello   world";
+        let err = decode(input, &DecodeOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("hello   world"));
+
+        let lenient = DecodeOptions {
+            strict: false,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(decode(input, &lenient).unwrap(), serde_json::json!({"greeting": "hello   world"}));
+
+        let collapse = DecodeOptions {
+            collapse_whitespace: true,
+            ..lenient
+        };
+        assert_eq!(decode(input, &collapse).unwrap(), serde_json::json!({"greeting": "hello world"}));
+
+        // Quoted values keep their whitespace in every mode
+        assert_eq!(
+            decode("greeting: \"hello   world\"", &collapse).unwrap(),
+            serde_json::json!({"greeting": "hello   world"})
+        );
+    }
+
+    #[test]
+    fn test_control_char_in_unquoted_cell() {
+        let input = "rows[2,]{a,b}:\n  x\ty,1\n  z,2";
+        let err = decode(input, &DecodeOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("U+0009"), "{err}");
+
+        let lenient = DecodeOptions {
+            strict: false,
+            ..DecodeOptions::default()
+        };
+        let decoded = decode(input, &lenient).unwrap();
+        assert_eq!(decoded["rows"][0]["a"], "x\ty");
+
+        // Escaped inside quotes is fine
+        let decoded = decode("rows[1,]{a,b}:\n  \"x\\ty\",1", &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded["rows"][0]["a"], "x\ty");
+    }
+
+    #[test]
+    fn test_first_difference() {
+        let a = serde_json::json!({"a": {"b": [1, 2, {"c": "x"}]}, "d": 1});
+        assert_eq!(first_difference(&a, &a.clone()), None);
+
+        let b = serde_json::json!({"a": {"b": [1, 2, {"c": "y"}]}, "d": 1});
+        assert_eq!(first_difference(&a, &b).as_deref(), Some("a.b[2].c"));
+
+        let b = serde_json::json!({"a": {"b": [1, 2]}, "d": 1});
+        assert_eq!(first_difference(&a, &b).as_deref(), Some("a.b[2]"));
+
+        let b = serde_json::json!({"a": {"b": [1, 2, {"c": "x"}]}, "d": 1, "e": 2});
+        assert_eq!(first_difference(&a, &b).as_deref(), Some("e"));
+
+        assert_eq!(first_difference(&a, &serde_json::json!(1)).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_matrix_mode_roundtrip() {
+        let value = serde_json::json!({"m": [[1, 2, 3], [4, 5.5, -6]], "ragged": [[1, 2], [3]]});
+        let options = EncodeOptions::builder().matrix_mode(true).build();
+        let encoded = encode_document(&value, &options).unwrap();
+        assert!(encoded.starts_with("m:\n  [2,][3,]:\n    1,2,3\n    4,5.5,-6\n"));
+        // Ragged arrays keep the expanded form
+        assert!(encoded.contains("ragged:\n  [2,]:\n    - [2,]: 1,2"));
+
+        let decoded = decode(&encoded, &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded, value);
+
+        let err = decode("m:\n  [2,][3,]:\n    1,2,3\n    4,5\n", &DecodeOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("Matrix row has 2 cells, expected 3"));
+    }
+
+    #[test]
+    fn test_custom_null_literal_roundtrip() {
+        let data = serde_json::json!({
+            "missing": null,
+            "tilde": "~",
+            "word": "null",
+            "cells": [null, "~", 1],
+            "rows": [{"id": 1, "note": null}, {"id": 2, "note": "~"}]
+        });
+        let options = EncodeOptions::builder().null_repr("~").build();
+        let result = encode_value(&data, 0, &options).unwrap();
+        assert!(result.contains("missing: ~\n"));
+        assert!(result.contains("tilde: \"~\"\n"));
+        assert!(result.contains("word: \"null\"\n"));
+
+        let decode_options = DecodeOptions::builder().null_repr("~").build();
+        assert_eq!(decode(&result, &decode_options).unwrap(), data);
+    }
+
+    #[test]
+    fn test_flatten_paths_roundtrip() {
+        let data = serde_json::json!({"db": {"host": "localhost", "pool": {"min": 1, "max": 8}}, "debug": true});
+        let options = EncodeOptions::builder().flatten_paths(true).build();
+        let encoded = encode_document(&data, &options).unwrap();
+        assert_eq!(encoded, "db.host: localhost\ndb.pool.min: 1\ndb.pool.max: 8\ndebug: true");
+
+        let decode_options = DecodeOptions::builder().expand_paths(true).build();
+        assert_eq!(decode(&encoded, &decode_options).unwrap(), data);
+
+        // Flattened paths need no indentation; nesting left after them does
+        let flat = EncodeOptions::builder().flatten_paths(true).indent(0).build();
+        assert_eq!(encode_document(&data, &flat).unwrap(), encoded);
+        let nested = serde_json::json!({"a": {"b": 1}});
+        let err = encode_document(&nested, &EncodeOptions::builder().indent(0).build()).unwrap_err();
+        assert!(err.to_string().contains("indent 0"), "{}", err);
+        assert!(encode_document(&serde_json::json!({"a": [{"x": 1}]}), &flat).is_err());
+        assert!(encode_document(&serde_json::json!({"a": [1, 2]}), &flat).is_ok());
+    }
+
+    #[test]
+    fn test_expand_paths_keeps_literal_dotted_keys() {
+        let toon = "\"a.b\": 1\nc.d: 2\nrows[2,]{x.y,z}:\n  1,2\n  3,4\nitems[1]:\n  - e.f: 5";
+        let decode_options = DecodeOptions::builder().expand_paths(true).build();
+        assert_eq!(
+            decode(toon, &decode_options).unwrap(),
+            serde_json::json!({
+                "a.b": 1,
+                "c": {"d": 2},
+                "rows": [{"x.y": 1, "z": 2}, {"x.y": 3, "z": 4}],
+                "items": [{"e.f": 5}]
+            })
+        );
+
+        // A path through a scalar is a conflict in strict mode
+        assert!(decode("c: 1\nc.d: 2", &decode_options).is_err());
+    }
+
+    #[test]
+    fn test_flatten_paths_rejects_literal_dotted_key() {
+        let options = EncodeOptions::builder().flatten_paths(true).build();
+        let err = encode_document(&serde_json::json!({"db": {"a.b": 1}}), &options).unwrap_err();
+        assert!(err.to_string().contains("Key 'a.b' contains the path separator '.'"));
+
+        // Keys inside arrays aren't flattened or expanded, so their dots are kept
+        let data = serde_json::json!({"db": {"host": "h"}, "rows": [{"x.y": 1}, {"x.y": 2}], "items": [{"e.f": {"g": 1}}]});
+        let encoded = encode_document(&data, &options).unwrap();
+        let decode_options = DecodeOptions::builder().expand_paths(true).build();
+        assert_eq!(decode(&encoded, &decode_options).unwrap(), data);
+
+        assert!(flatten(&serde_json::json!({"a.b": 1}), ".").is_err());
+        assert!(flatten(&serde_json::json!({"a.b": 1}), "/").is_ok());
+    }
+
+    #[test]
+    fn test_sorted_keys_in_expanded_items() {
+        let data = serde_json::json!({
+            "b": 1,
+            "items": [{"z": 1, "a": {"y": 2, "x": 1}}, {"m": 1}],
+            "a": 2
+        });
+        let options = EncodeOptions::builder().sort_keys(true).build();
+        let encoded = encode_document(&data, &options).unwrap();
+        assert_eq!(
+            encoded,
+            "a: 2\nb: 1\nitems:\n  [2,]:\n    - a:\n        x: 1\n        y: 2\n      z: 1\n    - m: 1"
+        );
+
+        let options = EncodeOptions::builder().key_order(vec!["id".to_string()]).sort_keys(true).build();
+        let rows = serde_json::json!([{"name": "a", "id": 1, "age": 3}]);
+        assert_eq!(encode_document(&rows, &options).unwrap(), "[1,]{id,age,name}:\n  1,3,a");
+    }
+
+    #[test]
+    fn test_non_finite_number_tokens() {
+        for token in ["inf", "-inf", "nan", "1e999"] {
+            let err = decode(&format!("x: {}", token), &DecodeOptions::default()).unwrap_err();
+            assert!(err.to_string().contains("is not finite"), "{}", token);
+
+            let lenient = DecodeOptions::builder().strict(false).build();
+            let decoded = decode(&format!("x: {}", token), &lenient).unwrap();
+            assert_eq!(decoded, serde_json::json!({"x": token}));
+        }
+
+        // Quoted, they are ordinary strings
+        let decoded = decode("x: \"nan\"", &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded, serde_json::json!({"x": "nan"}));
+    }
+
+    #[test]
+    fn test_leading_bom_is_stripped() {
+        let decoded = decode("\u{FEFF}name: Alice\nid: 1", &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded, serde_json::json!({"name": "Alice", "id": 1}));
+
+        // A key that really starts with a BOM is escaped rather than written raw
+        let value = serde_json::json!({"\u{FEFF}a": "\u{FEFF}"});
+        let encoded = encode_document(&value, &EncodeOptions::default()).unwrap();
+        assert_eq!(encoded, "\"\\ufeffa\": \"\\ufeff\"");
+        assert_eq!(decode(&encoded, &DecodeOptions::default()).unwrap(), value);
+    }
+
+    #[test]
+    fn test_encode_preview_truncates() {
+        let data = serde_json::json!({
+            "ids": (1..=12).collect::<Vec<_>>(),
+            "rows": [{"id": 1}, {"id": 2}, {"id": 3}],
+            "note": "abcdefghijklmnop"
+        });
+        let preview = PreviewConfig { max_items: 2, max_chars: 5 };
+        let encoded = encode_preview(&data, preview, &EncodeOptions::default()).unwrap();
+        assert_eq!(
+            encoded,
+            "ids:\n  [3,]: 1,2,\"…(+10 more)\"\nrows:\n  [3,]:\n    - id: 1\n    - id: 2\n    - \"…(+1 more)\"\nnote: \"abcde…(+11 more)\""
+        );
+
+        // Markers are counted in the headers, so a preview decodes strictly
+        assert_eq!(
+            decode(&encoded, &DecodeOptions::default()).unwrap(),
+            serde_json::json!({
+                "ids": [1, 2, "…(+10 more)"],
+                "rows": [{"id": 1}, {"id": 2}, "…(+1 more)"],
+                "note": "abcde…(+11 more)"
+            })
+        );
+
+        // Short values are left alone
+        let data = serde_json::json!({"ids": [1, 2], "note": "abc"});
+        assert_eq!(encode_preview(&data, preview, &EncodeOptions::default()).unwrap(), "ids:\n  [2,]: 1,2\nnote: abc");
+    }
+
+    #[test]
+    fn test_decode_without_space_after_colon() {
+        let input = "id:1\nname:Alice\ntags[2,]:a,b\nusers[2,]{id,name}:\n  1,Bob\n  2,Carol\nitems[2,]:\n  - k:1\n    v:x\n  - [2,]:3,4\nmeta:\n  ok:true";
+        let decoded = decode(input, &DecodeOptions::default()).unwrap();
+        assert_eq!(
+            decoded,
+            serde_json::json!({
+                "id": 1,
+                "name": "Alice",
+                "tags": ["a", "b"],
+                "users": [{"id": 1, "name": "Bob"}, {"id": 2, "name": "Carol"}],
+                "items": [{"k": 1, "v": "x"}, [3, 4]],
+                "meta": {"ok": true}
+            })
+        );
+
+        assert_eq!(decode("[2,]:1,2", &DecodeOptions::default()).unwrap(), serde_json::json!([1, 2]));
+        assert_eq!(decode("\"a b\":\"c d\"", &DecodeOptions::default()).unwrap(), serde_json::json!({"a b": "c d"}));
+    }
+
+    #[test]
+    fn test_escape_delimiter_inline_roundtrip() {
+        let data = serde_json::json!({
+            "note": "a,b",
+            "tags": ["x,y", "z"],
+            "rows": [{"id": 1, "name": "Smith,J"}, {"id": 2, "name": "Doe"}]
+        });
+        let options = EncodeOptions::builder().escape_delimiter_inline(true).build();
+        let encoded = encode_document(&data, &options).unwrap();
+        assert_eq!(encoded, "note: a\\,b\ntags:\n  [2,]: x\\,y,z\nrows:\n  [2,]{id,name}:\n    1,Smith\\,J\n    2,Doe");
+        assert_eq!(decode(&encoded, &DecodeOptions::default()).unwrap(), data);
+
+        let options = EncodeOptions::builder().escape_delimiter_inline(true).delimiter("\t").build();
+        let data = serde_json::json!({"tags": ["a\tb", "c|d"]});
+        let encoded = encode_document(&data, &options).unwrap();
+        assert_eq!(encoded, "tags:\n  [2\t]: a\\tb\tc|d");
+        assert_eq!(decode(&encoded, &DecodeOptions::default()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_savings() {
+        let value = serde_json::json!({"users": [{"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}]});
+        let options = EncodeOptions::default();
+        let savings = savings(&value, &options).unwrap();
+
+        assert_eq!(savings.json_bytes, serde_json::to_string(&value).unwrap().len());
+        assert_eq!(savings.toon_bytes, encode_document(&value, &options).unwrap().len());
+        assert!(savings.ratio() < 1.0);
+    }
+
+    #[test]
+    fn test_inline_array_trailing_delimiter() {
+        let err = decode("nums[3,]: 1,2,3,", &DecodeOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("Trailing delimiter after inline array of 3 items"));
+
+        let lenient = DecodeOptions::builder().strict(false).build();
+        assert_eq!(decode("nums[3,]: 1,2,3,", &lenient).unwrap(), serde_json::json!({"nums": [1, 2, 3]}));
+        assert_eq!(decode("tags[2|]: a|b|", &lenient).unwrap(), serde_json::json!({"tags": ["a", "b"]}));
+
+        // A quoted empty string is a real item
+        assert_eq!(
+            decode("tags[2,]: a,\"\"", &DecodeOptions::default()).unwrap(),
+            serde_json::json!({"tags": ["a", ""]})
+        );
+    }
+
+    #[test]
+    fn test_tabular_header_duplicate_field() {
+        let input = "rows[1,]{id,id,name}:\n  1,2,a";
+        let err = decode(input, &DecodeOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("Duplicate field 'id' in tabular header"));
+
+        // Lenient mode keeps the last column, as before
+        let lenient = DecodeOptions::builder().strict(false).build();
+        assert_eq!(decode(input, &lenient).unwrap(), serde_json::json!({"rows": [{"id": 2, "name": "a"}]}));
+    }
+
+    #[test]
+    fn test_compact_numbers_roundtrip() {
+        let options = EncodeOptions::builder().compact_numbers(true).build();
+        let cases = [
+            (0.1, "0.1"),
+            (1e-10, "1e-10"),
+            (1.5e300, "1.5e300"),
+            (123456.789, "123456.789"),
+            (-2.5e-7, "-2.5e-7"),
+            (1e16, "1e16"),
+            (0.30000000000000004, "0.30000000000000004"),
+        ];
+        for (f, expected) in cases {
+            let value = serde_json::json!({"x": f});
+            let encoded = encode_document(&value, &options).unwrap();
+            assert_eq!(encoded, format!("x: {}", expected));
+
+            let decoded = decode(&encoded, &DecodeOptions::default()).unwrap();
+            assert_eq!(decoded["x"].as_f64(), Some(f));
+            assert!(expected.len() <= f.to_string().len());
+        }
+    }
+
+    #[test]
+    fn test_streaming_decoder_chunks() {
+        let mut decoder = StreamingDecoder::new(DecodeOptions::default());
+        decoder.feed("id: 1\nusers[2,]{id,na").unwrap();
+        assert!(decoder.needs_more());
+        assert_eq!(decoder.snapshot().unwrap(), serde_json::json!({"id": 1}));
+
+        decoder.feed("me}:\n  1,Alice\n  2,Bob").unwrap();
+        let value = decoder.finish().unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"id": 1, "users": [{"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}]})
+        );
+
+        // Finishing resets the decoder for the next document
+        decoder.feed("x: 2\n").unwrap();
+        assert!(!decoder.needs_more());
+        assert_eq!(decoder.finish().unwrap(), serde_json::json!({"x": 2}));
+    }
+
+    #[test]
+    fn test_streaming_decoder_snapshot_mid_block() {
+        let mut decoder = StreamingDecoder::new(DecodeOptions::default());
+        decoder.feed("users[2,]{id,name}:\n  1,Alice\n").unwrap();
+        assert!(decoder.needs_more());
+        assert_eq!(decoder.snapshot().unwrap(), serde_json::json!({"users": [{"id": 1, "name": "Alice"}]}));
+
+        // A row cut off before its last cell
+        decoder.feed("  2\n").unwrap();
+        assert_eq!(
+            decoder.snapshot().unwrap(),
+            serde_json::json!({"users": [{"id": 1, "name": "Alice"}, {"id": 2}]})
+        );
+
+        let mut decoder = StreamingDecoder::new(DecodeOptions::default());
+        decoder.feed("users[2,]{id,name}:\n  1,Alice\n  2,Bob\n").unwrap();
+        assert!(!decoder.needs_more());
+        decoder.feed("tags[3,]:\n  - a\n").unwrap();
+        assert!(decoder.needs_more());
+        assert_eq!(decoder.snapshot().unwrap()["tags"], serde_json::json!(["a"]));
+
+        // finish still holds tables to their declared length
+        decoder.feed("rows[2,]{k}:\n  1\n").unwrap();
+        let err = decoder.finish().unwrap_err();
+        assert!(err.to_string().contains("found 1"), "{}", err);
+    }
+
+    #[test]
+    fn test_streaming_decoder_closed_fields() {
+        let mut decoder = StreamingDecoder::new(DecodeOptions::default());
+        decoder.feed("a: 1\nb:\n  c: 2\nd[1,]: x\n").unwrap();
+        assert_eq!(decoder.snapshot().unwrap(), serde_json::json!({"a": 1, "b": {"c": 2}, "d": ["x"]}));
+
+        // A top-level field is checked once the next one starts
+        let err = decoder.feed("e[2,]{k}:\n  1\nf: 2\n").unwrap_err();
+        assert!(err.to_string().contains("at line 5"), "{}", err);
+
+        let mut decoder = StreamingDecoder::new(DecodeOptions::default());
+        decoder.feed("a: 1\nb: 2\na: 3\n").unwrap();
+        let err = decoder.finish().unwrap_err();
+        assert_eq!(err.to_string(), "Decoding error: Duplicate key 'a' at line 3");
+
+        // Root arrays and unwrap_root see the whole document
+        let options = DecodeOptions::builder().unwrap_root("data").build();
+        let mut decoder = StreamingDecoder::new(options);
+        decoder.feed("data[2,]:\n  - 1\n  - 2\n").unwrap();
+        assert_eq!(decoder.finish().unwrap(), serde_json::json!([1, 2]));
+    }
+
+    #[test]
+    fn test_length_marker_styles() {
+        let value = serde_json::json!({
+            "tags": ["a", "b"],
+            "users": [{"id": 1}, {"id": 2}],
+            "items": [1, {"k": "v"}],
+            "none": []
+        });
+
+        let options = EncodeOptions::builder().length_marker_style(LengthMarkerStyle::Inline).build();
+        assert_eq!(
+            encode_document(&value, &options).unwrap(),
+            "tags:\n  [2,]: a,b\nusers:\n  [2,]{id}:\n    1\n    2\nitems:\n  [2,]:\n    - 1\n    - k: v\nnone:\n  [0,]:"
+        );
+
+        let options = EncodeOptions::builder()
+            .length_marker_style(LengthMarkerStyle::TrailingComment)
+            .build();
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(
+            encoded,
+            "tags:\n  []: a,b  # 2 items\nusers:\n  []{id}:  # 2 items\n    1\n    2\nitems:\n  []:  # 2 items\n    - 1\n    - k: v\nnone:\n  []:  # 0 items"
+        );
+        let decode_options = DecodeOptions::builder().allow_comments(true).build();
+        assert_eq!(decode(&encoded, &decode_options).unwrap(), value);
+
+        let options = EncodeOptions::builder().length_marker_style(LengthMarkerStyle::Omitted).build();
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(
+            encoded,
+            "tags:\n  []: a,b\nusers:\n  []{id}:\n    1\n    2\nitems:\n  []:\n    - 1\n    - k: v\nnone:\n  []:"
+        );
+        assert_eq!(decode(&encoded, &DecodeOptions::default()).unwrap(), value);
+    }
+
+    #[test]
+    fn test_allow_comments_keeps_quoted_hash() {
+        let options = DecodeOptions::builder().allow_comments(true).build();
+        let decoded = decode("a: \"x # y\"  # note\nb: c#d", &options).unwrap();
+        assert_eq!(decoded, serde_json::json!({"a": "x # y", "b": "c#d"}));
+    }
+
+    #[test]
+    fn test_valueless_key_without_children_is_empty_object() {
+        let options = DecodeOptions::default();
+        assert_eq!(decode("a:\nb: 1", &options).unwrap(), serde_json::json!({"a": {}, "b": 1}));
+        assert_eq!(decode("b: 1\na:", &options).unwrap(), serde_json::json!({"b": 1, "a": {}}));
+        assert_eq!(
+            decode("outer:\n  a:\n  b: 1\nc: 2", &options).unwrap(),
+            serde_json::json!({"outer": {"a": {}, "b": 1}, "c": 2})
+        );
+        assert_eq!(
+            decode("items[2,]:\n  - a:\n    b: 1\n  - c: 2", &options).unwrap(),
+            serde_json::json!({"items": [{"a": {}, "b": 1}, {"c": 2}]})
+        );
+
+        // Same shape the encoder writes for empty objects
+        let value = serde_json::json!({"a": {}, "b": 1});
+        assert_eq!(encode_document(&value, &EncodeOptions::default()).unwrap(), "a:\nb: 1");
+    }
+
+    #[test]
+    fn test_max_tabular_columns() {
+        let row = |i: i64| serde_json::json!({"a": i, "b": i, "c": i, "d": i, "e": i});
+        let value = serde_json::json!({"rows": [row(1), row(2)]});
+
+        let options = EncodeOptions::builder().max_tabular_columns(5).build();
+        let encoded = encode_document(&value, &options).unwrap();
+        assert!(encoded.starts_with("rows:\n  [2,]{a,b,c,d,e}:\n    1,1,1,1,1"));
+
+        let options = EncodeOptions::builder().max_tabular_columns(4).build();
+        let encoded = encode_document(&value, &options).unwrap();
+        assert!(encoded.starts_with("rows:\n  [2,]:\n    - a: 1\n      b: 1"));
+        assert_eq!(decode(&encoded, &DecodeOptions::default()).unwrap(), value);
+    }
+
+    #[test]
+    fn test_underscore_numbers() {
+        let options = DecodeOptions::builder().allow_underscore_numbers(true).build();
+        let decoded = decode("a: 1_000\nb: -1_000_000\nc: 2.718_5\nd: _1\ne: 1_\nf: 1__0\ng: a_b", &options).unwrap();
+        assert_eq!(
+            decoded,
+            serde_json::json!({"a": 1000, "b": -1000000, "c": 2.7185, "d": "_1", "e": "1_", "f": "1__0", "g": "a_b"})
+        );
+
+        // Off by default
+        assert_eq!(
+            decode("a: 1_000", &DecodeOptions::default()).unwrap(),
+            serde_json::json!({"a": "1_000"})
+        );
+    }
+
+    #[test]
+    fn test_decode_with_meta_detects_indent() {
+        let input = "# settings\nuser:\n    name: Alice\n    tags[2,]: a,b";
+        let options = DecodeOptions::builder().allow_comments(true).build();
+        let (value, meta) = decode_with_meta(input, &options).unwrap();
+        assert_eq!(value, serde_json::json!({"user": {"name": "Alice", "tags": ["a", "b"]}}));
+        assert_eq!(meta.indent, Some(4));
+        assert_eq!(meta.comments.get("user").map(String::as_str), Some("settings"));
+
+        // Re-encoding with the detected width gives the source back
+        let options = EncodeOptions::builder().indent(meta.indent.unwrap()).build();
+        assert_eq!(encode_document(&value, &options).unwrap(), "user:\n    name: Alice\n    tags:\n        [2,]: a,b");
+
+        let (_, meta) = decode_with_meta("a: 1", &DecodeOptions::default()).unwrap();
+        assert_eq!(meta.indent, None);
+    }
+
+    #[test]
+    fn test_decode_zero_row_tabular() {
+        let options = DecodeOptions::default();
+        assert_eq!(
+            decode("users[0,]{id,name}:\nnext: 1", &options).unwrap(),
+            serde_json::json!({"users": [], "next": 1})
+        );
+        assert_eq!(decode("users[0,]{id,name}:", &options).unwrap(), serde_json::json!({"users": []}));
+        assert_eq!(decode("[0,]{id}:", &options).unwrap(), serde_json::json!([]));
+        assert_eq!(
+            decode("items[1,]:\n  - rows[0,]{id}:\n    k: v", &options).unwrap(),
+            serde_json::json!({"items": [{"rows": [], "k": "v"}]})
+        );
+    }
+
+    #[test]
+    fn test_decode_deep_nesting_errors() {
+        // 100k nested keys, generated lazily so the decoder only ever reads
+        // as far as the depth check lets it
+        let options = DecodeOptions::default();
+        let lines = (0..100_000).map(|i| Ok(Cow::Owned(format!("{}k:", " ".repeat(i)))));
+        let err = Decoder::new(lines, &options).finish().unwrap_err();
+        assert_eq!(err.to_string(), "Decoding error: Nesting deeper than max_depth 256 at line 257");
+
+        // The deepest document the default allows still decodes
+        let lines = (0..256).map(|i| Ok(Cow::Owned(format!("{}k:", " ".repeat(i)))));
+        assert!(Decoder::new(lines, &options).finish().is_ok());
+
+        let lines = (0..100_000).map(|i| match i {
+            0 => Ok(Cow::Borrowed("[1,]:")),
+            _ => Ok(Cow::Owned(format!("{}- [1,]:", " ".repeat(i)))),
+        });
+        let err = Decoder::new(lines, &options).finish().unwrap_err();
+        assert!(err.to_string().contains("Nesting deeper than max_depth 256"));
+
+        let options = DecodeOptions::builder().max_depth(2).build();
+        assert_eq!(decode("a:\n  b:\n    c: 1", &options).unwrap(), serde_json::json!({"a": {"b": {"c": 1}}}));
+        assert!(decode("a:\n  b:\n    c:\n      d: 1", &options).is_err());
+        assert!(decode("[1,]:\n  - [1,]:\n    - [1,]:\n      - x", &options).is_err());
+    }
+
+    #[test]
+    fn test_normalize_equivalent_documents() {
+        let options = DecodeOptions::default();
+        let a = "name: Alice\ntags[2,]: x,y\nusers[2,]{id,role}:\n  1,admin\n  2,user";
+        let b = "users:\n    [2|]{role|id}:\n        admin|1\n        user|2\ntags:\n    [2\t]: \"x\"\t\"y\"\nname: \"Alice\"";
 
-            Ok(result)
-        }
+        let normalized = normalize(a, &options).unwrap();
+        assert_eq!(normalized, normalize(b, &options).unwrap());
+        assert_eq!(normalized, "name: Alice\ntags:\n  [2,]: x,y\nusers:\n  [2,]{id,role}:\n    1,admin\n    2,user");
+
+        // Already canonical text is a fixed point
+        assert_eq!(normalize(&normalized, &options).unwrap(), normalized);
     }
-}
 
-/// Unescape a TOON string
-fn unescape_string(s: &str) -> Result<String, ToonError> {
-    let mut result = String::new();
-    let mut chars = s.chars();
+    #[test]
+    fn test_decode_delimiter_only_cell() {
+        let options = DecodeOptions::default();
+        assert_eq!(
+            decode("rows[1,]{a,b,c}:\n  x,\",\",y", &options).unwrap(),
+            serde_json::json!({"rows": [{"a": "x", "b": ",", "c": "y"}]})
+        );
+        assert_eq!(
+            decode("rows[1,]{a,b,c}:\n  \",x\",\",\",\"y,\"", &options).unwrap(),
+            serde_json::json!({"rows": [{"a": ",x", "b": ",", "c": "y,"}]})
+        );
+        assert_eq!(
+            decode("rows[1|]{a|b|c}:\n  x|\"|\"|y", &options).unwrap(),
+            serde_json::json!({"rows": [{"a": "x", "b": "|", "c": "y"}]})
+        );
 
-    while let Some(ch) = chars.next() {
-        if ch == '\\' {
-            match chars.next() {
-                Some('\\') => result.push('\\'),
-                Some('"') => result.push('"'),
-                Some('n') => result.push('\n'),
-                Some('r') => result.push('\r'),
-                Some('t') => result.push('\t'),
-                Some(other) => {
-                    return Err(ToonError::DecodingError(format!(
-                        "Invalid escape sequence: \\{}",
-                        other
-                    )))
-                }
-                None => {
-                    return Err(ToonError::DecodingError(
-                        "Unterminated escape sequence".to_string(),
-                    ))
-                }
-            }
-        } else {
-            result.push(ch);
-        }
+        let value = serde_json::json!({"rows": [{"a": "x", "b": ",", "c": "y"}]});
+        let encoded = encode_document(&value, &EncodeOptions::default()).unwrap();
+        assert_eq!(decode(&encoded, &options).unwrap(), value);
     }
 
-    Ok(result)
-}
+    #[test]
+    fn test_index_expanded_items_roundtrip() {
+        let value = serde_json::json!({"items": [{"id": 1, "tags": ["a"]}, "x", [1, 2], {}, {"id": 2}]});
+        let options = EncodeOptions::builder().index_expanded_items(true).build();
 
-/// Parse a value from a TOON string
-fn parse_value(s: &str, _delimiter: &str) -> Result<Value, ToonError> {
-    let s = s.trim();
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(
+            encoded,
+            "items:\n  [5,]:\n    - [0] id: 1\n      tags:\n        [1,]: a\n    - [1] x\n    - [2] [2,]: 1,2\n    - [3]\n    - [4] id: 2"
+        );
+        assert_eq!(decode(&encoded, &DecodeOptions::default()).unwrap(), value);
 
-    if s.is_empty() {
-        return Ok(Value::String(String::new()));
+        // Strings that look like an index are quoted, so they survive too
+        let value = serde_json::json!(["[0] x", "[1]", {"a": 1}]);
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(decode(&encoded, &DecodeOptions::default()).unwrap(), value);
     }
 
-    // Quoted string
-    if s.starts_with('"') && s.ends_with('"') {
-        let inner = &s[1..s.len() - 1];
-        return Ok(Value::String(unescape_string(inner)?));
+    #[test]
+    fn test_decode_allow_blank_rows() {
+        let input = "users[3,]{id,name}:\n  1,Alice\n\n  2,Bob\n  \n\n  3,Cara\n\nnext: 1";
+        let expected = serde_json::json!({
+            "users": [{"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}, {"id": 3, "name": "Cara"}],
+            "next": 1
+        });
+        let options = DecodeOptions::builder().allow_blank_rows(true).build();
+        assert_eq!(decode(input, &options).unwrap(), expected);
+
+        // By default the first blank line ends the table
+        assert!(decode(input, &DecodeOptions::default()).is_err());
+        assert_eq!(
+            decode("m[2,][2,]:\n  1,2\n\n  3,4", &options).unwrap(),
+            serde_json::json!({"m": [[1, 2], [3, 4]]})
+        );
     }
 
-    // Boolean
-    if s == "true" {
-        return Ok(Value::Bool(true));
+    #[test]
+    fn test_tuple_marker_roundtrip() {
+        let point = tag_tuple(serde_json::json!([1, 2.5, "x"]));
+        let value = serde_json::json!({"point": point, "path": [point, "end"], "label": "(2)"});
+        let options = EncodeOptions::builder().tuple_marker(true).build();
+
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(
+            encoded,
+            "point:\n  (3,): 1,2.5,x\npath:\n  [2,]:\n    - (3,): 1,2.5,x\n    - end\nlabel: \"(2)\""
+        );
+
+        let decode_options = DecodeOptions::builder().tuple_marker(true).build();
+        assert_eq!(decode(&encoded, &decode_options).unwrap(), value);
+        assert_eq!(decode("(0,):", &decode_options).unwrap(), tag_tuple(serde_json::json!([])));
+
+        // Without the option a tuple line is an ordinary key
+        assert_eq!(decode("(2,): 1", &DecodeOptions::default()).unwrap(), serde_json::json!({"(2,)": 1}));
     }
-    if s == "false" {
-        return Ok(Value::Bool(false));
+
+    #[test]
+    fn test_rename_keys() {
+        let rename = std::collections::HashMap::from([("user_id".to_string(), "id".to_string())]);
+        let options = EncodeOptions::builder().rename_keys(rename).build();
+
+        let value = serde_json::json!({
+            "users": [{"user_id": 1, "name": "Alice"}, {"user_id": 2, "name": "Bob"}],
+            "owner": {"user_id": 3}
+        });
+        assert_eq!(
+            encode_document(&value, &options).unwrap(),
+            "users:\n  [2,]{id,name}:\n    1,Alice\n    2,Bob\nowner:\n  id: 3"
+        );
+
+        // Renaming onto an existing key collides
+        let value = serde_json::json!({"user_id": 1, "id": 2});
+        assert!(encode_document(&value, &options).is_err());
+        let value = serde_json::json!({"rows": [{"user_id": 1, "id": 2}]});
+        assert!(encode_document(&value, &options).is_err());
     }
 
-    // Null
-    if s == "null" {
-        return Ok(Value::Null);
+    #[test]
+    fn test_decode_strict_tabular_shape() {
+        let strict = DecodeOptions::default();
+        let lenient = DecodeOptions::builder().strict(false).build();
+
+        let short = "users[2,]{id,name}:\n  1,Alice\n  2";
+        let err = decode(short, &strict).unwrap_err();
+        assert_eq!(err.to_string(), "Decoding error: Tabular row has 1 cells, expected 2 at line 3");
+        assert_eq!(
+            decode(short, &lenient).unwrap(),
+            serde_json::json!({"users": [{"id": 1, "name": "Alice"}, {"id": 2}]})
+        );
+
+        let long = "users[1,]{id,name}:\n  1,Alice,admin";
+        let err = decode(long, &strict).unwrap_err();
+        assert_eq!(err.to_string(), "Decoding error: Tabular row has 3 cells, expected 2 at line 2");
+
+        let missing_row = "users[3,]{id,name}:\n  1,Alice\n  2,Bob";
+        let err = decode(missing_row, &strict).unwrap_err();
+        assert_eq!(err.to_string(), "Decoding error: Tabular header declares 3 rows, found 2 at line 1");
+        assert_eq!(decode(missing_row, &lenient).unwrap()["users"].as_array().unwrap().len(), 2);
+
+        // Headers without a count are not checked
+        assert!(decode("users[]{id}:\n  1\n  2", &strict).is_ok());
     }
 
-    // Number
-    if let Ok(i) = s.parse::<i64>() {
-        return Ok(Value::Number(i.into()));
+    #[test]
+    fn test_number_formatting_ignores_locale() {
+        let options = EncodeOptions::default();
+        assert_eq!(encode_value(&serde_json::json!(1234.5), 0, &options).unwrap(), "1234.5");
+        assert_eq!(encode_value(&serde_json::json!(1000000), 0, &options).unwrap(), "1000000");
+        assert_eq!(encode_value(&serde_json::json!(-9876543.25), 0, &options).unwrap(), "-9876543.25");
+
+        // The shortest-form path keeps the same invariant
+        let compact = EncodeOptions::builder().compact_numbers(true).build();
+        assert_eq!(encode_value(&serde_json::json!(1234.5), 0, &compact).unwrap(), "1234.5");
+        assert_eq!(encode_value(&serde_json::json!(1000000), 0, &compact).unwrap(), "1000000");
     }
-    if let Ok(f) = s.parse::<f64>() {
-        if let Some(n) = serde_json::Number::from_f64(f) {
-            return Ok(Value::Number(n));
+
+    #[test]
+    fn test_decode_schema() {
+        let input = "name: Demo\nusers[2,]{id,name}:\n  1,Alice\n  2,Bob\nstats:\n  orders:\n    [2|]{order_id|\"total $\":float}:\n      1|9.5\n      2|3\n  count: 2\n  tags:\n    [2,]: a,b\n  empty:\nlog[2,]:\n  - x: 1\n  - y";
+        assert_eq!(
+            decode_schema(input).unwrap(),
+            serde_json::json!({
+                "name": "scalar",
+                "users": ["id", "name"],
+                "stats": {"orders": ["order_id", "total $"], "count": "scalar", "tags": "array", "empty": {}},
+                "log": "array"
+            })
+        );
+
+        assert_eq!(decode_schema("[2,]{id}:\n  1\n  2").unwrap(), serde_json::json!(["id"]));
+        assert_eq!(decode_schema("= 5").unwrap(), serde_json::json!("scalar"));
+        assert_eq!(decode_schema("").unwrap(), serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_list_marker_roundtrip() {
+        let value = serde_json::json!({"items": [{"id": 1, "tags": ["a"]}, "*x", [1, 2], {}, -3, "- y"]});
+        let options = EncodeOptions::builder().list_marker("* ").build();
+
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(
+            encoded,
+            "items:\n  [6,]:\n    * id: 1\n      tags:\n        [1,]: a\n    * \"*x\"\n    * [2,]: 1,2\n    *\n    * -3\n    * \"- y\""
+        );
+        let decode_options = DecodeOptions::builder().list_marker("* ").build();
+        assert_eq!(decode(&encoded, &decode_options).unwrap(), value);
+
+        // The default decoder doesn't take `*` for a list marker
+        assert!(decode(&encoded, &DecodeOptions::default()).is_err());
+
+        for marker in ["", "  ", "a ", ": ", "# ", "[ "] {
+            let options = EncodeOptions::builder().list_marker(marker).build();
+            assert!(encode_document(&value, &options).is_err(), "{:?}", marker);
+            let options = DecodeOptions::builder().list_marker(marker).build();
+            assert!(decode("a: 1", &options).is_err(), "{:?}", marker);
         }
     }
 
-    // Otherwise, it's a string
-    Ok(Value::String(s.to_string()))
-}
+    #[test]
+    fn test_quote_if_needed_outputs() {
+        // Outputs of the two-pass implementation (needs_quoting, then escape_string)
+        let cases: [(&str, &str); 26] = [
+            ("", "\"\""),
+            ("plain", "plain"),
+            ("caf\u{e9}", "caf\u{e9}"),
+            ("two words", "\"two words\""),
+            ("em\u{2003}space", "\"em\u{2003}space\""),
+            ("a,b", "\"a,b\""),
+            ("a|b", "a|b"),
+            ("a\tb", "\"a\\tb\""),
+            ("say \"hi\"", "\"say \\\"hi\\\"\""),
+            ("line\nbreak", "\"line\\nbreak\""),
+            ("bell\u{7}", "\"bell\\u0007\""),
+            ("\u{feff}bom", "\"\\ufeffbom\""),
+            ("mid\u{feff}bom", "mid\u{feff}bom"),
+            ("a\u{feff}\tb:c", "\"a\\ufeff\\tb:c\""),
+            ("-lead", "\"-lead\""),
+            ("#tag", "\"#tag\""),
+            ("(2)", "(2)"),
+            ("true", "\"true\""),
+            ("42", "\"42\""),
+            ("+1.5", "\"+1.5\""),
+            (".5", "\".5\""),
+            ("5.", "\"5.\""),
+            ("1e5", "\"1e5\""),
+            ("1.2.3", "1.2.3"),
+            ("1e", "1e"),
+            ("007", "\"007\""),
+        ];
+        let options = EncodeOptions::default();
+        for (s, expected) in cases {
+            assert_eq!(quote_if_needed(s, &options), expected, "{:?}", s);
+        }
+        for s in ["inf", "+Infinity", "NaN"] {
+            assert_eq!(quote_if_needed(s, &options), format!("\"{}\"", s));
+        }
 
-/// Decode TOON format to JSON Value
-pub fn decode(toon_str: &str, _options: &DecodeOptions) -> Result<Value, ToonError> {
-    let lines: Vec<&str> = toon_str.lines().collect();
+        let pipe = EncodeOptions::builder().delimiter("|").build();
+        assert_eq!(quote_if_needed("a|b", &pipe), "\"a|b\"");
+        assert_eq!(quote_if_needed("a,b", &pipe), "a,b");
 
-    if lines.is_empty() {
-        return Ok(Value::Object(serde_json::Map::new()));
+        let inline = EncodeOptions::builder().escape_delimiter_inline(true).build();
+        assert_eq!(quote_if_needed("a,b,c", &inline), "a\\,b\\,c");
+        assert_eq!(quote_if_needed("a\u{feff},b", &inline), "a\u{feff}\\,b");
+        assert_eq!(quote_if_needed("a,b:c", &inline), "\"a,b:c\"");
+        let tab_inline = EncodeOptions::builder().delimiter("\t").escape_delimiter_inline(true).build();
+        assert_eq!(quote_if_needed("a\tb", &tab_inline), "a\\tb");
+        assert_eq!(quote_if_needed("a\tb c", &tab_inline), "\"a\\tb c\"");
+
+        let markers = EncodeOptions::builder().tuple_marker(true).list_marker("* ").build();
+        assert_eq!(quote_if_needed("(2)", &markers), "\"(2)\"");
+        assert_eq!(quote_if_needed("*star", &markers), "\"*star\"");
     }
 
-    // Simple implementation for basic cases
-    // This is a simplified decoder for the MVP
-    let mut result = serde_json::Map::new();
-    let delimiter = ","; // Default delimiter
-    let mut pending_key: Option<String> = None;
+    #[test]
+    fn test_tabular_under_list_item() {
+        let options = DecodeOptions::default();
+        let value = serde_json::json!([{"rows": [{"id": 1}, {"id": 2}]}]);
+        let encoded = encode_document(&value, &EncodeOptions::default()).unwrap();
+        assert_eq!(decode(&encoded, &options).unwrap(), value);
 
-    let mut i = 0;
-    while i < lines.len() {
-        let line = lines[i].trim_end();
+        // A table on the `- ` line itself: its rows sit below the item's
+        // fields, and the next field closes it
+        let input = "[2,]:\n  - rows[2,]{id,name}:\n      1,a\n      2,b\n    k: 1\n  - rows[1,]{id}:\n      3";
+        assert_eq!(
+            decode(input, &options).unwrap(),
+            serde_json::json!([
+                {"rows": [{"id": 1, "name": "a"}, {"id": 2, "name": "b"}], "k": 1},
+                {"rows": [{"id": 3}]}
+            ])
+        );
+    }
 
-        if line.is_empty() {
-            i += 1;
-            continue;
-        }
+    #[test]
+    fn test_redact_keys() {
+        let options = EncodeOptions::builder()
+            .redact_keys(vec!["password".to_string(), "token".to_string()])
+            .build();
 
-        // Check for key: value pattern
-        if let Some(colon_pos) = line.find(':') {
-            let key_part = line[..colon_pos].trim();
-            let value_part = line[colon_pos + 1..].trim();
+        let value = serde_json::json!({
+            "user": "alice",
+            "Password": "hunter2",
+            "token": {"value": "abc", "scopes": ["read"]},
+            "logins": [{"id": 1, "password": "a"}, {"id": 2, "password": "b"}]
+        });
+        assert_eq!(
+            encode_document(&value, &options).unwrap(),
+            "user: alice\nPassword: ***\ntoken: ***\nlogins:\n  [2,]{id,password}:\n    1,***\n    2,***"
+        );
+    }
 
-            // Array header pattern: key[N]{...}: or key[N]:
-            if key_part.contains('[') {
-                let bracket_start = key_part.find('[').unwrap();
-                let mut key = key_part[..bracket_start].trim().to_string();
+    #[test]
+    fn test_sort_rows_by() {
+        let options = EncodeOptions::builder().sort_rows_by("id").build();
+        let value = serde_json::json!({"users": [
+            {"id": 3, "name": "Cara"}, {"id": 1, "name": "Alice"}, {"id": 10, "name": "Jo"}, {"id": 2, "name": "Bob"}
+        ]});
+        assert_eq!(
+            encode_document(&value, &options).unwrap(),
+            "users:\n  [4,]{id,name}:\n    1,Alice\n    2,Bob\n    3,Cara\n    10,Jo"
+        );
 
-                // If key is empty and we have a pending key (nested structure), use it
-                if key.is_empty() && pending_key.is_some() {
-                    key = pending_key.take().unwrap();
-                }
+        // Mixed types: null, booleans, numbers, strings; ties keep input order
+        let value = serde_json::json!([
+            {"id": "b", "n": 1}, {"id": 2.5, "n": 2}, {"id": null, "n": 3}, {"id": true, "n": 4}, {"id": 2.5, "n": 5}
+        ]);
+        assert_eq!(
+            encode_document(&value, &options).unwrap(),
+            "[5,]{id,n}:\n  null,3\n  true,4\n  2.5,2\n  2.5,5\n  b,1"
+        );
 
-                // Check for tabular array {fields}:
-                if key_part.contains('{') && value_part.is_empty() {
-                    let fields_start = key_part.find('{').unwrap();
-                    let fields_end = key_part.find('}').unwrap();
-                    let fields_str = &key_part[fields_start + 1..fields_end];
-                    let fields: Vec<&str> = fields_str.split(delimiter).map(|s| s.trim()).collect();
+        // Null placeholder rows go last
+        let options = EncodeOptions::builder().sort_rows_by("id").tabular_allow_null_rows(true).build();
+        let value = serde_json::json!([{"id": 2, "n": 0}, null, {"id": 1, "n": 0}]);
+        assert_eq!(encode_document(&value, &options).unwrap(), "[3,]{id,n}:\n  1,0\n  2,0\n  ,");
 
-                    // Read data rows
-                    let mut rows = Vec::new();
-                    i += 1;
-                    while i < lines.len() {
-                        let data_line = lines[i];
-                        if data_line.trim().is_empty() || (!data_line.starts_with(' ') && !data_line.starts_with('\t')) {
-                            break;
-                        }
+        let value = serde_json::json!({"users": [{"name": "Alice"}]});
+        assert!(encode_document(&value, &options).is_err());
+    }
 
-                        let data_line = data_line.trim();
-                        let values: Vec<&str> = data_line.split(delimiter).collect();
+    #[test]
+    fn test_object_of_only_nested_values() {
+        let options = EncodeOptions::default();
+        let value = serde_json::json!({"a": {"x": 1}, "b": {"y": 2}, "c": {"z": 3}});
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(encoded, "a:\n  x: 1\nb:\n  y: 2\nc:\n  z: 3");
+        assert_eq!(decode(&encoded, &DecodeOptions::default()).unwrap(), value);
 
-                        let mut row_obj = serde_json::Map::new();
-                        for (field, value) in fields.iter().zip(values.iter()) {
-                            row_obj.insert(field.to_string(), parse_value(value, delimiter)?);
-                        }
-                        rows.push(Value::Object(row_obj));
-                        i += 1;
-                    }
+        // Deeper blocks, arrays and empty objects side by side
+        let value = serde_json::json!({
+            "a": {"x": {"q": [1, 2]}},
+            "b": [{"y": 2}, "s"],
+            "c": {},
+            "d": {"z": [{"k": 1}]}
+        });
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(decode(&encoded, &DecodeOptions::default()).unwrap(), value);
 
-                    result.insert(key, Value::Array(rows));
-                    pending_key = None;
-                    continue;
-                }
+        let spaced = EncodeOptions::builder().section_spacing(true).build();
+        let encoded = encode_document(&value, &spaced).unwrap();
+        assert_eq!(decode(&encoded, &DecodeOptions::default()).unwrap(), value);
+    }
 
-                // Inline primitive array: key[N]: val1,val2,val3
-                if !value_part.is_empty() {
-                    let values: Vec<Value> = value_part
-                        .split(delimiter)
-                        .map(|s| parse_value(s.trim(), delimiter))
-                        .collect::<Result<Vec<_>, _>>()?;
-                    result.insert(key, Value::Array(values));
-                    pending_key = None;
-                    i += 1;
-                    continue;
-                }
+    #[test]
+    fn test_index_large_arrays_roundtrip() {
+        let options = EncodeOptions::builder().index_large_arrays(3).build();
+        let value = serde_json::json!({"scores": [9.5, "n/a", null, true], "few": [1, 2, 3]});
 
-                // Array without inline values - might have data on next lines
-                i += 1;
-                continue;
-            }
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(
+            encoded,
+            "scores:\n  [4,]{i,value}:\n    0,9.5\n    1,n/a\n    2,null\n    3,true\nfew:\n  [3,]: 1,2,3"
+        );
 
-            // Simple key: value
-            if !value_part.is_empty() {
-                let key = if key_part.starts_with('"') && key_part.ends_with('"') {
-                    unescape_string(&key_part[1..key_part.len() - 1])?
-                } else {
-                    key_part.to_string()
-                };
-                result.insert(key, parse_value(value_part, delimiter)?);
-                pending_key = None;
-            } else {
-                // Key with empty value - might be parent of nested structure
-                pending_key = Some(key_part.to_string());
-            }
-        }
+        let decode_options = DecodeOptions::builder().unindex_arrays(true).build();
+        assert_eq!(decode(&encoded, &decode_options).unwrap(), value);
 
-        i += 1;
+        // Tables whose `i` doesn't count up from 0 stay objects
+        let table = "[2,]{i,value}:\n  1,a\n  0,b";
+        assert_eq!(
+            decode(table, &decode_options).unwrap(),
+            serde_json::json!([{"i": 1, "value": "a"}, {"i": 0, "value": "b"}])
+        );
     }
 
-    Ok(Value::Object(result))
-}
+    #[test]
+    fn test_tab_delimited_nested_table_roundtrip() {
+        let options = EncodeOptions::builder().delimiter("\t").tabular_allow_null_rows(true).build();
+        let value = serde_json::json!({"outer": {"rows": [
+            {"a": "", "b": "x y", "c": 1},
+            null,
+            {"a": "p", "b": "", "c": null},
+            null
+        ]}});
 
-/// Encode Python data to TOON format
-#[pyfunction]
-#[pyo3(signature = (data, options=None))]
-pub fn encode(_py: Python, data: &Bound<'_, PyAny>, options: Option<&EncodeOptions>) -> PyResult<String> {
-    let opts = options.cloned().unwrap_or_default();
+        // Leading tabs are empty cells and the trailing all-tab line is a null row
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(
+            encoded,
+            "outer:\n  rows:\n    [4\t]{a\tb\tc}:\n      \"\"\t\"x y\"\t1\n      \t\t\n      p\t\"\"\tnull\n      \t\t"
+        );
+        let decode_options = DecodeOptions::builder().tabular_allow_null_rows(true).build();
+        assert_eq!(decode(&encoded, &decode_options).unwrap(), value);
 
-    // Convert Python object to serde_json::Value
-    let value: Value = depythonize(data)
-        .map_err(|e| ToonError::PythonError(e.to_string()))?;
+        let value = serde_json::json!({"items": [{"rows": [{"a": 1, "b": 2}, null]}, "x"]});
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(decode(&encoded, &decode_options).unwrap(), value);
+    }
 
-    // Encode to TOON
-    let result = encode_value(&value, 0, &opts)
-        .map_err(|e| PyErr::from(e))?;
+    #[test]
+    fn test_tab_delimited_row_indentation() {
+        let options = DecodeOptions::default();
+        let err = decode("rows[1\t]{a\tb}:\n\t1\t2", &options).unwrap_err();
+        assert_eq!(err.to_string(), "Decoding error: Tab-delimited rows must be indented with spaces at line 2");
 
-    Ok(result)
-}
+        let err = decode("rows[2\t]{a\tb}:\n  1\t2\n   \t3", &options).unwrap_err();
+        assert_eq!(err.to_string(), "Decoding error: Tab-delimited row indented 3 spaces, expected 2 at line 3");
 
-/// Decode TOON format to Python data
-#[pyfunction]
-#[pyo3(signature = (toon_str, options=None))]
-pub fn decode_toon(py: Python, toon_str: &str, options: Option<&DecodeOptions>) -> PyResult<PyObject> {
-    let opts = options.cloned().unwrap_or_default();
+        // One unit of spaces, then an empty first cell
+        assert_eq!(
+            decode("rows[2\t]{a\tb}:\n  1\t2\n  \t3", &options).unwrap(),
+            serde_json::json!({"rows": [{"a": 1, "b": 2}, {"a": "", "b": 3}]})
+        );
+    }
 
-    // Decode from TOON
-    let value = decode(toon_str, &opts)
-        .map_err(|e| PyErr::from(e))?;
+    #[test]
+    fn test_decode_raw_keys() {
+        let options = DecodeOptions::builder().raw_keys(vec!["note".to_string()]).build();
+        let input = "note: a, b: c\nother: 42\nnested:\n  note: \"quoted\" 1,2\n  n: 1";
+        assert_eq!(
+            decode(input, &options).unwrap(),
+            serde_json::json!({"note": "a, b: c", "other": 42, "nested": {"note": "\"quoted\" 1,2", "n": 1}})
+        );
 
-    // Convert to Python object
-    let py_obj = pythonize(py, &value)
-        .map_err(|e| ToonError::PythonError(e.to_string()))?;
+        // Raw values are never inferred as numbers or literals
+        assert_eq!(decode("note: 42", &options).unwrap(), serde_json::json!({"note": "42"}));
+        assert_eq!(decode("note:\n  a: 1", &options).unwrap(), serde_json::json!({"note": {"a": 1}}));
+    }
 
-    Ok(py_obj.unbind())
-}
+    #[test]
+    fn test_version_tag() {
+        let options = EncodeOptions::builder().emit_version(true).build();
+        let value = serde_json::json!({"a": 1, "b": [1, 2]});
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(encoded, "#toon v1\na: 1\nb:\n  [2,]: 1,2");
 
-/// Python module
-#[pymodule]
-fn _toon_tuna(m: &Bound<'_, PyModule>) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(encode, m)?)?;
-    m.add_function(wrap_pyfunction!(decode_toon, m)?)?;
-    m.add_class::<EncodeOptions>()?;
-    m.add_class::<DecodeOptions>()?;
-    Ok(())
-}
+        let decode_options = DecodeOptions::default();
+        assert_eq!(decode(&encoded, &decode_options).unwrap(), value);
+        assert_eq!(decode("#toon v1.3\n[2,]: 1,2", &decode_options).unwrap(), serde_json::json!([1, 2]));
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // Missing version is accepted
+        assert_eq!(decode("a: 1", &decode_options).unwrap(), serde_json::json!({"a": 1}));
+
+        let err = decode("#toon v2\na: 1", &decode_options).unwrap_err();
+        assert_eq!(err.to_string(), "Decoding error: Unsupported TOON version 'v2' at line 1");
+        let err = decode("#toon vx\na: 1", &decode_options).unwrap_err();
+        assert_eq!(err.to_string(), "Decoding error: Malformed TOON version 'vx' at line 1");
+    }
 
     #[test]
-    fn test_quote_if_needed() {
-        let opts = EncodeOptions::default();
-        assert_eq!(quote_if_needed("hello", &opts.delimiter), "hello");
-        assert_eq!(quote_if_needed("hello world", &opts.delimiter), "\"hello world\"");
-        assert_eq!(quote_if_needed("true", &opts.delimiter), "\"true\"");
-        assert_eq!(quote_if_needed("123", &opts.delimiter), "\"123\"");
+    fn test_decode_with_spans() {
+        let input = "name: demo\nconfig:\n  db:\n    host: localhost\n    port: 5432\n  debug: true\nitems[2]:\n  - id: 1\n    tags[1]: a\n  - x\nusers[2,]{id,name}:\n  1,Alice\n  2,Bob";
+        let (value, spans) = decode_with_spans(input, &DecodeOptions::default()).unwrap();
+        assert_eq!(value, decode(input, &DecodeOptions::default()).unwrap());
+
+        assert_eq!(spans[""], (1, 13));
+        assert_eq!(spans["/name"], (1, 1));
+        assert_eq!(spans["/config"], (2, 6));
+        assert_eq!(spans["/config/db"], (3, 5));
+        assert_eq!(spans["/config/db/port"], (5, 5));
+        assert_eq!(spans["/items/0"], (8, 9));
+        assert_eq!(spans["/items/0/tags"], (9, 9));
+        assert_eq!(spans["/items/1"], (10, 10));
+        assert_eq!(spans["/users"], (11, 13));
+        assert_eq!(spans["/users/1"], (13, 13));
+
+        // `~` and `/` in keys are escaped
+        let (_, spans) = decode_with_spans("\"a/b\":\n  \"c~d\": 1", &DecodeOptions::default()).unwrap();
+        assert_eq!(spans["/a~1b/c~0d"], (2, 2));
+
+        // Plain decoding records nothing
+        let (_, meta) = decode_with_meta(input, &DecodeOptions::default()).unwrap();
+        assert!(meta.spans.is_empty());
     }
 
     #[test]
-    fn test_encode_simple_object() {
-        let data = serde_json::json!({
-            "id": 123,
-            "name": "Alice"
+    fn test_on_number_overflow() {
+        let input = "big: 99999999999999999999";
+
+        let options = DecodeOptions::default();
+        assert_eq!(decode(input, &options).unwrap(), serde_json::json!({"big": 1e20}));
+
+        let options = DecodeOptions::builder().on_number_overflow(NumberOverflow::AsString).build();
+        assert_eq!(decode(input, &options).unwrap(), serde_json::json!({"big": "99999999999999999999"}));
+        assert_eq!(decode("big: -99999999999999999999", &options).unwrap(), serde_json::json!({"big": "-99999999999999999999"}));
+
+        let options = DecodeOptions::builder().on_number_overflow(NumberOverflow::Error).build();
+        let err = decode(input, &options).unwrap_err();
+        assert_eq!(err.to_string(), "Decoding error: Integer '99999999999999999999' does not fit in 64 bits");
+
+        // u64 values and floats are unaffected
+        assert_eq!(decode("n: 18446744073709551615", &options).unwrap(), serde_json::json!({"n": u64::MAX}));
+        assert_eq!(decode("n: 1.5", &options).unwrap(), serde_json::json!({"n": 1.5}));
+    }
+
+    #[test]
+    fn test_tabular_footer() {
+        let options = EncodeOptions::builder().tabular_footer(true).build();
+        let value = serde_json::json!({
+            "items": [
+                {"id": 1, "name": "a", "price": 2.5},
+                {"id": 2, "name": "b", "price": null},
+                {"id": 3, "name": "c", "price": 1.25}
+            ],
+            "total": 3
         });
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(
+            encoded,
+            "items:\n  [3,]{id,name,price}:\n    1,a,2.5\n    2,b,null\n    3,c,1.25\n    # count=3 sum(id)=6 sum(price)=3.75\ntotal: 3"
+        );
 
-        let opts = EncodeOptions::default();
-        let result = encode_value(&data, 0, &opts).unwrap();
+        let decode_options = DecodeOptions::builder().allow_comments(true).build();
+        assert_eq!(decode(&encoded, &decode_options).unwrap(), value);
 
-        assert!(result.contains("id: 123"));
-        assert!(result.contains("name: Alice"));
+        // Without comments enabled the footer reads as a malformed row
+        assert!(decode(&encoded, &DecodeOptions::default()).is_err());
     }
 
     #[test]
-    fn test_encode_tabular_array() {
-        let data = serde_json::json!({
-            "users": [
-                {"id": 1, "name": "Alice"},
-                {"id": 2, "name": "Bob"}
+    fn test_lossless_overrides_lossy_options() {
+        let value = serde_json::json!({"password": "hunter2", "note": null, "tags": [], "ok": true, "big": u64::MAX, "x": "1"});
+        let lossy = EncodeOptions::builder()
+            .skip_nulls(true)
+            .skip_empty(true)
+            .true_repr("yes")
+            .redact_keys(vec!["password".to_string()]);
+        let encoded = encode_document(&value, &lossy.clone().build()).unwrap();
+        assert_ne!(decode(&encoded, &DecodeOptions::default()).unwrap(), value);
+
+        let encoded = encode_document(&value, &lossy.lossless(true).build()).unwrap();
+        assert_eq!(decode(&encoded, &DecodeOptions::default()).unwrap(), value);
+
+        // Top-level scalars get the sentinel so they read back as themselves
+        let options = EncodeOptions::builder().lossless(true).build();
+        assert_eq!(encode_document(&serde_json::json!("a b"), &options).unwrap(), "= \"a b\"");
+    }
+
+    /// Any JSON value, a few levels deep
+    fn arb_json() -> impl proptest::strategy::Strategy<Value = Value> {
+        use proptest::prelude::*;
+
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::Bool),
+            any::<i64>().prop_map(Value::from),
+            any::<u64>().prop_map(Value::from),
+            any::<f64>().prop_filter("finite", |f| f.is_finite()).prop_map(Value::from),
+            any::<String>().prop_map(Value::String),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                proptest::collection::vec(inner.clone(), 0..8).prop_map(Value::Array),
+                proptest::collection::vec((any::<String>(), inner), 0..8)
+                    .prop_map(|fields| Value::Object(fields.into_iter().collect())),
             ]
-        });
+        })
+    }
 
-        let opts = EncodeOptions::default();
-        let result = encode_value(&data, 0, &opts).unwrap();
+    proptest::proptest! {
+        #[test]
+        fn test_lossless_round_trip(value in arb_json()) {
+            // Lossy settings that `lossless` must override
+            let options = EncodeOptions::builder()
+                .lossless(true)
+                .skip_nulls(true)
+                .skip_empty(true)
+                .true_repr("1")
+                .false_repr("0")
+                .compact_numbers(true)
+                .flatten_paths(true)
+                .redact_keys(vec!["a".to_string()])
+                .tuple_marker(true)
+                .length_marker_style(LengthMarkerStyle::TrailingComment)
+                .indent(0)
+                .build();
+            let encoded = encode_document(&value, &options).unwrap();
+            let decoded = decode(&encoded, &DecodeOptions::default()).unwrap();
+            proptest::prop_assert_eq!(decoded, value, "encoded: {:?}", encoded);
+        }
+    }
 
-        assert!(result.contains("[2,]{id,name}:"));
-        assert!(result.contains("1,Alice"));
-        assert!(result.contains("2,Bob"));
+    #[test]
+    fn test_encode_to_writer() {
+        let fields = vec!["id".to_string(), "name".to_string()];
+        let rows = || (0..3).map(|i| Ok(serde_json::Map::from_iter([("id".to_string(), serde_json::json!(i)), ("name".to_string(), serde_json::json!(format!("u{}", i)))])));
+        let options = EncodeOptions::default();
+
+        let mut out = Vec::new();
+        encode_to_writer(rows(), Some(3), &fields, &options, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "[3,]{id,name}:\n  0,u0\n  1,u1\n  2,u2");
+
+        // Without a known length the header has none
+        let mut out = Vec::new();
+        encode_to_writer(rows(), None, &fields, &options, &mut out).unwrap();
+        let toon = String::from_utf8(out).unwrap();
+        assert!(toon.starts_with("[]{id,name}:\n  0,u0"));
+        assert_eq!(decode(&toon, &DecodeOptions::default()).unwrap()[2]["name"], "u2");
+
+        let err = encode_to_writer(rows(), Some(5), &fields, &options, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "Encoding error: Header declares 5 rows, but 3 were written");
     }
 
     #[test]
-    fn test_encode_primitive_array() {
-        let data = serde_json::json!({
-            "tags": [1, 2, 3]
+    fn test_decode_tab_indentation() {
+        let expected = serde_json::json!({"server": {"host": "localhost", "tls": {"enabled": true}}, "items": [1, {"id": 2}], "port": 80});
+        let input = "server:\n\thost: localhost\n\ttls:\n\t\tenabled: true\nitems[2,]:\n\t- 1\n\t- id: 2\nport: 80";
+        assert_eq!(decode(input, &DecodeOptions::default()).unwrap(), expected);
+
+        // Wider tabs line up with space-indented lines
+        let options = DecodeOptions::builder().tab_width(2).build();
+        let mixed = "server:\n\thost: localhost\n  tls:\n\t  enabled: true\nitems[2,]:\n  - 1\n\t- id: 2\nport: 80";
+        assert_eq!(decode(mixed, &options).unwrap(), expected);
+        assert_eq!(decode_with_meta(mixed, &options).unwrap().1.indent, Some(2));
+
+        // With tab_width 0 tabs aren't indentation
+        let options = DecodeOptions::builder().tab_width(0).build();
+        assert!(decode(input, &options).is_err());
+
+        // Tab-delimited rows keep their leading empty cells
+        let table = "rows[2\t]{a\tb}:\n  1\t2\n  \t3";
+        let options = DecodeOptions::builder().tab_width(4).build();
+        assert_eq!(
+            decode(table, &options).unwrap(),
+            serde_json::json!({"rows": [{"a": 1, "b": 2}, {"a": "", "b": 3}]})
+        );
+    }
+
+    #[test]
+    fn test_retarget_delimiter() {
+        let input = "users[2,]{id,name,tags}:\n  1,Alice,a|b\n  2,\"Smith, Bob\",\n\nids[3,]: 1,2,3";
+        let output = retarget_delimiter(input, ",", "|").unwrap();
+        assert_eq!(output, "users[2|]{id|name|tags}:\n  1|Alice|\"a|b\"\n  2|\"Smith, Bob\"|\n\nids[3|]: 1|2|3");
+        assert_eq!(
+            decode(&output, &DecodeOptions::default()).unwrap(),
+            decode(input, &DecodeOptions::default()).unwrap()
+        );
+
+        // Nested and list-item tables, matrices and expanded headers; other delimiters are left alone
+        let value = serde_json::json!({
+            "a": {"rows": [{"x": 1, "y": "p,q"}, {"x": 2, "y": ""}]},
+            "items": [{"t": [{"k": 1, "v": 2}], "n": "m"}, [1, 2]],
+            "grid": [[1, 2], [3, 4]]
         });
+        let toon = encode_document(&value, &EncodeOptions::builder().matrix_mode(true).build()).unwrap();
+        let toon = format!("{}\nkeep[2|]: a,b|c", toon);
+        for to in ["|", "\t"] {
+            let output = retarget_delimiter(&toon, ",", to).unwrap();
+            assert!(output.ends_with("keep[2|]: a,b|c"));
+            assert_eq!(
+                decode(&output, &DecodeOptions::default()).unwrap(),
+                decode(&toon, &DecodeOptions::default()).unwrap()
+            );
+            if to == "\t" {
+                assert_eq!(retarget_delimiter(&output, to, ",").unwrap(), toon);
+            }
+        }
 
-        let opts = EncodeOptions::default();
-        let result = encode_value(&data, 0, &opts).unwrap();
+        assert!(matches!(retarget_delimiter(input, ",", ";"), Err(ToonError::InvalidDelimiter(_))));
+    }
 
-        assert!(result.contains("tags:"));
-        assert!(result.contains("[3,]: 1,2,3"));
+    #[test]
+    fn test_json_passthrough_keys() {
+        let value = serde_json::json!({
+            "id": 7,
+            "payload": {"a": [1, 2], "b": {"c": "x, y"}},
+            "events": [{"kind": "click", "meta": {"x": 1}}, {"kind": "view", "meta": null}]
+        });
+        let options = EncodeOptions::builder()
+            .json_passthrough_keys(vec!["payload".to_string(), "meta".to_string()])
+            .build();
+        let encoded = encode_document(&value, &options).unwrap();
+        assert_eq!(
+            encoded,
+            "id: 7\npayload: \"{\\\"a\\\":[1,2],\\\"b\\\":{\\\"c\\\":\\\"x, y\\\"}}\"\nevents:\n  [2,]{kind,meta}:\n    click,\"{\\\"x\\\":1}\"\n    view,\"null\""
+        );
+
+        let decode_options = DecodeOptions::builder()
+            .json_passthrough_keys(vec!["payload".to_string(), "meta".to_string()])
+            .build();
+        assert_eq!(decode(&encoded, &decode_options).unwrap(), value);
+
+        // Without the keys the JSON stays text
+        assert_eq!(
+            decode(&encoded, &DecodeOptions::default()).unwrap()["payload"],
+            "{\"a\":[1,2],\"b\":{\"c\":\"x, y\"}}"
+        );
+
+        let err = decode("payload: \"{oops\"", &decode_options).unwrap_err();
+        assert!(err.to_string().starts_with("Decoding error: Invalid JSON under 'payload'"));
+    }
+
+    #[test]
+    fn test_expanded_scalar_items_are_typed() {
+        let options = DecodeOptions::default();
+        assert_eq!(
+            decode("[3,]:\n  - null\n  - 1\n  - \"x\"", &options).unwrap(),
+            serde_json::json!([null, 1, "x"])
+        );
+
+        // An object element forces the expanded form; the scalars beside it keep their types
+        let value = serde_json::json!([null, 1, "x", true, "null", "1", {"a": 1}]);
+        let encoded = encode_document(&value, &EncodeOptions::default()).unwrap();
+        assert_eq!(encoded, "[7,]:\n  - null\n  - 1\n  - x\n  - true\n  - \"null\"\n  - \"1\"\n  - a: 1");
+        assert_eq!(decode(&encoded, &options).unwrap(), value);
+    }
+
+    #[test]
+    fn test_max_string_len() {
+        let options = EncodeOptions::builder().max_string_len(5).build();
+        assert_eq!(encode_document(&serde_json::json!({"s": "héllo"}), &options).unwrap(), "s: héllo");
+
+        let err = encode_document(&serde_json::json!({"a": [{"s": "hello!"}]}), &options).unwrap_err();
+        assert_eq!(err.to_string(), "Encoding error: String of 6 characters exceeds max_string_len 5 at a[0].s");
+
+        // Lenient mode writes it anyway
+        let options = EncodeOptions::builder().max_string_len(5).strict(false).build();
+        assert_eq!(encode_document(&serde_json::json!({"s": "hello!"}), &options).unwrap(), "s: hello!");
+    }
+
+    #[test]
+    fn test_numeric_keys_as_array() {
+        let options = DecodeOptions::builder().numeric_keys_as_array(true).build();
+        let input = "tags:\n  \"1\": b\n  \"0\": a\n  \"2\":\n    \"0\": x\nname: n";
+        assert_eq!(
+            decode(input, &options).unwrap(),
+            serde_json::json!({"tags": ["a", "b", ["x"]], "name": "n"})
+        );
+
+        // A gap, a padded index or an empty object keeps the object
+        let input = "gap:\n  \"0\": a\n  \"2\": c\npadded:\n  \"00\": a\nempty:";
+        assert_eq!(
+            decode(input, &options).unwrap(),
+            serde_json::json!({"gap": {"0": "a", "2": "c"}, "padded": {"00": "a"}, "empty": {}})
+        );
+
+        // Off by default
+        assert_eq!(
+            decode("\"0\": a", &DecodeOptions::default()).unwrap(),
+            serde_json::json!({"0": "a"})
+        );
+    }
+
+    #[test]
+    fn test_defensive_keys_round_trip() {
+        let keys = ["a[b]", "a{b}", "a:b", "x=y", "a(b)", "a|b", "items[3]", "a b", "#x", "1", "", "plain", "a.b"];
+        let mut obj = serde_json::Map::new();
+        let mut row = serde_json::Map::new();
+        for (i, key) in keys.iter().enumerate() {
+            obj.insert(key.to_string(), serde_json::json!([1, 2]));
+            obj.insert(format!("{}_s", key), serde_json::json!("v"));
+            row.insert(key.to_string(), serde_json::json!(i));
+        }
+        obj.insert("rows".to_string(), serde_json::json!([row.clone(), row]));
+        let value = Value::Object(obj);
+
+        for defensive in [false, true] {
+            let options = EncodeOptions::builder().defensive_keys(defensive).build();
+            let toon = encode_document(&value, &options).unwrap();
+            assert_eq!(decode(&toon, &DecodeOptions::default()).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_defensive_keys_quotes_non_identifiers() {
+        let value = serde_json::json!({"x=y": 1, "a(b)": 2, "é": 3, "plain": 4, "rows": [{"a|b": 1}, {"a|b": 2}]});
+        let options = EncodeOptions::builder().defensive_keys(true).build();
+        let toon = encode_document(&value, &options).unwrap();
+        assert!(toon.contains("\"x=y\": 1"));
+        assert!(toon.contains("\"a(b)\": 2"));
+        assert!(toon.contains("\"é\": 3"));
+        assert!(toon.contains("plain: 4"));
+        assert!(toon.contains("{\"a|b\"}"));
+
+        let plain = encode_document(&value, &EncodeOptions::default()).unwrap();
+        assert!(plain.contains("x=y: 1"));
+    }
+
+    #[test]
+    fn test_content_hash_ignores_formatting() {
+        let a = content_hash("name: Demo\nusers[2,]{id,name}:\n  1,Alice\n  2,Bob\ntags[2,]: a,b").unwrap();
+        let b = content_hash("tags[2|]: a|b\nusers[2]:\n  - name: Alice\n    id: 1\n  - id: 2\n    name: Bob\nname: Demo").unwrap();
+        assert_eq!(a, b);
+
+        let changed = content_hash("name: Demo\nusers[2,]{id,name}:\n  1,Alice\n  2,Bobby\ntags[2,]: a,b").unwrap();
+        assert_ne!(a, changed);
+        let reordered = content_hash("name: Demo\nusers[2,]{id,name}:\n  1,Alice\n  2,Bob\ntags[2,]: b,a").unwrap();
+        assert_ne!(a, reordered);
+    }
+
+    #[test]
+    fn test_content_hash_distinguishes_types() {
+        let hashes: Vec<u64> = ["v: 1", "v: \"1\"", "v: true", "v: null", "v[1,]: 1", "v:\n  w: 1"]
+            .iter()
+            .map(|doc| content_hash(doc).unwrap())
+            .collect();
+        for (i, a) in hashes.iter().enumerate() {
+            assert!(hashes[i + 1..].iter().all(|b| a != b));
+        }
+        // Fixed across releases, so hashes can be stored
+        assert_eq!(content_hash("v: 1").unwrap(), 16165525667743511088);
+    }
+
+    #[test]
+    fn test_leading_hash_item_survives_comments() {
+        let value = serde_json::json!({"tags": ["#hashtag", {"a": "#x"}, ["#nested"]]});
+        let toon = encode_document(&value, &EncodeOptions::default()).unwrap();
+        assert!(toon.contains("- \"#hashtag\""));
+
+        let options = DecodeOptions { allow_comments: true, ..Default::default() };
+        assert_eq!(decode(&toon, &options).unwrap(), value);
+    }
+
+    #[test]
+    fn test_decode_flat_pointers() {
+        let toon = "users[2,]{id,name}:\n  1,Alice\n  2,Bob\nmeta:\n  a/b: x\n  tags[0,]:\n  empty:";
+        let flat = decode_flat(toon).unwrap();
+        assert_eq!(
+            flat,
+            serde_json::json!({
+                "/users/0/id": 1,
+                "/users/0/name": "Alice",
+                "/users/1/id": 2,
+                "/users/1/name": "Bob",
+                "/meta/a~1b": "x",
+                "/meta/tags": [],
+                "/meta/empty": {}
+            })
+        );
+        let keys: Vec<_> = flat.as_object().unwrap().keys().cloned().collect();
+        assert_eq!(keys[0], "/users/0/id");
+
+        assert_eq!(decode_flat("= 5").unwrap(), serde_json::json!({"": 5}));
     }
 }
@@ -2,8 +2,45 @@
 Test basic TOON encoding and decoding functionality
 """
 
+import enum
+import json
+import os
+import tempfile
+
 import pytest
-from toon_tuna import encode, decode, EncodeOptions, DecodeOptions
+from toon_tuna import (
+    encode,
+    decode,
+    decode_file,
+    encode_rows,
+    encode_to_file,
+    roundtrip_ok,
+    encode_preview,
+    savings,
+    normalize,
+    retarget_delimiter,
+    validate_finite,
+    encode_flattened,
+    decode_unflatten,
+    encode_with_comments,
+    decode_with_comments,
+    decode_with_spans,
+    decode_with_meta,
+    decode_flat,
+    merge,
+    decode_schema,
+    content_hash,
+    EncodeOptions,
+    DecodeOptions,
+    BytesEncoding,
+    LengthMarkerStyle,
+    NumberOverflow,
+    StreamingDecoder,
+    ToonError,
+    ToonEncodeError,
+    ToonDecodeError,
+    ToonDelimiterError,
+)
 
 
 class TestEncoding:
@@ -36,6 +73,14 @@ class TestEncoding:
         assert "settings:" in result
         assert "theme: dark" in result
 
+    def test_preserves_key_order(self):
+        """Test that dict insertion order is kept in the output."""
+        data = {"z": 1, "a": 2, "m": 3}
+
+        result = encode(data)
+
+        assert result.splitlines() == ["z: 1", "a: 2", "m: 3"]
+
     def test_tabular_array(self):
         """Test encoding uniform arrays (tabular format)."""
         data = {"users": [{"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}]}
@@ -43,7 +88,7 @@ class TestEncoding:
         result = encode(data)
 
         # Should use tabular format
-        assert "[2,]{id,name}:" in result or "[2]{id,name}:" in result
+        assert "[2,]{id,name}:" in result
         assert "1,Alice" in result
         assert "2,Bob" in result
 
@@ -54,8 +99,7 @@ class TestEncoding:
         result = encode(data)
 
         # Should use inline format
-        assert "[5,]:" in result or "[5]:" in result
-        assert "1,2,3,4,5" in result
+        assert "[5,]: 1,2,3,4,5" in result
 
     def test_string_array(self):
         """Test encoding arrays of strings."""
@@ -63,8 +107,7 @@ class TestEncoding:
 
         result = encode(data)
 
-        assert "[3,]:" in result or "[3]:" in result
-        assert "red,green,blue" in result or "red" in result
+        assert "[3,]: red,green,blue" in result
 
     def test_empty_object(self):
         """Test encoding empty object."""
@@ -80,7 +123,7 @@ class TestEncoding:
 
         result = encode(data)
 
-        assert "[0]:" in result
+        assert "[0,]:" in result
 
     def test_boolean_values(self):
         """Test encoding boolean values."""
@@ -176,6 +219,120 @@ class TestDecoding:
         assert result["id"] == 123
         assert result["name"] == "Alice"
 
+    def test_decode_non_finite_numbers(self):
+        """Test inf/nan tokens are rejected in strict mode and kept as strings otherwise."""
+        for token in ["inf", "-inf", "nan"]:
+            with pytest.raises(ValueError, match="not finite"):
+                decode(f"x: {token}")
+            assert decode(f"x: {token}", DecodeOptions(strict=False)) == {"x": token}
+
+    def test_decode_strips_bom(self):
+        """Test a leading byte order mark is not read as part of the first key."""
+        result = decode("\ufeffid: 1\nname: Alice")
+
+        assert result == {"id": 1, "name": "Alice"}
+
+    def test_decode_without_space_after_colon(self):
+        """Test key:value, inline arrays and tabular headers decode without a space after ':'."""
+        toon_str = "id:1\ntags[2,]:a,b\nusers[1,]{id,name}:\n  1,Bob"
+
+        result = decode(toon_str)
+
+        assert result == {"id": 1, "tags": ["a", "b"], "users": [{"id": 1, "name": "Bob"}]}
+
+    def test_decode_inline_trailing_delimiter(self):
+        """Test a trailing delimiter is rejected in strict mode and dropped in lenient mode."""
+        with pytest.raises(ToonDecodeError, match="of 3 items"):
+            decode("nums[3,]: 1,2,3,")
+
+        assert decode("nums[3,]: 1,2,3,", DecodeOptions(strict=False)) == {"nums": [1, 2, 3]}
+
+    def test_decode_lenient_misplaced_lines(self):
+        """Test lenient mode recovers from over-indented fields and stray lines among items."""
+        with pytest.raises(ToonDecodeError, match="Unexpected indentation"):
+            decode("a: 1\n    b: 2")
+        assert decode("a: 1\n    b: 2", DecodeOptions(strict=False)) == {"a": 1, "b": 2}
+
+        toon_str = "items[2,]:\n  - 1\n  stray: x\n  - 2"
+        with pytest.raises(ToonDecodeError, match="list item"):
+            decode(toon_str)
+        assert decode(toon_str, DecodeOptions(strict=False)) == {"items": [1, 2]}
+
+    def test_decode_duplicate_header_field(self):
+        """Test a repeated tabular field is rejected instead of dropping a column."""
+        with pytest.raises(ToonDecodeError, match="Duplicate field 'id'"):
+            decode("rows[1,]{id,id,name}:\n  1,2,a")
+
+        with pytest.raises(ToonEncodeError):
+            encode_rows([{"id": 1}], ["id", "id"])
+
+    def test_decode_valueless_key_followed_by_sibling(self):
+        """Test a bare key with no indented children decodes to an empty object."""
+        assert decode("a:\nb: 1") == {"a": {}, "b": 1}
+        assert decode("x:\n  a:\n  b: 1") == {"x": {"a": {}, "b": 1}}
+
+    def test_decode_underscore_numbers(self):
+        """Test allow_underscore_numbers only strips underscores between digits."""
+        options = DecodeOptions(allow_underscore_numbers=True)
+
+        assert decode("a: 1_000\nb: _1\nc: 1_", options) == {"a": 1000, "b": "_1", "c": "1_"}
+
+    def test_decode_raw_keys(self):
+        """Test raw keys keep the rest of the line verbatim."""
+        options = DecodeOptions(raw_keys=["note"])
+
+        assert decode("note: a, b: c\ncount: 2", options) == {"note": "a, b: c", "count": 2}
+
+    def test_decode_zero_row_tabular(self):
+        """Test a tabular header with no rows decodes to an empty list."""
+        assert decode("users[0,]{id,name}:\ncount: 0") == {"users": [], "count": 0}
+
+    def test_decode_delimiter_only_cell(self):
+        """Test a quoted cell holding just the delimiter stays one cell."""
+        assert decode('rows[1,]{a,b,c}:\n  x,",",y') == {"rows": [{"a": "x", "b": ",", "c": "y"}]}
+
+    def test_decode_tabular_shape_checks(self):
+        """Test strict decoding checks row widths and the declared row count."""
+        with pytest.raises(ToonDecodeError, match="Tabular row has 1 cells, expected 2"):
+            decode("users[2,]{id,name}:\n  1,Alice\n  2")
+        with pytest.raises(ToonDecodeError, match="Tabular row has 3 cells, expected 2"):
+            decode("users[1,]{id,name}:\n  1,Alice,admin")
+        with pytest.raises(ToonDecodeError, match="declares 3 rows, found 2"):
+            decode("users[3,]{id,name}:\n  1,Alice\n  2,Bob")
+
+    def test_tab_delimited_nested_table(self):
+        """Test tab-delimited tables keep leading empty cells and trailing null rows."""
+        data = {"outer": {"rows": [{"a": "", "b": 1}, None, {"a": "x", "b": 2}, None]}}
+
+        result = encode(data, delimiter="\t", tabular_allow_null_rows=True)
+
+        assert decode(result, DecodeOptions(tabular_allow_null_rows=True)) == data
+        with pytest.raises(ToonDecodeError, match="indented with spaces"):
+            decode("rows[1\t]{a\tb}:\n\t1\t2")
+
+    def test_object_of_only_nested_values(self):
+        """Test sibling nested objects stay separate blocks."""
+        data = {"a": {"x": 1}, "b": {"y": 2}, "c": {"z": 3}}
+
+        result = encode(data)
+
+        assert result == "a:\n  x: 1\nb:\n  y: 2\nc:\n  z: 3"
+        assert decode(result) == data
+
+    def test_tabular_under_list_item(self):
+        """Test a tabular array inside an expanded list item round-trips."""
+        data = [{"rows": [{"id": 1}, {"id": 2}]}]
+
+        assert decode(encode(data)) == data
+
+    def test_decode_allow_blank_rows(self):
+        """Test allow_blank_rows skips blank lines between tabular rows."""
+        toon_str = "users[2,]{id,name}:\n  1,Alice\n\n  2,Bob"
+
+        result = decode(toon_str, DecodeOptions(allow_blank_rows=True))
+
+        assert result == {"users": [{"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}]}
+
     def test_decode_tabular_array(self):
         """Test decoding tabular array."""
         toon_str = """users:
@@ -280,6 +437,125 @@ class TestRoundTrip:
         assert decoded["value"] is None
         assert decoded["other"] == original["other"]
 
+    def test_roundtrip_bytes(self):
+        """Test round-trip of bytes through base64 and hex tokens."""
+        original = {"payload": b"\x00\x01hi\xff"}
+
+        for encoding, token in [
+            (BytesEncoding.Base64, "b64:AAFoaf8="),
+            (BytesEncoding.Hex, "hex:00016869ff"),
+        ]:
+            encoded = encode(original, EncodeOptions(bytes_encoding=encoding))
+            assert token in encoded
+
+            decoded = decode(encoded, DecodeOptions(decode_bytes_prefix=True))
+            assert decoded == original
+
+        # Without the decode flag the token stays a string
+        encoded = encode(original, EncodeOptions(bytes_encoding=BytesEncoding.Hex))
+        assert decode(encoded)["payload"] == "hex:00016869ff"
+
+    def test_roundtrip_flatten_paths(self):
+        """Test flat dotted-key output round-trips through expand_paths."""
+        original = {"db": {"host": "localhost", "port": 5432}, "debug": False}
+
+        encoded = encode(original, flatten_paths=True)
+        assert encoded == "db.host: localhost\ndb.port: 5432\ndebug: false"
+        assert decode(encoded, DecodeOptions(expand_paths=True)) == original
+
+    def test_flatten_paths_literal_dotted_key(self):
+        """Test quoted and tabular dotted keys aren't expanded into objects."""
+        decoded = decode('"a.b": 1\nrows[1,]{x.y}:\n  2', DecodeOptions(expand_paths=True))
+        assert decoded == {"a.b": 1, "rows": [{"x.y": 2}]}
+
+        with pytest.raises(ToonEncodeError, match="path separator"):
+            encode({"db": {"a.b": 1}}, flatten_paths=True)
+        with pytest.raises(ToonEncodeError, match="path separator"):
+            encode_flattened({"a.b": 1})
+
+    def test_roundtrip_detected_indent(self):
+        """Test decode_with_meta reports the source indent so re-encoding matches."""
+        source = "user:\n    id: 1\n    name: Alice"
+
+        data, meta = decode_with_meta(source)
+
+        assert meta["indent"] == 4
+        assert encode(data, indent=meta["indent"]) == source
+
+    def test_roundtrip_flattened(self):
+        """Test nested records become tabular when flattened and decode back."""
+        original = {
+            "users": [
+                {"id": 1, "address": {"city": "Paris", "geo": {"lat": 48.8}}},
+                {"id": 2, "address": {"city": "Rome", "geo": {"lat": 41.9}}},
+            ]
+        }
+
+        encoded = encode_flattened(original)
+        assert "{id,address.city,address.geo.lat}:" in encoded
+
+        assert decode_unflatten(encoded) == original
+
+    def test_roundtrip_file(self):
+        """Test decoding a TOON file written by encode."""
+        original = {"users": [{"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}]}
+
+        with tempfile.TemporaryDirectory() as tmp:
+            path = os.path.join(tmp, "data.toon")
+            with open(path, "w") as f:
+                f.write(encode(original))
+
+            assert decode_file(path) == original
+
+            with pytest.raises(FileNotFoundError):
+                decode_file(os.path.join(tmp, "missing.toon"))
+
+    def test_roundtrip_comments(self):
+        """Test comments survive a decode/encode cycle."""
+        toon_str = "# service name\nname: api\ndb:\n  # primary only\n  host: x"
+
+        data, comments = decode_with_comments(toon_str)
+        assert data == {"name": "api", "db": {"host": "x"}}
+        assert comments == {"name": "service name", "db.host": "primary only"}
+
+        assert encode_with_comments(data, comments) == toon_str
+
+    def test_hash_lines_need_allow_comments(self):
+        """Test '#' lines are only skipped as comments under allow_comments."""
+        toon_str = "# note\n#tag: 1"
+
+        assert decode(toon_str, DecodeOptions(allow_comments=True)) == {}
+        assert decode(toon_str, DecodeOptions(strict=False)) == {"#tag": 1}
+
+    def test_roundtrip_ok(self):
+        """Test the round-trip diagnostic on data that survives and data that doesn't."""
+        assert roundtrip_ok({"id": 1, "users": [{"id": 1, "name": "Alice"}]})
+        assert roundtrip_ok({"a": 1}, with_path=True) == (True, None)
+
+        # Whole floats come back as ints
+        assert roundtrip_ok({"a": {"ratio": 1.0}}, with_path=True) == (False, "a.ratio")
+
+        # A bare top-level scalar can't be decoded without the sentinel
+        assert roundtrip_ok("hello", with_path=True) == (False, "")
+        assert roundtrip_ok("hello", EncodeOptions(scalar_sentinel=True))
+
+    def test_roundtrip_matrix(self):
+        """Test numeric matrices round-trip through the compact matrix form."""
+        data = {"grid": [[1, 2, 3], [4, 5, 6]]}
+        toon = encode(data, matrix_mode=True)
+
+        assert toon == "grid:\n  [2,][3,]:\n    1,2,3\n    4,5,6"
+        assert decode(toon) == data
+
+    def test_roundtrip_compact_numbers(self):
+        """Test compact_numbers writes the shortest exact float text."""
+        data = {"small": 1e-10, "big": 1.5e300, "tenth": 0.1}
+
+        toon = encode(data, compact_numbers=True)
+
+        assert toon == "small: 1e-10\nbig: 1.5e300\ntenth: 0.1"
+        assert decode(toon) == data
+
     def test_roundtrip_numbers(self):
         """Test round-trip for various number types."""
         original = {"int": 42, "float": 3.14, "zero": 0}
@@ -303,6 +579,83 @@ class TestOptions:
         assert options.indent == 4
         assert options.use_length_markers is False
 
+    def test_sort_keys_and_key_order(self):
+        """Test key ordering applies to objects, expanded items and tabular columns."""
+        data = {"b": 1, "a": [{"name": "x", "id": 1, "tags": ["t"]}]}
+
+        assert encode(data, sort_keys=True) == "a:\n  [1,]:\n    - id: 1\n      name: x\n      tags:\n        [1,]: t\nb: 1"
+        assert encode([{"name": "x", "id": 1}], key_order=["id"]) == "[1,]{id,name}:\n  1,x"
+
+    def test_encode_preview(self):
+        """Test previews truncate long arrays and strings with a visible marker."""
+        data = {"ids": list(range(50)), "bio": "x" * 100}
+
+        result = encode_preview(data, max_items=3, max_chars=10)
+
+        assert result == 'ids:\n  [4,]: 0,1,2,"…(+47 more)"\nbio: "xxxxxxxxxx…(+90 more)"'
+        assert decode(result) == {"ids": [0, 1, 2, "…(+47 more)"], "bio": "xxxxxxxxxx…(+90 more)"}
+
+    def test_escape_delimiter_inline(self):
+        """Test values containing the delimiter are escaped instead of quoted and decode back."""
+        data = {"tags": ["x,y", "z"], "note": "a,b"}
+
+        toon = encode(data, escape_delimiter_inline=True)
+
+        assert toon == "tags:\n  [2,]: x\\,y,z\nnote: a\\,b"
+        assert decode(toon) == data
+
+    def test_length_marker_trailing_comment(self):
+        """Test lengths written as trailing comments decode with allow_comments."""
+        data = {"tags": ["a", "b"], "users": [{"id": 1}, {"id": 2}]}
+
+        toon = encode(data, length_marker_style=LengthMarkerStyle.TrailingComment)
+
+        assert toon == "tags:\n  []: a,b  # 2 items\nusers:\n  []{id}:  # 2 items\n    1\n    2"
+        assert decode(toon, DecodeOptions(allow_comments=True)) == data
+
+    def test_length_marker_omitted(self):
+        """Test the Omitted style writes bare brackets."""
+        toon = encode({"tags": ["a", "b"]}, length_marker_style=LengthMarkerStyle.Omitted)
+
+        assert toon == "tags:\n  []: a,b"
+        assert decode(toon) == {"tags": ["a", "b"]}
+
+    def test_max_tabular_columns(self):
+        """Test arrays wider than max_tabular_columns fall back to expanded form."""
+        data = {"rows": [{"a": 1, "b": 2, "c": 3}]}
+
+        assert encode(data, max_tabular_columns=3) == "rows:\n  [1,]{a,b,c}:\n    1,2,3"
+        assert encode(data, max_tabular_columns=2) == "rows:\n  [1,]:\n    - a: 1\n      b: 2\n      c: 3"
+
+    def test_encode_default_callable(self):
+        """Test default converts unsupported objects, including nested ones."""
+
+        class Money:
+            def __init__(self, cents):
+                self.cents = cents
+
+        def default(obj):
+            if isinstance(obj, Money):
+                return f"${obj.cents / 100:.2f}"
+            raise TypeError(f"unsupported: {obj!r}")
+
+        data = {"total": Money(123), "items": [{"price": Money(50)}]}
+
+        assert encode(data, default=default) == 'total: $1.23\nitems:\n  [1,]{price}:\n    $0.50'
+
+        with pytest.raises(ToonError):
+            encode({"total": Money(1)})
+        with pytest.raises(ToonError, match="unchanged"):
+            encode({"total": Money(1)}, default=lambda obj: obj)
+
+    def test_custom_null_literal(self):
+        """Test a custom null literal round-trips and quotes matching strings."""
+        data = {"missing": None, "tilde": "~"}
+        toon = encode(data, null_repr="~")
+
+        assert toon == 'missing: ~\ntilde: "~"'
+        assert decode(toon, DecodeOptions(null_repr="~")) == data
+
     def test_decode_options_creation(self):
         """Test creating DecodeOptions."""
         options = DecodeOptions(strict=False)
@@ -318,6 +671,703 @@ class TestOptions:
 
         assert "[]: 1,2,3" in result
 
+    def test_encode_rows_generator(self):
+        """Test encoding rows pulled lazily from a generator."""
+        pulled = []
+
+        def rows():
+            for i in range(3):
+                pulled.append(i)
+                yield {"id": i, "name": f"user{i}", "extra": "ignored"}
+
+        result = encode_rows(rows(), ["id", "name"])
+
+        assert pulled == [0, 1, 2]
+        assert result == "[3,]{id,name}:\n  0,user0\n  1,user1\n  2,user2"
+        assert decode(result) == [{"id": i, "name": f"user{i}"} for i in range(3)]
+
+    def test_encode_rows_missing_field(self):
+        """Test missing fields fail in strict mode and become null otherwise."""
+        rows = [{"id": 1}]
+
+        with pytest.raises(ValueError):
+            encode_rows(iter(rows), ["id", "name"])
+
+        result = encode_rows(iter(rows), ["id", "name"], EncodeOptions(strict=False))
+        assert result == "[1,]{id,name}:\n  1,null"
+
+    def test_keyword_overrides(self):
+        """Test keyword arguments override fields of the options object."""
+        data = {"user": {"tags": ["a", "b"]}}
+        options = EncodeOptions(delimiter="|", use_length_markers=False)
+
+        result = encode(data, options, indent=4)
+
+        assert result == "user:\n    tags:\n        []: a|b"
+        # The passed options object is left untouched
+        assert options.indent == 2
+
+        assert encode(data, delimiter="\t") == "user:\n  tags:\n    [2\t]: a\tb"
+
+    def test_decode_options_from_dict(self):
+        """Test DecodeOptions built from partial dicts keep other defaults."""
+        options = DecodeOptions.from_dict({"strict": False})
+        assert options.strict is False
+        assert options.empty_cell_as_null is False
+        assert options.delimiter is None
+
+        options = DecodeOptions.from_dict({"delimiter": "|", "empty_cell_as_null": True})
+        assert options.strict is True
+        assert decode("rows[2]{a|b}:\n  1|\n  2|x", options) == {
+            "rows": [{"a": 1, "b": None}, {"a": 2, "b": "x"}]
+        }
+
+        with pytest.raises(TypeError):
+            DecodeOptions.from_dict({"stirct": False})
+
+    def test_unknown_keyword_override(self):
+        """Test unknown keyword overrides are rejected."""
+        with pytest.raises(TypeError):
+            encode({"a": 1}, indnet=4)
+
+
+class Color(enum.Enum):
+    RED = "red"
+    GREEN = "green"
+
+
+class Priority(enum.Enum):
+    LOW = 1
+    HIGH = 2
+
+
+class TestEnumEncoding:
+    """Test encoding enum members by value."""
+
+    def test_enums_by_value(self):
+        """Test string- and int-valued enums encode as their values."""
+        data = {"color": Color.RED, "priority": Priority.HIGH, "tags": [Color.GREEN, Priority.LOW]}
+
+        result = encode(data, EncodeOptions(enums_by_value=True))
+
+        assert result == "color: red\npriority: 2\ntags:\n  [2,]: green,1"
+
+    def test_enums_rejected_by_default(self):
+        """Test enum members still fail without enums_by_value."""
+        with pytest.raises(ValueError):
+            encode({"color": Color.RED})
+
+
+class TestPairsAsObject:
+    """Test lists of (key, value) pairs encoding as ordered objects."""
+
+    def test_pairs_keep_order(self):
+        """Pairs encode as an object in list order."""
+        result = encode([("b", 1), ("a", 2)], pairs_as_object=True)
+
+        assert result == "b: 1\na: 2"
+        assert decode(result) == {"b": 1, "a": 2}
+        assert list(decode(result)) == ["b", "a"]
+
+    def test_nested_pairs(self):
+        """Pair lists nested under keys and other lists are converted too."""
+        data = {"row": [("z", [("y", True)]), ("x", None)]}
+
+        assert encode(data, pairs_as_object=True) == "row:\n  z:\n    y: true\n  x: null"
+
+    def test_pairs_left_alone_by_default(self):
+        """Without the option pairs stay a list of arrays; mixed lists are never objects."""
+        assert decode(encode([("b", 1), ("a", 2)])) == [["b", 1], ["a", 2]]
+        mixed = [("b", 1), 3]
+        assert decode(encode(mixed, pairs_as_object=True)) == [["b", 1], 3]
+        assert encode([], pairs_as_object=True) == encode([])
+
+    def test_duplicate_pair_keys(self):
+        """Repeated keys fail in strict mode; otherwise the last pair wins."""
+        with pytest.raises(ToonEncodeError, match="Duplicate key 'a'"):
+            encode([("a", 1), ("a", 2)], pairs_as_object=True)
+        assert encode([("a", 1), ("a", 2)], pairs_as_object=True, strict=False) == "a: 2"
+
+
+class TestRenameKeys:
+    """Test renaming keys while encoding."""
+
+    def test_rename_tabular_header(self):
+        """Renamed keys show up in tabular headers; the source is untouched."""
+        data = {"users": [{"user_id": 1, "name": "Alice"}, {"user_id": 2, "name": "Bob"}]}
+
+        result = encode(data, rename_keys={"user_id": "id"})
+
+        assert result == "users:\n  [2,]{id,name}:\n    1,Alice\n    2,Bob"
+        assert "user_id" in data["users"][0]
+
+    def test_rename_collision(self):
+        """A rename onto an existing key fails in strict mode."""
+        with pytest.raises(ToonEncodeError, match="Key collision"):
+            encode({"user_id": 1, "id": 2}, rename_keys={"user_id": "id"})
+
+
+class TestListMarker:
+    """Test a custom marker for expanded array items."""
+
+    def test_star_marker_roundtrip(self):
+        """Items written with "* " decode back with the same marker."""
+        data = {"items": [{"id": 1}, "x", [1, 2]]}
+
+        result = encode(data, list_marker="* ")
+
+        assert result == "items:\n  [3,]:\n    * id: 1\n    * x\n    * [2,]: 1,2"
+        assert decode(result, DecodeOptions(list_marker="* ")) == data
+
+    def test_ambiguous_marker(self):
+        """Markers that could be read as a value are rejected."""
+        with pytest.raises(ToonEncodeError, match="List marker"):
+            encode({"items": [{"id": 1}, "x"]}, list_marker="x ")
+
+
+class TestIndexLargeArrays:
+    """Test long primitive arrays written as index,value tables."""
+
+    def test_roundtrip(self):
+        """The index column is written on encode and dropped on decode."""
+        data = {"ids": list(range(10, 15))}
+
+        result = encode(data, index_large_arrays=3)
+
+        assert result.startswith("ids:\n  [5,]{i,value}:\n    0,10\n    1,11")
+        assert decode(result, DecodeOptions(unindex_arrays=True)) == data
+        assert encode({"ids": [1, 2]}, index_large_arrays=3) == "ids:\n  [2,]: 1,2"
+
+
+class TestSortRowsBy:
+    """Test sorting tabular rows for diff-stable output."""
+
+    def test_rows_sorted_by_id(self):
+        """Rows come out ordered by id whatever the input order."""
+        data = {"users": [{"id": 3, "name": "Cara"}, {"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}]}
+
+        result = encode(data, sort_rows_by="id")
+
+        assert result == "users:\n  [3,]{id,name}:\n    1,Alice\n    2,Bob\n    3,Cara"
+        assert [u["id"] for u in data["users"]] == [3, 1, 2]
+
+    def test_missing_column(self):
+        """Sorting by a column the rows lack is an error."""
+        with pytest.raises(ToonEncodeError, match="Cannot sort rows by 'id'"):
+            encode({"users": [{"name": "Alice"}]}, sort_rows_by="id")
+
+
+class TestRedactKeys:
+    """Test hiding secret values in log output."""
+
+    def test_password_redacted(self):
+        """A password field is written as *** and other fields are intact."""
+        data = {"user": "alice", "password": "hunter2", "sessions": [{"id": 1, "token": "x"}]}
+
+        result = encode(data, redact_keys=["password", "token"])
+
+        assert result == "user: alice\npassword: ***\nsessions:\n  [1,]{id,token}:\n    1,***"
+        assert data["password"] == "hunter2"
+
+
+class TestTupleMarker:
+    """Test tuples surviving a round trip under tuple_marker."""
+
+    def test_tuple_roundtrip(self):
+        """Flat tuples encode as (N,): lines and decode back to tuples."""
+        data = {"point": (1, 2.5, "x"), "points": [(0, 0), (1, 1)], "nested": ((1, 2),)}
+
+        result = encode(data, tuple_marker=True)
+
+        assert result.startswith("point:\n  (3,): 1,2.5,x\n")
+        decoded = decode(result, DecodeOptions(tuple_marker=True))
+        assert decoded == {"point": (1, 2.5, "x"), "points": [(0, 0), (1, 1)], "nested": [(1, 2)]}
+        assert isinstance(decoded["point"], tuple)
+
+    def test_tuples_are_lists_by_default(self):
+        """Without tuple_marker tuples encode as arrays and decode as lists."""
+        assert decode(encode({"point": (1, 2)})) == {"point": [1, 2]}
+
+
+class TestIndexExpandedItems:
+    """Test index markers on expanded array items."""
+
+    def test_indices_roundtrip(self):
+        """Indices are written after each list marker and skipped on decode."""
+        data = {"items": [{"id": 1}, "x", [1, 2]]}
+
+        result = encode(data, index_expanded_items=True)
+
+        assert result == "items:\n  [3,]:\n    - [0] id: 1\n    - [1] x\n    - [2] [2,]: 1,2"
+        assert decode(result) == data
+
+
+class TestStrictEncoding:
+    """Test strict-mode encoding checks."""
+
+    def test_strict_rejects_infinity(self):
+        """Strict encoding rejects non-finite floats."""
+        with pytest.raises(ValueError):
+            encode({"value": float("inf")})
+
+    def test_strict_rejects_nan_in_list(self):
+        """Strict encoding finds non-finite floats inside nested lists."""
+        with pytest.raises(ValueError):
+            encode({"values": [1.0, float("nan")]})
+
+    def test_validate_finite(self):
+        """validate_finite reports the path of a NaN buried in nested arrays."""
+        data = {"features": [[0.5, 1.0], [2.0, float("nan")]], "label": 1}
+
+        assert validate_finite(data) == "features[1][1]"
+        assert validate_finite({"row": (1.0, float("-inf"))}) == "row[1]"
+        assert validate_finite(float("inf")) == ""
+        assert validate_finite({"features": [[0.5, 1.0]], "name": "ok"}) is None
+
+    def test_non_strict_emits_null(self):
+        """Non-strict encoding coerces non-finite floats to null."""
+        result = encode({"value": float("inf")}, EncodeOptions(strict=False))
+
+        assert "value: null" in result
+
+    def test_strict_rejects_u64_overflow(self):
+        """Strict encoding rejects integers wider than 64 bits."""
+        with pytest.raises(ValueError, match="does not fit in 64 bits"):
+            encode({"big": 2**64})
+        with pytest.raises(ValueError):
+            encode({"small": -(2**63) - 1})
+
+        assert encode({"big": 2**64}, EncodeOptions(strict=False)) == "big: 18446744073709552000"
+
+    def test_u64_encodes_exactly(self):
+        """Integers up to 2**64 - 1 fit and round-trip exactly."""
+        result = encode({"big": 2**64 - 1, "mid": 2**63})
+
+        assert result == "big: 18446744073709551615\nmid: 9223372036854775808"
+        assert decode(result) == {"big": 2**64 - 1, "mid": 2**63}
+
+
+class TestStreamingDecoder:
+    """Test decoding a document fed in chunks."""
+
+    def test_chunks_split_mid_line(self):
+        """Test a line split across chunks is held until it is complete."""
+        decoder = StreamingDecoder()
+        decoder.feed("id: 1\nname: Al")
+
+        assert decoder.needs_more
+        assert decoder.snapshot() == {"id": 1}
+
+        decoder.feed("ice\n")
+
+        assert not decoder.needs_more
+        assert decoder.finish() == {"id": 1, "name": "Alice"}
+
+    def test_finish_with_options(self):
+        """Test options apply and a final line without a newline is decoded."""
+        decoder = StreamingDecoder(DecodeOptions(strict=False))
+        decoder.feed("nums[3,]: 1,2,")
+        decoder.feed("3,")
+
+        assert decoder.finish() == {"nums": [1, 2, 3]}
+
+    def test_snapshot_mid_table(self):
+        """Test a snapshot reads a table still short of its declared rows."""
+        decoder = StreamingDecoder()
+        decoder.feed("users[2,]{id,name}:\n  1,Alice\n")
+
+        assert decoder.needs_more
+        assert decoder.snapshot() == {"users": [{"id": 1, "name": "Alice"}]}
+
+        decoder.feed("  2,Bob\n")
+
+        assert not decoder.needs_more
+        assert decoder.finish() == {"users": [{"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}]}
+
+
+class TestSavings:
+    """Test the JSON vs TOON byte comparison."""
+
+    def test_savings(self):
+        """Test sizes and ratio match the JSON and TOON encodings."""
+        data = {"users": [{"id": i, "name": f"user{i}"} for i in range(10)]}
+
+        result = savings(data)
+
+        assert result["json_bytes"] == len(json.dumps(data, separators=(",", ":")))
+        assert result["toon_bytes"] == len(encode(data))
+        assert result["ratio"] == result["toon_bytes"] / result["json_bytes"]
+        assert result["ratio"] < 1
+
+
+class TestExceptions:
+    """Test errors surface as specific exception classes."""
+
+    def test_decode_error(self):
+        """Invalid TOON raises ToonDecodeError."""
+        with pytest.raises(ToonDecodeError, match="Duplicate key 'a'"):
+            decode("a:\n  x: 1\na: 2")
+
+    def test_encode_error(self):
+        """Unencodable data raises ToonEncodeError."""
+        with pytest.raises(ToonEncodeError):
+            encode({"big": 2**64})
+
+    def test_delimiter_error(self):
+        """An unsupported delimiter raises ToonDelimiterError."""
+        with pytest.raises(ToonDelimiterError):
+            encode({"tags": ["a", "b"]}, delimiter=";")
+
+    def test_hierarchy(self):
+        """All errors share the ToonError base, which is a ValueError."""
+        for cls in (ToonEncodeError, ToonDecodeError, ToonDelimiterError):
+            assert issubclass(cls, ToonError)
+        assert issubclass(ToonError, ValueError)
+
+
+class TestNormalize:
+    """Test re-emitting TOON in canonical form."""
+
+    def test_equivalent_documents_match(self):
+        """Differently formatted documents with equal data normalize identically."""
+        a = "name: Alice\ntags[2,]: x,y"
+        b = 'tags:\n    [2|]: "x"|y\nname: "Alice"'
+
+        assert normalize(a) == normalize(b) == "name: Alice\ntags:\n  [2,]: x,y"
+
+    def test_normalize_invalid(self):
+        """Input that fails to decode raises ToonDecodeError."""
+        with pytest.raises(ToonDecodeError):
+            normalize("a: 1\na: 2")
+
+
+class TestDecodeSchema:
+    """Test schema discovery without decoding values."""
+
+    def test_two_tabular_arrays(self):
+        """Each tabular array is listed with its columns."""
+        toon_str = (
+            "users[2,]{id,name}:\n  1,Alice\n  2,Bob\n"
+            "report:\n  orders:\n    [1,]{order_id,total}:\n      7,9.5\n  title: Q1"
+        )
+
+        assert decode_schema(toon_str) == {
+            "users": ["id", "name"],
+            "report": {"orders": ["order_id", "total"], "title": "scalar"},
+        }
+
+
+class TestDecodeDepth:
+    """Test deeply nested input fails cleanly instead of crashing."""
+
+    def test_deep_nesting(self):
+        """Nesting past max_depth raises ToonDecodeError."""
+        toon = "\n".join(" " * i + "k:" for i in range(5000))
+        with pytest.raises(ToonDecodeError, match="max_depth 256"):
+            decode(toon)
+
+    def test_custom_max_depth(self):
+        """max_depth can be lowered or raised."""
+        toon = "a:\n  b:\n    c: 1"
+        assert decode(toon, DecodeOptions(max_depth=2)) == {"a": {"b": {"c": 1}}}
+        with pytest.raises(ToonDecodeError):
+            decode(toon, DecodeOptions(max_depth=1))
+
+
+class TestDuckTypedMappings:
+    """Test encoding mapping-like objects that aren't dicts."""
+
+    class Row:
+        """ORM-style row exposing keys() and __getitem__ only."""
+
+        def __init__(self, **fields):
+            self._fields = fields
+
+        def keys(self):
+            return iter(self._fields)
+
+        def __getitem__(self, key):
+            return self._fields[key]
+
+    def test_row_encoded_as_object(self):
+        """Rows are encoded like the dicts they stand for."""
+        data = {"user": self.Row(id=1, name="Alice"), "rows": [self.Row(id=2, name="Bob")]}
+
+        result = encode(data, duck_typed_mappings=True)
+
+        assert result == encode({"user": {"id": 1, "name": "Alice"}, "rows": [{"id": 2, "name": "Bob"}]})
+
+    def test_disabled_by_default(self):
+        """Without the flag the row is unsupported."""
+        with pytest.raises(Exception):
+            encode({"user": self.Row(id=1)})
+
+
+class TestDecodeWithSpans:
+    """Test mapping decoded values back to source lines."""
+
+    def test_nested_key_span(self):
+        """A nested key reports the lines of its block."""
+        toon = "name: demo\nconfig:\n  db:\n    host: localhost\n    port: 5432\n  debug: true"
+
+        data, spans = decode_with_spans(toon)
+
+        assert data == decode(toon)
+        assert spans["/config/db"] == (3, 5)
+        assert spans["/config/db/port"] == (5, 5)
+        assert spans[""] == (1, 6)
+
+
+class TestNumberOverflow:
+    """Test integers too large for 64 bits."""
+
+    TOON = "big: 99999999999999999999"
+
+    def test_as_float(self):
+        """The default parses a float, as before."""
+        assert decode(self.TOON) == {"big": 1e20}
+
+    def test_as_string(self):
+        """AsString keeps every digit."""
+        result = decode(self.TOON, DecodeOptions(on_number_overflow=NumberOverflow.AsString))
+        assert result == {"big": "99999999999999999999"}
+
+    def test_error(self):
+        """Error raises instead of losing precision."""
+        with pytest.raises(ToonDecodeError, match="does not fit in 64 bits"):
+            decode(self.TOON, DecodeOptions(on_number_overflow=NumberOverflow.Error))
+
+
+class TestTabularFooter:
+    """Test the count/sum footer written under tables for review."""
+
+    def test_footer_written_and_ignored(self):
+        """The footer sums numeric columns and decodes away with comments on."""
+        data = {"orders": [{"id": 1, "sku": "A", "qty": 2}, {"id": 2, "sku": "B", "qty": 5}]}
+
+        result = encode(data, tabular_footer=True)
+
+        assert result.endswith("\n    # count=2 sum(id)=3 sum(qty)=7")
+        assert decode(result, DecodeOptions(allow_comments=True)) == data
+
+
+class TestLossless:
+    """Test the exact round-trip mode."""
+
+    def test_round_trip_despite_lossy_options(self):
+        """lossless overrides options that would drop or rewrite data."""
+        data = {"password": "hunter2", "note": None, "tags": [], "ok": True, "ratio": 0.1, "rows": [{}]}
+
+        result = encode(data, lossless=True, skip_nulls=True, skip_empty=True, redact_keys=["password"], true_repr="yes")
+
+        assert decode(result) == data
+
+
+class TestEncodeToFile:
+    """Test streaming rows straight to a file."""
+
+    def test_thousand_rows(self):
+        """A list of 1000 rows is written with its count and decodes back."""
+        rows = [{"id": i, "name": f"user{i}"} for i in range(1000)]
+
+        with tempfile.TemporaryDirectory() as tmp:
+            path = os.path.join(tmp, "users.toon")
+            encode_to_file(rows, path, ["id", "name"])
+
+            with open(path) as f:
+                assert f.read().startswith("[1000,]{id,name}:\n  0,user0")
+            assert decode_file(path) == rows
+
+    def test_generator(self):
+        """A generator has no length, so the header omits it."""
+        with tempfile.TemporaryDirectory() as tmp:
+            path = os.path.join(tmp, "ids.toon")
+            encode_to_file(({"id": i} for i in range(3)), path, ["id"])
+
+            with open(path) as f:
+                assert f.read() == "[]{id}:\n  0\n  1\n  2"
+
+
+class TestTabWidth:
+    """Test decoding tab-indented documents."""
+
+    def test_tab_indented_nested_object(self):
+        """Each tab is one level, or tab_width columns next to spaces."""
+        toon = "user:\n\tname: Alice\n\taddress:\n\t\tcity: Paris"
+        expected = {"user": {"name": "Alice", "address": {"city": "Paris"}}}
+
+        assert decode(toon) == expected
+        assert decode(toon, DecodeOptions(tab_width=4)) == expected
+        assert decode("user:\n    name: Alice\n\taddress:\n\t    city: Paris", DecodeOptions(tab_width=4)) == expected
+
+
+class TestRetargetDelimiter:
+    """Test switching delimiters without a full decode."""
+
+    def test_comma_table_to_pipe(self):
+        """Header, fields and rows switch to pipes; cells with pipes get quoted."""
+        toon = 'users[3,]{id,name,note}:\n  1,Alice,ok\n  2,"Smith, Bob",a|b\n  3,Cara,""'
+
+        result = retarget_delimiter(toon, ",", "|")
+
+        assert result == 'users[3|]{id|name|note}:\n  1|Alice|ok\n  2|"Smith, Bob"|"a|b"\n  3|Cara|""'
+        assert decode(result) == decode(toon)
+
+    def test_invalid_delimiter(self):
+        """Only comma, pipe and tab are delimiters."""
+        with pytest.raises(ToonDelimiterError):
+            retarget_delimiter("a: 1", ",", ";")
+
+
+class TestJsonPassthroughKeys:
+    """Test fields carried as JSON text."""
+
+    def test_nested_object_round_trip(self):
+        """A nested object under a passthrough key is one quoted JSON string."""
+        data = {"id": 1, "blob": {"a": [1, 2], "b": {"c": None}}}
+
+        result = encode(data, json_passthrough_keys=["blob"])
+
+        assert result == 'id: 1\nblob: "{\\"a\\":[1,2],\\"b\\":{\\"c\\":null}}"'
+        assert json.loads(decode(result)["blob"]) == data["blob"]
+        assert decode(result, DecodeOptions(json_passthrough_keys=["blob"])) == data
+
+
+class TestExpandedScalarItems:
+    """Test scalar list items keep their types."""
+
+    def test_null_number_string(self):
+        """`- null`, `- 1` and `- "x"` decode to None, 1 and "x"."""
+        assert decode('[3,]:\n  - null\n  - 1\n  - "x"') == [None, 1, "x"]
+
+    def test_round_trip_beside_object(self):
+        """Quoted look-alikes stay strings in the expanded form."""
+        data = [None, 1, "x", True, "null", "1", {"a": 1}]
+
+        assert decode(encode(data)) == data
+
+
+class TestMaxStringLen:
+    """Test rejecting oversized strings in strict mode."""
+
+    def test_just_over_limit(self):
+        """A string one character past the limit raises; one at it is written."""
+        assert encode({"s": "x" * 10}, max_string_len=10) == "s: " + "x" * 10
+        with pytest.raises(ToonEncodeError, match="exceeds max_string_len 10"):
+            encode({"s": "x" * 11}, max_string_len=10)
+
+
+class TestNumericKeysAsArray:
+    """Test objects keyed by indices decoding as arrays."""
+
+    def test_contiguous_keys(self):
+        """Keys 0..N-1 become a list in index order."""
+        toon = encode({"items": {"0": "a", "1": "b", "2": "c"}})
+
+        assert decode(toon, DecodeOptions(numeric_keys_as_array=True)) == {"items": ["a", "b", "c"]}
+
+    def test_gap_keeps_object(self):
+        """A missing index leaves the object alone."""
+        data = {"items": {"0": "a", "2": "c"}}
+
+        assert decode(encode(data), DecodeOptions(numeric_keys_as_array=True)) == data
+
+
+class TestDefensiveKeys:
+    """Test defensive quoting of keys."""
+
+    def test_bracket_and_brace_keys_round_trip(self):
+        data = {"a[b]": [1, 2], "a{b}": {"c": 1}, "a:b": "v", "rows": [{"a[b]": 1}, {"a[b]": 2}]}
+        for defensive in (False, True):
+            assert decode(encode(data, defensive_keys=defensive)) == data
+
+    def test_quotes_non_identifier_keys(self):
+        result = encode({"x=y": 1, "plain": 2}, defensive_keys=True)
+        assert '"x=y": 1' in result
+        assert "plain: 2" in result
+        assert "x=y: 1" in encode({"x=y": 1})
+
+
+class TestContentHash:
+    """Test hashing decoded values."""
+
+    def test_equivalent_documents_hash_equally(self):
+        data = {"name": "Demo", "users": [{"id": 1, "name": "Alice"}, {"id": 2, "name": "Bob"}]}
+        tabular = encode(data)
+        piped = encode(data, delimiter="|", indent=4)
+        reordered = encode({"users": data["users"], "name": "Demo"})
+        assert content_hash(tabular) == content_hash(piped) == content_hash(reordered)
+
+    def test_changed_value_hashes_differently(self):
+        assert content_hash("a: 1\nb: 2") != content_hash("a: 1\nb: 3")
+        assert content_hash("tags[2,]: x,y") != content_hash("tags[2,]: y,x")
+
+    def test_invalid_document_raises(self):
+        with pytest.raises(ToonDecodeError):
+            content_hash("a[2,]{x}:\n  1,2\n  3")
+
+
+class TestLeadingHash:
+    """Test values starting with '#' aren't read back as comments."""
+
+    def test_hashtag_in_expanded_array(self):
+        data = {"tags": ["#hashtag", {"note": "#x"}]}
+        result = encode(data)
+        assert '- "#hashtag"' in result
+        assert decode(result, DecodeOptions(allow_comments=True)) == data
+
+
+class TestDecodeFlat:
+    """Test decoding to JSON Pointer keys."""
+
+    def test_nested_document(self):
+        data = {"users": [{"id": 1, "name": "Alice"}], "meta": {"a/b": "x", "tags": ["p", "q"], "empty": {}}}
+        assert decode_flat(encode(data)) == {
+            "/users/0/id": 1,
+            "/users/0/name": "Alice",
+            "/meta/a~1b": "x",
+            "/meta/tags/0": "p",
+            "/meta/tags/1": "q",
+            "/meta/empty": {},
+        }
+
+
+class TestVersionTag:
+    """Test the optional #toon version line."""
+
+    def test_round_trip(self):
+        """emit_version writes the line and decode accepts it."""
+        result = encode({"a": 1}, emit_version=True)
+
+        assert result == "#toon v1\na: 1"
+        assert decode(result) == {"a": 1}
+
+    def test_missing_version(self):
+        """Documents without the line still decode."""
+        assert decode("a: 1") == {"a": 1}
+
+    def test_unknown_version(self):
+        """A newer major version is rejected."""
+        with pytest.raises(ToonDecodeError, match="Unsupported TOON version 'v2'"):
+            decode("#toon v2\na: 1")
+
+
+class TestMerge:
+    """Test deep-merging TOON documents."""
+
+    def test_merge(self):
+        assert merge("id: 1\nname: Alice", "name: Bob") == "id: 1\nname: Bob"
+        assert merge("tags[1,]: a", "tags[1,]: b", concat_arrays=False) == "tags:\n  [1,]: b"
+
+    def test_decode_options(self):
+        """Both documents are read with decode_options."""
+        base = "# defaults\nretries: 1"
+        with pytest.raises(ToonDecodeError):
+            merge(base, "timeout: 5")
+
+        merged = merge(base, "timeout: 5", decode_options=DecodeOptions(allow_comments=True))
+        assert merged == "retries: 1\ntimeout: 5"
+
 
 class TestEdgeCases:
     """Test edge cases and error handling."""